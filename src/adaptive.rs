@@ -0,0 +1,135 @@
+use std::io::{self, Read, Write};
+
+use crate::checkpoint::{read_f64, read_u32, write_f64, write_u32};
+use crate::vector3::Color;
+
+// running statistics of the luminance of the samples taken in one pixel
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct PixelStats {
+  pub samples: u32,
+  sum: f64,
+  sum_sq: f64
+}
+
+
+impl PixelStats {
+  pub fn add(&mut self, color: Color) {
+    let l = color.luminance();
+    self.samples += 1;
+    self.sum += l;
+    self.sum_sq += l * l;
+  }
+
+  pub fn merge(&mut self, other: &PixelStats) {
+    self.samples += other.samples;
+    self.sum += other.sum;
+    self.sum_sq += other.sum_sq;
+  }
+
+  pub fn mean(&self) -> f64 {
+    if self.samples == 0 {0.0} else {self.sum / self.samples as f64}
+  }
+
+  pub fn variance(&self) -> f64 {
+    if self.samples < 2 {
+      return f64::INFINITY;
+    }
+    let n = self.samples as f64;
+    ((self.sum_sq - self.sum * self.sum / n) / (n - 1.0)).max(0.0)
+  }
+
+  // standard error of the pixel mean after the gamma 2 the image is written with,
+  // so the threshold means the same in dark and bright parts of the image
+  pub fn error(&self) -> f64 {
+    let standard_error = (self.variance() / self.samples as f64).sqrt();
+    standard_error / (2.0 * self.mean().max(1e-4).sqrt())
+  }
+
+  pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    write_u32(writer, self.samples)?;
+    write_f64(writer, self.sum)?;
+    write_f64(writer, self.sum_sq)
+  }
+
+  pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    Ok(Self {
+      samples: read_u32(reader)?,
+      sum: read_f64(reader)?,
+      sum_sq: read_f64(reader)?
+    })
+  }
+}
+
+
+
+
+// stop sampling a pixel once its error is below the threshold, but take at least
+// min_samples to have a usable variance estimate and at most max_samples
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveSampling {
+  pub min_samples: u32,
+  pub max_samples: u32,
+  pub threshold: f64
+}
+
+
+impl AdaptiveSampling {
+  // convergence is only checked every few samples, the variance barely moves in between
+  const CHECK_INTERVAL: u32 = 8;
+
+  pub fn converged(&self, stats: &PixelStats) -> bool {
+    if stats.samples >= self.max_samples {
+      return true;
+    }
+    if stats.samples < self.min_samples || !stats.samples.is_multiple_of(Self::CHECK_INTERVAL) {
+      return false;
+    }
+    stats.error() < self.threshold
+  }
+}
+
+
+// blue for few samples through green and yellow to red for max_samples
+pub fn heatmap_color(samples: u32, max_samples: u32) -> Color {
+  let t = (samples as f64 / max_samples.max(1) as f64).clamp(0.0, 1.0);
+  let ramp = |center: f64| (1.5 - (4.0 * (t - center)).abs()).clamp(0.0, 1.0);
+  Color::new(ramp(0.75), ramp(0.5), ramp(0.25))
+}
+
+
+
+#[cfg(test)]
+mod adaptive_tests {
+  use super::*;
+
+  #[test]
+  fn test_constant_pixel_converges_at_min_samples() {
+    let adaptive = AdaptiveSampling {min_samples: 16, max_samples: 256, threshold: 0.01};
+    let mut stats = PixelStats::default();
+    while !adaptive.converged(&stats) {
+      stats.add(Color::new(0.5, 0.5, 0.5));
+    }
+    assert_eq!(stats.samples, 16);
+  }
+
+  #[test]
+  fn test_noisy_pixel_runs_to_max_samples() {
+    let adaptive = AdaptiveSampling {min_samples: 16, max_samples: 64, threshold: 0.001};
+    let mut stats = PixelStats::default();
+    while !adaptive.converged(&stats) {
+      let l = if stats.samples % 2 == 0 {0.0} else {4.0};
+      stats.add(Color::new(l, l, l));
+    }
+    assert_eq!(stats.samples, 64);
+  }
+
+  #[test]
+  fn test_variance() {
+    let mut stats = PixelStats::default();
+    for l in [1.0, 2.0, 3.0, 4.0] {
+      stats.add(Color::new(l, l, l));
+    }
+    assert!((stats.mean() - 2.5).abs() < 0.000001);
+    assert!((stats.variance() - 5.0 / 3.0).abs() < 0.000001);
+  }
+}
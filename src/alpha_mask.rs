@@ -0,0 +1,96 @@
+use crate::hittable::{Hittable, HitRecord, HittableObject};
+use crate::ray::Ray;
+use crate::interval::Interval;
+use crate::rng::Rng;
+use crate::texture::ScalarTexture;
+
+pub enum AlphaMode {
+  // hard cutout, surfaces with alpha below the threshold are not there
+  Cutout(f64),
+  // the surface is hit with probability alpha, which averages to partial coverage
+  Stochastic
+}
+
+
+// opacity texture on top of any object
+pub struct AlphaMasked {
+  pub object: Box<HittableObject>,
+  pub alpha: ScalarTexture,
+  pub mode: AlphaMode
+}
+
+
+impl AlphaMasked {
+  pub fn new(object: HittableObject, alpha: ScalarTexture, mode: AlphaMode) -> Self {
+    Self {
+      object: Box::new(object),
+      alpha,
+      mode
+    }
+  }
+
+  pub fn opaque_at(&self, rec: &HitRecord, rng: &mut Rng) -> bool {
+    let alpha = self.alpha.value(rec.u, rec.v, rec.p);
+    match self.mode {
+      AlphaMode::Cutout(threshold) => alpha >= threshold,
+      AlphaMode::Stochastic => alpha >= 1.0 || (alpha > 0.0 && rng.uniform() < alpha)
+    }
+  }
+}
+
+
+impl Hittable for AlphaMasked {
+  // a rejected hit moves the start of the interval past it and asks again, so the
+  // object still reports its closest opaque hit and the closest-hit search in
+  // HittableList keeps working unchanged
+  fn hit(&self, ray: &Ray, ray_t: Interval, rng: &mut Rng) -> Option<HitRecord<'_>> {
+    let mut t_min = ray_t.min;
+    loop {
+      let rec = self.object.hit(ray, Interval::new(t_min, ray_t.max), rng)?;
+      if self.opaque_at(&rec, rng) {
+        return Some(rec);
+      }
+      t_min = rec.t;
+    }
+  }
+}
+
+
+
+#[cfg(test)]
+mod alpha_mask_tests {
+  use super::*;
+  use crate::material::{Lambertian, MaterialType};
+  use crate::sphere::Sphere;
+  use crate::texture::{Checker, TextureType};
+  use crate::vector3::{Color, Point, Vector3};
+
+  fn masked_sphere(alpha: ScalarTexture) -> AlphaMasked {
+    let mat = MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    AlphaMasked::new(
+      HittableObject::Sphere(Sphere::new(Point::new(0.0, 0.0, -2.0), 0.5, mat)),
+      alpha,
+      AlphaMode::Cutout(0.5)
+    )
+  }
+
+  #[test]
+  fn test_transparent_is_missed() {
+    let ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0));
+    assert!(masked_sphere(ScalarTexture::constant(0.0)).hit(&ray, Interval::new(0.001, f64::INFINITY), &mut Rng::new(0)).is_none());
+    let opaque = masked_sphere(ScalarTexture::constant(1.0));
+    let rec = opaque.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut Rng::new(0)).unwrap();
+    assert!((rec.t - 1.5).abs() < 0.000001);
+  }
+
+  #[test]
+  fn test_hole_reveals_back_face() {
+    // the checker is clear at the front of the sphere (z = -1.5) and solid at the back (z = -2.5)
+    let holes = TextureType::Checker(Checker::new(1.0, Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0)));
+    let ray = Ray::new(Point::new(0.1, 0.1, 0.0), Vector3::new(0.0, 0.0, -1.0));
+    let sphere = masked_sphere(ScalarTexture::channel(holes, 0));
+    let rec = sphere.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut Rng::new(0)).unwrap();
+    assert!(rec.t > 2.0);
+    assert!(!rec.front_face);
+  }
+}
@@ -0,0 +1,338 @@
+use std::ops::Add;
+
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::hittable::HitRecord;
+use crate::material::Lobe;
+use crate::ray::Ray;
+use crate::vector3::{Color, Point, Vector3};
+
+// arbitrary output variables, written next to the image as <name>_<aov>.ppm, or .pfm
+// for the data ones
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aov {
+  // distance from the camera to the first hit
+  Depth,
+  // world space shading normal at the first hit
+  Normal,
+  Albedo,
+  MaterialId,
+  ObjectId,
+  // world space position of the first hit
+  Position,
+  // emitters and sky seen straight from the camera
+  Emission,
+  // light that scattered once on its way to the camera
+  Direct,
+  // light that scattered more than once
+  Indirect,
+  // direct and indirect light split by the lobe the path took at its first scattering event
+  Diffuse,
+  Specular,
+  Transmission
+}
+
+
+impl Aov {
+  pub const ALL: [Aov; 12] = [
+    Aov::Depth, Aov::Normal, Aov::Albedo, Aov::MaterialId, Aov::ObjectId, Aov::Position,
+    Aov::Emission, Aov::Direct, Aov::Indirect, Aov::Diffuse, Aov::Specular, Aov::Transmission
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Aov::Depth        => "depth",
+      Aov::Normal       => "normal",
+      Aov::Albedo       => "albedo",
+      Aov::MaterialId   => "material",
+      Aov::ObjectId     => "object",
+      Aov::Position     => "position",
+      Aov::Emission     => "emission",
+      Aov::Direct       => "direct",
+      Aov::Indirect     => "indirect",
+      Aov::Diffuse      => "diffuse",
+      Aov::Specular     => "specular",
+      Aov::Transmission => "transmission"
+    }
+  }
+
+  // measurements rather than colors, written as floats so they keep their values
+  pub fn is_data(&self) -> bool {
+    matches!(self, Aov::Depth | Aov::Normal | Aov::Position)
+  }
+
+  // a comma separated list of names, or all of them
+  pub fn parse_list(list: &str) -> Result<Vec<Aov>, String> {
+    if list == "all" {
+      return Ok(Self::ALL.to_vec());
+    }
+    let mut aovs = Vec::new();
+    for name in list.split(',') {
+      let aov = Self::ALL.iter().find(|aov| aov.name() == name).ok_or_else(|| format!("unknown aov {}", name))?;
+      if !aovs.contains(aov) {
+        aovs.push(*aov);
+      }
+    }
+    Ok(aovs)
+  }
+
+  // the light passes add up to the image, so they are reconstructed with the same filter.
+  // everything else is averaged inside the pixel, and the ids are taken from the first
+  // sample only since a blend of two ids means nothing
+  fn filter(&self, image_filter: &Filter) -> Filter {
+    match self {
+      Aov::Emission | Aov::Direct | Aov::Indirect | Aov::Diffuse | Aov::Specular | Aov::Transmission => *image_filter,
+      _ => Filter::Box(0.5)
+    }
+  }
+
+  fn first_sample_only(&self) -> bool {
+    matches!(self, Aov::MaterialId | Aov::ObjectId)
+  }
+}
+
+
+
+
+// the radiance a camera path carries, split by how it reached the camera.
+// emission + direct + indirect is the image, the lobes split direct + indirect
+#[derive(Clone, Copy)]
+pub struct LightPaths<T> {
+  pub emission: T,
+  pub direct: T,
+  pub indirect: T,
+  pub lobes: [T; 3]
+}
+
+
+impl<T: Copy + Add<Output = T>> LightPaths<T> {
+  pub fn new(zero: T) -> Self {
+    Self {emission: zero, direct: zero, indirect: zero, lobes: [zero; 3]}
+  }
+
+  // bounces is the number of scattering events the light went through, first_lobe
+  // the lobe of the first one
+  pub fn add(&mut self, contribution: T, bounces: u32, first_lobe: Option<Lobe>) {
+    match bounces {
+      0 => self.emission = self.emission + contribution,
+      1 => self.direct = self.direct + contribution,
+      _ => self.indirect = self.indirect + contribution
+    }
+    if let Some(lobe) = first_lobe {
+      let i = match lobe {
+        Lobe::Diffuse      => 0,
+        Lobe::Specular     => 1,
+        Lobe::Transmission => 2
+      };
+      self.lobes[i] = self.lobes[i] + contribution;
+    }
+  }
+
+  pub fn total(&self) -> T {
+    self.emission + self.direct + self.indirect
+  }
+
+  pub fn map<U>(&self, f: impl Fn(T) -> U) -> LightPaths<U> {
+    LightPaths {
+      emission: f(self.emission),
+      direct: f(self.direct),
+      indirect: f(self.indirect),
+      lobes: [f(self.lobes[0]), f(self.lobes[1]), f(self.lobes[2])]
+    }
+  }
+}
+
+
+// what a camera ray hit first
+#[derive(Clone, Copy)]
+pub struct SurfaceInfo {
+  pub distance: f64,
+  pub normal: Vector3,
+  pub position: Point,
+  pub albedo: Color,
+  pub material_id: u32,
+  pub object_id: usize
+}
+
+
+impl SurfaceInfo {
+  pub fn new(ray: &Ray, rec: &HitRecord) -> Self {
+    Self {
+      distance: rec.t * ray.direction.length(),
+      normal: if rec.front_face {rec.shading_normal} else {-rec.shading_normal},
+      position: rec.p,
+      albedo: rec.material.albedo(rec),
+      material_id: rec.material.id(),
+      object_id: rec.object_id
+    }
+  }
+}
+
+
+// everything one camera sample contributes to the image and the aovs
+pub struct AovSample {
+  pub light: LightPaths<Color>,
+  pub surface: Option<SurfaceInfo>
+}
+
+
+impl AovSample {
+  pub fn value(&self, aov: Aov) -> Color {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let data = |f: fn(&SurfaceInfo) -> Color| self.surface.as_ref().map_or(black, f);
+    match aov {
+      Aov::Depth        => data(|s| Color::new(s.distance, s.distance, s.distance)),
+      Aov::Normal       => data(|s| s.normal),
+      Aov::Albedo       => data(|s| s.albedo),
+      Aov::MaterialId   => data(|s| id_color(s.material_id as usize)),
+      Aov::ObjectId     => data(|s| id_color(s.object_id + 1)),
+      Aov::Position     => data(|s| s.position),
+      Aov::Emission     => self.light.emission,
+      Aov::Direct       => self.light.direct,
+      Aov::Indirect     => self.light.indirect,
+      Aov::Diffuse      => self.light.lobes[0],
+      Aov::Specular     => self.light.lobes[1],
+      Aov::Transmission => self.light.lobes[2]
+    }
+  }
+}
+
+
+// well separated colors for consecutive ids, hues stepped by the golden ratio
+pub fn id_color(id: usize) -> Color {
+  let hue = (id as f64 * 0.618033988749895).fract() * 6.0;
+  let x = 1.0 - (hue % 2.0 - 1.0).abs();
+  let (r, g, b) = match hue as usize {
+    0 => (1.0, x, 0.0),
+    1 => (x, 1.0, 0.0),
+    2 => (0.0, 1.0, x),
+    3 => (0.0, x, 1.0),
+    4 => (x, 0.0, 1.0),
+    _ => (1.0, 0.0, x)
+  };
+  // squared, the image is written with gamma 2
+  let c = Color::new(0.2 + 0.7 * r, 0.2 + 0.7 * g, 0.2 + 0.7 * b);
+  c * c
+}
+
+
+
+
+// the films of the enabled aovs, for a band of rows or the whole image like Film
+pub struct AovFilms {
+  pub aovs: Vec<Aov>,
+  pub films: Vec<Film>
+}
+
+
+impl AovFilms {
+  pub fn new(aovs: &[Aov], width: usize, height: usize) -> Self {
+    Self {aovs: aovs.to_vec(), films: aovs.iter().map(|_| Film::new(width, height)).collect()}
+  }
+
+  pub fn band_for_rows(aovs: &[Aov], width: usize, height: usize, first_row: usize, last_row: usize, filter: &Filter) -> Self {
+    Self {
+      aovs: aovs.to_vec(),
+      films: aovs.iter().map(|_| Film::band_for_rows(width, height, first_row, last_row, filter)).collect()
+    }
+  }
+
+  // first is whether this is the first sample taken in its pixel
+  pub fn add_sample(&mut self, x: f64, y: f64, sample: &AovSample, first: bool, filter: &Filter) {
+    for (aov, film) in self.aovs.iter().zip(self.films.iter_mut()) {
+      if first || !aov.first_sample_only() {
+        film.add_sample(x, y, sample.value(*aov), &aov.filter(filter));
+      }
+    }
+  }
+
+  pub fn film(&self, aov: Aov) -> Option<&Film> {
+    self.aovs.iter().position(|&a| a == aov).map(|i| &self.films[i])
+  }
+
+  pub fn merge(&mut self, other: &AovFilms) {
+    for (film, other) in self.films.iter_mut().zip(other.films.iter()) {
+      film.merge(other);
+    }
+  }
+}
+
+
+// a view of a data aov to look at, mapped into [0, 1] and squared to survive the gamma.
+// depth and position are stretched over this image, so views don't compare between frames
+pub fn false_color(aov: Aov, pixels: Vec<Color>) -> Vec<Color> {
+  let squared = |c: Color| c * c;
+  match aov {
+    Aov::Depth => {
+      // near is bright, nothing hit is black
+      let far = pixels.iter().map(|c| c.x).fold(0.0, f64::max);
+      pixels.into_iter()
+        .map(|c| if c.x > 0.0 {1.0 - 0.9 * c.x / far} else {0.0})
+        .map(|d| squared(Color::new(d, d, d)))
+        .collect()
+    },
+    Aov::Normal => pixels.into_iter()
+      .map(|n| if n.near_zero() {n} else {squared(0.5 * (n + Color::new(1.0, 1.0, 1.0)))})
+      .collect(),
+    Aov::Position => {
+      // stretched over the bounding box of everything that was hit
+      let (mut min, mut max) = (Point::new(f64::INFINITY, f64::INFINITY, f64::INFINITY), -Point::new(f64::INFINITY, f64::INFINITY, f64::INFINITY));
+      for p in pixels.iter().filter(|p| !p.near_zero()) {
+        min = Point::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+        max = Point::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+      }
+      let scale = |v: f64, lo: f64, hi: f64| if hi > lo {(v - lo) / (hi - lo)} else {0.5};
+      pixels.into_iter()
+        .map(|p| if p.near_zero() {p} else {squared(Color::new(scale(p.x, min.x, max.x), scale(p.y, min.y, max.y), scale(p.z, min.z, max.z)))})
+        .collect()
+    },
+    _ => pixels
+  }
+}
+
+
+
+#[cfg(test)]
+mod aov_tests {
+  use super::*;
+
+  #[test]
+  fn test_light_paths_add_up() {
+    let mut paths = LightPaths::new(0.0);
+    paths.add(1.0, 0, None);
+    paths.add(2.0, 1, Some(Lobe::Diffuse));
+    paths.add(4.0, 3, Some(Lobe::Specular));
+    paths.add(8.0, 2, Some(Lobe::Diffuse));
+    assert_eq!(paths.total(), 15.0);
+    assert_eq!((paths.emission, paths.direct, paths.indirect), (1.0, 2.0, 12.0));
+    assert_eq!(paths.lobes, [10.0, 4.0, 0.0]);
+    assert_eq!(paths.lobes.iter().sum::<f64>(), paths.direct + paths.indirect);
+  }
+
+  #[test]
+  fn test_parse_list() {
+    assert_eq!(Aov::parse_list("depth,albedo,depth").unwrap(), vec![Aov::Depth, Aov::Albedo]);
+    assert_eq!(Aov::parse_list("all").unwrap().len(), Aov::ALL.len());
+    assert!(Aov::parse_list("depth,beauty").is_err());
+  }
+
+  #[test]
+  fn test_ids_come_from_the_first_sample() {
+    let mut films = AovFilms::new(&[Aov::ObjectId, Aov::Direct], 1, 1);
+    let sample = |object_id: usize, direct: f64| AovSample {
+      light: LightPaths {direct: Color::new(direct, direct, direct), ..LightPaths::new(Color::new(0.0, 0.0, 0.0))},
+      surface: Some(SurfaceInfo {
+        distance: 1.0,
+        normal: Vector3::new(0.0, 1.0, 0.0),
+        position: Point::new(0.0, 0.0, 0.0),
+        albedo: Color::new(0.5, 0.5, 0.5),
+        material_id: 1,
+        object_id
+      })
+    };
+    films.add_sample(0.5, 0.5, &sample(0, 1.0), true, &Filter::Box(0.5));
+    films.add_sample(0.5, 0.5, &sample(1, 3.0), false, &Filter::Box(0.5));
+    assert_eq!(films.films[0].resolve(&Filter::Box(0.5))[0], id_color(1));
+    assert_eq!(films.films[1].resolve(&Filter::Box(0.5))[0].x, 2.0);
+  }
+}
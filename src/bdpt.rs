@@ -0,0 +1,423 @@
+use std::f64::consts::PI;
+
+use crate::aov::{LightPaths, SurfaceInfo};
+use crate::camera::Projection;
+use crate::film::SplatBuffer;
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::integrator::Integrator;
+use crate::interval::Interval;
+use crate::light::{self, Light, Lights};
+use crate::material::{Lobe, Material};
+use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerType};
+use crate::vector3::{Color, Point, Vector3};
+
+// bidirectional path tracing, camera and light subpaths connected at every pair of
+// vertices. paths are never connected at materials that can't be evaluated
+pub struct Bdpt {
+  pub max_depth: u32,
+  lights: Lights,
+  // the objects light paths start from
+  emitters: Vec<usize>,
+  camera: Projection,
+  pub splats: SplatBuffer
+}
+
+// subpaths this many vertices long start playing russian roulette
+const ROULETTE_VERTICES: usize = 4;
+
+
+// area densities of sampling the vertex from the one before it (fwd) and after it (rev)
+#[derive(Clone)]
+struct Vertex<'a> {
+  p: Point,
+  // area densities are measured against it, volumes have none
+  normal: Option<Vector3>,
+  // None for the camera
+  rec: Option<HitRecord<'a>>,
+  // the ray that arrived at the vertex, materials are evaluated for it
+  ray: Ray,
+  beta: Color,
+  // the material can only be scattered through
+  delta: bool,
+  pdf_fwd: f64,
+  pdf_rev: f64,
+  // what scatter took on the way out
+  lobe: Option<Lobe>
+}
+
+
+impl<'a> Vertex<'a> {
+  fn camera(ray: &Ray, forward: Vector3) -> Self {
+    Self {
+      p: ray.origin,
+      normal: Some(forward),
+      rec: None,
+      ray: *ray,
+      beta: Color::new(1.0, 1.0, 1.0),
+      delta: false,
+      pdf_fwd: 0.0,
+      pdf_rev: 0.0,
+      lobe: None
+    }
+  }
+
+  fn surface(rec: HitRecord<'a>, ray: Ray, beta: Color) -> Self {
+    let volume = rec.material.is_phase_function();
+    Self {
+      p: rec.p,
+      normal: (!volume).then_some(rec.normal),
+      rec: Some(rec),
+      ray,
+      beta,
+      delta: false,
+      pdf_fwd: 0.0,
+      pdf_rev: 0.0,
+      lobe: None
+    }
+  }
+
+  fn cos(&self, direction: Vector3) -> f64 {
+    self.normal.map_or(1.0, |n| Vector3::dot(n, Vector3::unit_vector(&direction)).abs())
+  }
+
+  // a solid angle density at this vertex as area density at next
+  fn to_area(&self, pdf: f64, next: &Vertex) -> f64 {
+    let d = next.p - self.p;
+    pdf * next.cos(d) / d.length_squared()
+  }
+
+  // the material times the cosine toward direction
+  fn f(&self, direction: Vector3) -> Color {
+    let rec = self.rec.as_ref().expect("not the camera");
+    rec.material.eval(&self.ray, rec, direction).map_or(Color::new(0.0, 0.0, 0.0), |eval| eval.f)
+  }
+
+  // area density of sampling next from here, light paths start without prev
+  fn pdf(&self, camera: &Projection, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+    let direction = next.p - self.p;
+    let pdf = match (&self.rec, prev) {
+      (None, _) => camera.pdf(direction),
+      (Some(_), None) => self.emission_pdf(direction),
+      (Some(rec), Some(prev)) => {
+        rec.material.eval(&Ray::new(prev.p, self.p - prev.p), rec, direction).map_or(0.0, |eval| eval.pdf)
+      }
+    };
+    self.to_area(pdf, next)
+  }
+
+  // light paths leave lights cosine weighted out of them
+  fn emission_pdf(&self, direction: Vector3) -> f64 {
+    let rec = self.rec.as_ref().expect("not the camera");
+    Vector3::dot(rec.outward_normal(), Vector3::unit_vector(&direction)).max(0.0) / PI
+  }
+}
+
+
+impl Bdpt {
+  pub fn new(max_depth: u32, world: &HittableList, camera: Projection) -> Self {
+    let lights = Lights::new(world);
+    let emitters = lights.objects().collect();
+    Self {max_depth, lights, emitters, camera, splats: SplatBuffer::new(camera.width, camera.height)}
+  }
+
+  // returns the ray with its throughput and pdf if it escapes to the sky
+  fn random_walk<'a>(&self, world: &'a HittableList, (ray, beta, pdf): (Ray, Color, f64), path: &mut Vec<Vertex<'a>>, max_vertices: usize, sampler: &mut SamplerType) -> Option<(Ray, Color, f64)> {
+    let largest = |c: Color| c.x.max(c.y).max(c.z);
+    let start = largest(beta);
+    let (mut ray, mut beta, mut pdf) = (ray, beta, pdf);
+    while path.len() < max_vertices {
+      let Some(rec) = world.hit(&ray, Interval::new(0.001, f64::INFINITY), sampler.rng()) else {
+        return Some((ray, beta, pdf));
+      };
+      let mut vertex = Vertex::surface(rec, ray, beta);
+      vertex.pdf_fwd = path.last().expect("starts at the camera or a light").to_area(pdf, &vertex);
+      if path.len() + 1 == max_vertices {
+        path.push(vertex);
+        break;
+      }
+      let rec = vertex.rec.as_ref().expect("hit");
+      let Some(scatter) = rec.material.scatter(&ray, rec, sampler) else {
+        path.push(vertex);
+        break;
+      };
+      let direction = scatter.ray.direction;
+      let (pdf_fwd, pdf_rev, delta) = match rec.material.eval(&ray, rec, direction) {
+        Some(eval) => {
+          let back = Ray::new(rec.p + direction, -direction);
+          (eval.pdf, rec.material.eval(&back, rec, -ray.direction).map_or(0.0, |eval| eval.pdf), false)
+        },
+        None => (0.0, 0.0, true)
+      };
+      vertex.delta = delta;
+      vertex.lobe = Some(scatter.lobe);
+      let prev = path.last_mut().expect("starts at the camera or a light");
+      prev.pdf_rev = vertex.to_area(pdf_rev, prev);
+      path.push(vertex);
+      beta *= scatter.attenuation;
+      pdf = pdf_fwd;
+      ray = scatter.ray;
+      if path.len() >= ROULETTE_VERTICES {
+        let survive = (largest(beta) / start).min(0.95);
+        if sampler.get_1d() >= survive {
+          break;
+        }
+        beta /= survive;
+      }
+    }
+    None
+  }
+
+  fn light_subpath<'a>(&self, world: &'a HittableList, sampler: &mut SamplerType) -> Vec<Vertex<'a>> {
+    let mut path = Vec::new();
+    // drawn either way, so the dimensions after them stay put
+    let (u_light, u_point, u_direction) = (sampler.get_1d(), sampler.get_2d(), sampler.get_2d());
+    if self.max_depth == 0 {
+      return path;
+    }
+    let Some(emission) = self.lights.sample_emission(world, u_light, u_point, u_direction, sampler.rng()) else {
+      return path;
+    };
+    let (emitted, pdf_point, direction) = (emission.emitted, emission.pdf_point, emission.direction);
+    let mut origin = Vertex::surface(emission.rec, emission.probe, emitted / pdf_point);
+    origin.pdf_fwd = pdf_point;
+    let pdf_direction = origin.emission_pdf(direction);
+    let ray = Ray::new(origin.p, direction);
+    path.push(origin);
+    // the cosine of the emitted light cancels against the density, pi is left
+    self.random_walk(world, (ray, emitted * (PI / pdf_point), pdf_direction), &mut path, self.max_depth as usize, sampler);
+    path
+  }
+
+  // the camera path ran into a light (no light vertices)
+  fn hit_light(&self, world: &HittableList, camera: &[Vertex]) -> Color {
+    let pt = camera.last().expect("camera vertices");
+    let rec = pt.rec.as_ref().expect("hit");
+    let emitted = rec.material.emitted(rec);
+    if emitted.near_zero() {
+      return emitted;
+    }
+    // glowing volumes and such are only ever found this way
+    let weight = if self.emitters.contains(&rec.object_id) {self.mis_weight(world, camera, &[])} else {1.0};
+    pt.beta * emitted * weight
+  }
+
+  // a light sampled from the last camera vertex (one light vertex), the sky included
+  fn sample_light(&self, world: &HittableList, camera: &[Vertex], sampler: &mut SamplerType) -> Color {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let pt = camera.last().expect("camera vertices");
+    let (u_light, u) = (sampler.get_1d(), sampler.get_2d());
+    if pt.delta {
+      return black;
+    }
+    let Some(sample) = self.lights.sample(world, pt.p, u_light, u) else {
+      return black;
+    };
+    let rec = pt.rec.as_ref().expect("hit");
+    let Some(eval) = rec.material.eval(&pt.ray, rec, sample.direction) else {
+      return black;
+    };
+    if eval.f.near_zero() {
+      return black;
+    }
+    let ray = Ray::new(pt.p, sample.direction);
+    match (sample.light, world.hit(&ray, Interval::new(0.001, f64::INFINITY), sampler.rng())) {
+      // only this and escaping make paths to the sky, weighted like next event estimation
+      (Light::Sky, None) => pt.beta * eval.f * light::sky(&ray) * (light::power_heuristic(sample.pdf, eval.pdf) / sample.pdf),
+      (Light::Object(i), Some(light_rec)) if light_rec.object_id == i => {
+        let emitted = light_rec.material.emitted(&light_rec);
+        if emitted.near_zero() {
+          return black;
+        }
+        let mut qs = Vertex::surface(light_rec, ray, black);
+        qs.pdf_fwd = self.lights.emission_pdf(world, i);
+        pt.beta * eval.f * emitted * (self.mis_weight(world, camera, std::slice::from_ref(&qs)) / sample.pdf)
+      },
+      _ => black
+    }
+  }
+
+  // the last light vertex connected to the lens, splatted onto its pixel
+  fn splat(&self, world: &HittableList, light: &[Vertex], sampler: &mut SamplerType) {
+    let qs = light.last().expect("light vertices");
+    let lens = self.camera.sample_lens(sampler.get_2d());
+    if qs.delta {
+      return;
+    }
+    let to_lens = lens - qs.p;
+    let Some((x, y)) = self.camera.raster(lens, -to_lens) else {
+      return;
+    };
+    let f = qs.f(to_lens);
+    if f.near_zero() || !unoccluded(world, qs.p, lens, sampler) {
+      return;
+    }
+    let pt = Vertex::camera(&Ray::new(lens, -to_lens), self.camera.forward);
+    let importance = self.camera.importance(-to_lens) * pt.cos(to_lens) / to_lens.length_squared();
+    let weight = self.mis_weight(world, std::slice::from_ref(&pt), light);
+    self.splats.add(x, y, qs.beta * f * (importance * weight));
+  }
+
+  // the last camera and light vertices connected with a shadow ray
+  fn connect(&self, world: &HittableList, camera: &[Vertex], light: &[Vertex], sampler: &mut SamplerType) -> Color {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let (pt, qs) = (camera.last().expect("camera vertices"), light.last().expect("light vertices"));
+    if pt.delta || qs.delta {
+      return black;
+    }
+    let d = qs.p - pt.p;
+    let f = pt.f(d) * qs.f(-d);
+    if f.near_zero() || !unoccluded(world, pt.p, qs.p, sampler) {
+      return black;
+    }
+    pt.beta * f * qs.beta * (self.mis_weight(world, camera, light) / d.length_squared())
+  }
+
+  // power heuristic from the ratios of neighbouring strategies, skipping the ones that
+  // would connect at a delta vertex
+  fn mis_weight(&self, world: &HittableList, camera: &[Vertex], light: &[Vertex]) -> f64 {
+    let (s, t) = (light.len(), camera.len());
+    if s + t == 2 {
+      return 1.0;
+    }
+    // the densities and delta flags as they are with this connection
+    let mut camera_pdfs = camera.iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect::<Vec<(f64, f64, bool)>>();
+    let mut light_pdfs = light.iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect::<Vec<(f64, f64, bool)>>();
+    let pt = &camera[t - 1];
+    let pt_minus = t.checked_sub(2).map(|i| &camera[i]);
+    let qs = light.last();
+    let qs_minus = s.checked_sub(2).map(|i| &light[i]);
+    camera_pdfs[t - 1].1 = match qs {
+      Some(qs) => qs.pdf(&self.camera, qs_minus, pt),
+      None => self.lights.emission_pdf(world, pt.rec.as_ref().expect("a light").object_id)
+    };
+    camera_pdfs[t - 1].2 = false;
+    if let Some(pt_minus) = pt_minus {
+      camera_pdfs[t - 2].1 = match qs {
+        Some(qs) => pt.pdf(&self.camera, Some(qs), pt_minus),
+        None => pt.to_area(pt.emission_pdf(pt_minus.p - pt.p), pt_minus)
+      };
+    }
+    if let Some(qs) = qs {
+      light_pdfs[s - 1].1 = pt.pdf(&self.camera, pt_minus, qs);
+      light_pdfs[s - 1].2 = false;
+      if let Some(qs_minus) = qs_minus {
+        light_pdfs[s - 2].1 = qs.pdf(&self.camera, Some(pt), qs_minus);
+      }
+    }
+    // delta vertices have no density, they count the same for every strategy
+    let remap = |pdf: f64| if pdf != 0.0 {pdf} else {1.0};
+    let mut sum = 0.0;
+    let mut ratio = 1.0;
+    for i in (1..t).rev() {
+      ratio *= remap(camera_pdfs[i].1) / remap(camera_pdfs[i].0);
+      if !camera_pdfs[i].2 && !camera_pdfs[i - 1].2 {
+        sum += ratio * ratio;
+      }
+    }
+    ratio = 1.0;
+    for i in (0..s).rev() {
+      ratio *= remap(light_pdfs[i].1) / remap(light_pdfs[i].0);
+      let after_delta = i > 0 && light_pdfs[i - 1].2;
+      if !light_pdfs[i].2 && !after_delta {
+        sum += ratio * ratio;
+      }
+    }
+    1.0 / (1.0 + sum)
+  }
+}
+
+
+impl Integrator for Bdpt {
+  // what the camera path gathers comes back, light tracing goes to the splats
+  fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut SamplerType, surface: Option<&mut Option<SurfaceInfo>>) -> LightPaths<Color> {
+    let mut paths = LightPaths::new(Color::new(0.0, 0.0, 0.0));
+    // the sky counts as a vertex, like a light
+    let max_vertices = self.max_depth as usize + 1;
+    let mut camera = vec![Vertex::camera(ray, self.camera.forward)];
+    let start = (*ray, Color::new(1.0, 1.0, 1.0), self.camera.pdf(ray.direction));
+    let escaped = self.random_walk(world, start, &mut camera, max_vertices, sampler);
+    if let (Some(surface), Some(first)) = (surface, camera.get(1)) {
+      *surface = Some(SurfaceInfo::new(&first.ray, first.rec.as_ref().expect("hit")));
+    }
+    let light = self.light_subpath(world, sampler);
+    // paths connected at the first hit count as diffuse
+    let first_lobe = camera.get(1).map(|v| v.lobe.unwrap_or(Lobe::Diffuse));
+    if let Some((ray, beta, pdf)) = escaped {
+      let last = camera.last().expect("camera vertices");
+      // the sky was sampled at the last vertex too unless it was the camera or a delta
+      let weight = if camera.len() > 1 && !last.delta {light::power_heuristic(pdf, self.lights.sky_pdf())} else {1.0};
+      paths.add(beta * weight * light::sky(&ray), camera.len() as u32 - 1, first_lobe);
+    }
+    for t in 1..=camera.len() {
+      for s in 0..=light.len() {
+        if (s == 1 && t == 1) || s + t < 2 || s + t > max_vertices {
+          continue;
+        }
+        let bounces = (s + t - 2) as u32;
+        let lobe = if bounces == 0 {None} else {first_lobe};
+        match (s, t) {
+          (0, _) => paths.add(self.hit_light(world, &camera[..t]), bounces, lobe),
+          (_, 1) => self.splat(world, &light[..s], sampler),
+          (1, _) => paths.add(self.sample_light(world, &camera[..t], sampler), bounces, lobe),
+          _      => paths.add(self.connect(world, &camera[..t], &light[..s], sampler), bounces, lobe)
+        }
+      }
+    }
+    paths
+  }
+}
+
+
+// volumes in the way block it as often as they would scatter
+fn unoccluded(world: &HittableList, from: Point, to: Point, sampler: &mut SamplerType) -> bool {
+  let d = to - from;
+  let distance = d.length();
+  world.hit(&Ray::new(from, d / distance), Interval::new(0.001, distance - 0.001), sampler.rng()).is_none()
+}
+
+
+
+#[cfg(test)]
+mod bdpt_tests {
+  use super::*;
+  use crate::integrator::{IntegratorType, PathTracer};
+  use crate::material::{MaterialType, Metal};
+  use crate::sphere::Sphere;
+  use crate::test_scenes::{self, pinhole};
+
+  // paths have to go through the blurry mirror ball without connecting at it
+  fn lamp_room() -> HittableList {
+    test_scenes::lamp_room(Sphere::new(Point::new(0.4, 0.3, 0.0), 0.3, MaterialType::Metal(Metal::new(Color::new(0.8, 0.8, 0.8), 0.3))))
+  }
+
+  // mean over the pixels, splats included
+  fn image_mean(integrator: &IntegratorType, world: &HittableList, camera: &Projection, n: u32) -> f64 {
+    let pixels = test_scenes::pixel_means(integrator, world, camera, n);
+    let splats = integrator.splats().map_or(0.0, |s| s.resolve(1.0).iter().map(|c| c.y).sum::<f64>());
+    (pixels.iter().sum::<f64>() + splats / n as f64) / pixels.len() as f64
+  }
+
+  #[test]
+  fn test_agrees_with_path_tracing() {
+    let world = lamp_room();
+    let camera = pinhole();
+    let n = 10000;
+    let path = image_mean(&IntegratorType::Path(PathTracer {max_depth: 5, spectral: false}), &world, &camera, n);
+    let bdpt = IntegratorType::Bdpt(Box::new(Bdpt::new(5, &world, camera)));
+    let mean = image_mean(&bdpt, &world, &camera, n);
+    assert!((mean / path - 1.0).abs() < 0.01, "{} {}", mean, path);
+    // light tracing did its share
+    assert!(bdpt.splats().unwrap().resolve(1.0).iter().any(|c| c.y > 0.0));
+  }
+
+  #[test]
+  fn test_raster_inverts_camera_rays() {
+    let camera = pinhole();
+    let (x, y) = (2.7, 0.4);
+    let target = camera.pixel00_loc + (x - 0.5) * camera.pixel_delta_u + (y - 0.5) * camera.pixel_delta_v;
+    let (rx, ry) = camera.raster(camera.center, target - camera.center).unwrap();
+    assert!((rx - x).abs() < 1e-9 && (ry - y).abs() < 1e-9);
+    assert!(camera.raster(camera.center, camera.center - target).is_none());
+  }
+}
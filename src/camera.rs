@@ -1,15 +1,18 @@
-use std::cell::UnsafeCell;
-use std::f64::INFINITY;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread;
-use rand::rngs::ThreadRng;
-use rand::Rng;
-use image::RgbImage;
-use crate::hittable::{Hittable, HittableList};
+use std::time::{Duration, Instant};
+use crate::adaptive::{self, AdaptiveSampling, PixelStats};
+use crate::aov::{self, Aov, AovFilms, AovSample};
+use crate::checkpoint::Checkpoint;
+use crate::denoise::{Denoiser, Guides};
+use crate::film::{Film, SplatBuffer};
+use crate::filter::Filter;
+use crate::hittable::HittableList;
 use crate::image::Image;
-use crate::interval::Interval;
-use crate::material::Material;
+use crate::integrator::{Integrator, IntegratorType, PathTracer};
 use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerType, SobolSampler};
 use crate::vector3::{Color, Point, Vector3};
 use crate::{
   IMAGE_WIDTH,
@@ -18,27 +21,135 @@ use crate::{
 };
 
 
+// render in passes over the whole image instead of finishing pixel by pixel,
+// writing the image in between so the render can be watched as it converges
+#[derive(Clone, Copy, Debug)]
+pub struct Progressive {
+  pub pass_samples: u32,
+  // write after every pass when None, otherwise at most this often
+  pub write_interval: Option<Duration>,
+  // stop after the pass that goes over it
+  pub time_budget: Option<Duration>
+}
+
+
+// set from the ctrl-c handler, the render stops taking samples and writes what it has
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// returns whether the render had already been interrupted before
+pub fn interrupt() -> bool {
+  INTERRUPTED.swap(true, Ordering::Relaxed)
+}
+
+pub fn interrupted() -> bool {
+  INTERRUPTED.load(Ordering::Relaxed)
+}
+
+// passes are what checkpoints are taken between, so a render that is not progressive
+// still gets split into passes of this many samples when checkpointing
+const CHECKPOINT_PASS_SAMPLES: u32 = 16;
+
+// rows of the image rendered as one unit of work
+const TILE_ROWS: usize = 16;
+
+
+// the camera seen from the scene, for light paths that have to find their way back
+// to the image. the lens is a disk around center, pinhole when the disk vectors are
+// zero, and everything in focus lies on a plane focus_dist in front of it where the
+// pixels are spread out like for camera rays
+#[derive(Clone, Copy, Debug)]
+pub struct Projection {
+  pub center: Point,
+  // unit length, into the scene
+  pub forward: Vector3,
+  pub focus_dist: f64,
+  pub defocus_disk_u: Vector3,
+  pub defocus_disk_v: Vector3,
+  pub pixel00_loc: Point,
+  pub pixel_delta_u: Vector3,
+  pub pixel_delta_v: Vector3,
+  pub width: usize,
+  pub height: usize
+}
+
+
+impl Projection {
+  pub fn sample_lens(&self, u: (f64, f64)) -> Point {
+    let p = Vector3::sample_in_unit_disk(u);
+    self.center + (p.x * self.defocus_disk_u) + (p.y * self.defocus_disk_v)
+  }
+
+  // the image coordinates a ray leaving the lens at origin passes through,
+  // None when it misses the image
+  pub fn raster(&self, origin: Point, direction: Vector3) -> Option<(f64, f64)> {
+    let along = Vector3::dot(direction, self.forward);
+    if along <= 0.0 {
+      return None;
+    }
+    let on_plane = origin + (self.focus_dist / along) * direction;
+    let corner = self.pixel00_loc - 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
+    let x = Vector3::dot(on_plane - corner, self.pixel_delta_u) / self.pixel_delta_u.length_squared();
+    let y = Vector3::dot(on_plane - corner, self.pixel_delta_v) / self.pixel_delta_v.length_squared();
+    (x >= 0.0 && y >= 0.0 && x < self.width as f64 && y < self.height as f64).then_some((x, y))
+  }
+
+  // solid angle density of camera rays in direction, as if the whole image was
+  // sampled uniformly at once
+  pub fn pdf(&self, direction: Vector3) -> f64 {
+    let cos = Vector3::dot(Vector3::unit_vector(&direction), self.forward);
+    if cos <= 0.0 {0.0} else {1.0 / (self.film_area() * cos.powi(3))}
+  }
+
+  // how much light arriving along direction counts toward the image, normalized over
+  // the whole image like pdf. it is per lens area, and the lens points light paths
+  // connect to are picked uniformly, so the area never shows up
+  pub fn importance(&self, direction: Vector3) -> f64 {
+    let cos = Vector3::dot(Vector3::unit_vector(&direction), self.forward);
+    if cos <= 0.0 {0.0} else {1.0 / (self.film_area() * cos.powi(4))}
+  }
+
+  // of the image on a plane at distance one
+  fn film_area(&self) -> f64 {
+    let pixel = Vector3::cross(self.pixel_delta_u, self.pixel_delta_v).length();
+    pixel * (self.width * self.height) as f64 / (self.focus_dist * self.focus_dist)
+  }
+}
+
+
 pub struct Camera {
   pub camera_center: Vector3,
-  pub viewport_u: Vector3,
-  pub viewport_v: Vector3,
   pub pixel_delta_u: Vector3,
   pub pixel_delta_v: Vector3,
-  pub viewport_upper_left: Vector3,
   pub pixel00_loc: Vector3,
   pub img: Image,
   pub samples_per_pixel: i32,
-  pub pixel_samples_scale: f64,
-  pub rng: ThreadRng,
   pub max_depth: i32,
-  pub vfov: f64,
-  pub u: Vector3,
-  pub v: Vector3,
   pub w: Vector3,
   defocus_disk_u: Vector3,
   defocus_disk_v: Vector3,
   pub defocus_angle: f64,
   pub focus_dist: f64,
+  pub sampler: SamplerType,
+  pub filter: Filter,
+  pub adaptive: Option<AdaptiveSampling>,
+  // written with the number of samples each pixel took when sampling adaptively
+  pub heatmap: Option<Image>,
+  pub progressive: Option<Progressive>,
+  // how often the render state is saved to the checkpoint file next to the image
+  pub checkpoint_interval: Option<Duration>,
+  // the scene with its arguments and the seed it was built and sampled with, a
+  // checkpoint only continues the same render
+  pub scene: String,
+  pub seed: u64,
+  resumed: Option<Checkpoint>,
+  // extra outputs and the files they are written to
+  pub aovs: Vec<(Aov, Image)>,
+  // false colour images of data aovs, next to their float files
+  pub aov_views: Vec<(Aov, Image)>,
+  // applied to the image before it is written, the albedo and normal aovs it is
+  // guided by are rendered even when they are not written
+  pub denoiser: Option<Denoiser>,
+  pub integrator: IntegratorType
 }
 
 
@@ -71,156 +182,314 @@ impl Camera {
     let defocus_disk_v = v * defocus_radius;
     let pixel00_loc = viewport_upper_left + 0.5 * (pixel_delta_u + pixel_delta_v);
     let samples_per_pixel = 500;
-    let pixel_samples_scale = 1.0 / samples_per_pixel as f64;
-    let rng = rand::rng();
     let max_depth = 50;
     Self { 
       camera_center,
-      viewport_u,
-      viewport_v,
       pixel_delta_u,
       pixel_delta_v,
-      viewport_upper_left,
       pixel00_loc,
       img,
       samples_per_pixel,
-      pixel_samples_scale,
-      rng,
       max_depth,
-      vfov,
-      u, 
-      v,
       w,
       defocus_disk_u,
       defocus_disk_v,
       defocus_angle,
-      focus_dist
+      focus_dist,
+      sampler: SamplerType::Sobol(SobolSampler::new(0)),
+      filter: Filter::Box(0.5),
+      adaptive: None,
+      heatmap: None,
+      progressive: None,
+      checkpoint_interval: None,
+      scene: String::new(),
+      seed: 0,
+      resumed: None,
+      aovs: Vec::new(),
+      aov_views: Vec::new(),
+      denoiser: None,
+      integrator: IntegratorType::Path(PathTracer {max_depth: max_depth as u32, spectral: false})
+    }
+  }
+
+  // one camera sample through the image point (x, y), in linear rgb whichever way it was
+  // traced. the first hit is only looked at when some aov needs it
+  fn sample(&self, x: f64, y: f64, world: &HittableList, sampler: &mut SamplerType) -> AovSample {
+    let ray = self.get_ray(x, y, sampler);
+    let mut surface = None;
+    let wants_surface = (!self.aovs.is_empty() || self.denoiser.is_some()).then_some(&mut surface);
+    let light = self.integrator.li(&ray, world, sampler, wants_surface);
+    AovSample {light, surface}
+  }
+
+  pub fn projection(&self) -> Projection {
+    let pinhole = self.defocus_angle <= 0.0;
+    let zero = Vector3::new(0.0, 0.0, 0.0);
+    Projection {
+      center: self.camera_center,
+      forward: -self.w,
+      focus_dist: self.focus_dist,
+      defocus_disk_u: if pinhole {zero} else {self.defocus_disk_u},
+      defocus_disk_v: if pinhole {zero} else {self.defocus_disk_v},
+      pixel00_loc: self.pixel00_loc,
+      pixel_delta_u: self.pixel_delta_u,
+      pixel_delta_v: self.pixel_delta_v,
+      width: self.img.width as usize,
+      height: self.img.height as usize
     }
   }
 
-  pub fn ray_color<T: Hittable>(&self, ray: &Ray, depth: i32, world: &T) -> Color {
-    if depth <= 0 {
-      return Color::new(0.0, 0.0, 0.0);
+  // continue the render saved in the checkpoint instead of starting from nothing
+  pub fn resume(&mut self, checkpoint: Checkpoint) -> Result<(), String> {
+    let (width, height) = (self.img.width as usize, self.img.height as usize);
+    if checkpoint.film.width != width || checkpoint.film.height() != height {
+      return Err(format!("the checkpoint is {}x{}, the image {}x{}", checkpoint.film.width, checkpoint.film.height(), width, height));
+    }
+    if checkpoint.settings != self.settings() || checkpoint.aov_films.len() != self.aov_list().len() {
+      return Err(format!("the checkpoint was rendered with {}, not {}", checkpoint.settings, self.settings()));
     }
-    if let Some(rec) = world.hit(ray, Interval::new(0.001, INFINITY)) {
-      if let Some((attenuation, scattered)) = rec.material.scatter(ray, &rec) {
-        return attenuation * self.ray_color(&scattered, depth - 1, world);
-      } else {
-        return Color::new(0.0, 0.0, 0.0)
+    if let (Some(splats), Some(saved)) = (self.integrator.splats(), &checkpoint.splats) {
+      if saved.width != width {
+        return Err(String::from("the splats in the checkpoint don't fit the image"));
       }
+      splats.copy_from(saved);
     }
-    let unit_direction = Vector3::unit_vector(&ray.direction);
-    let a = 0.5 * (unit_direction.y + 1.0);
-    (1.0 - a) * Color::new(1.0, 1.0, 1.0) +  a * Color::new(0.5, 0.7, 1.0)
-  }
-
-  pub fn render(&mut self, world: &HittableList) {
-    for row in 0..self.img.height {
-      println!("Rows remain: {}", self.img.height - row);
-      for col in 0..self.img.width {
-        let mut color = Color::new(0.0, 0.0, 0.0);
-        for s in 0..self.samples_per_pixel {
-          let ray = self.get_ray(col, row);
-          color += self.ray_color(&ray, self.max_depth, world);
-        };
-        self.img.write_color(self.pixel_samples_scale * color);      
+    self.resumed = Some(checkpoint);
+    Ok(())
+  }
+
+  // what has to stay the same for samples of two runs to be combined
+  fn settings(&self) -> String {
+    let aovs = self.aov_list().iter().map(|aov| format!(" {}", aov.name())).collect::<String>();
+    format!(
+      "{} seed {} depth {} {} {} {:?}{}",
+      self.scene, self.seed, self.max_depth, self.integrator.name(), self.sampler.name(), self.filter, aovs
+    )
+  }
+
+  // the written aovs first, then the denoiser guides that are not among them
+  fn aov_list(&self) -> Vec<Aov> {
+    let mut aovs = self.aovs.iter().map(|(aov, _)| *aov).collect::<Vec<Aov>>();
+    if self.denoiser.is_some() {
+      for guide in [Aov::Albedo, Aov::Normal] {
+        if !aovs.contains(&guide) {
+          aovs.push(guide);
+        }
       }
     }
+    aovs
   }
 
+  // renders until every pixel has its samples, in one pass or progressively.
+  // when interrupted it stops early, the image and the checkpoint are still written
   pub fn render_threaded(&mut self, world: Arc<HittableList>) {
-    // we got the shared access to the world list
-    // we make an array and split divide it by threads
-    // we spawn threads
-    // each thread calculates its stuff and writes to its chunk
-    // at the end we have an array of pixels that we convert to png
-    
-    
-
-
-    let threaded_buffer
-      : Arc::<[UnsafeCell<(u8, u8, u8)>]> 
-      = Arc::from(
-        (0..IMAGE_WIDTH*IMAGE_HEIGHT)
-          .map(|_| UnsafeCell::new((0,0,0)))
-          .collect::<Vec<UnsafeCell<(u8, u8, u8)>>>());
-
-    let thread_count = thread::available_parallelism().map_or(4, |c| c.get() as u16);
-    println!("{}", thread_count);
-
-    let rows_per_thread = IMAGE_HEIGHT / thread_count;
-    let remainder_rows = IMAGE_HEIGHT % thread_count;
-
-    let mut handles = Vec::with_capacity(thread_count as usize);
-
-    for t in 0..thread_count {
-      let start_row = t * rows_per_thread;
-      let end_row 
-        = start_row 
-        + rows_per_thread 
-        + if t == thread_count - 1 
-            {remainder_rows}
-          else {0};
-
-      let buffer_clone = Arc::clone(&threaded_buffer);
-      let world_clone = Arc::clone(&world);
-
-      handles.push(
-        thread::spawn(move || {
-          // for row in start_row..end_row {
-          //   for col in 0..IMAGE_WIDTH {
-          //     let mut color = Color::new(0.0, 0.0, 0.0);
-          //     for s in 0..self.samples_per_pixel {
-          //       let ray = self.get_ray(col, row);
-          //       color += self.ray_color(&ray, self.max_depth, world_clone.as_ref());
-          //     };
-          //   }
-          // }
+    let width = self.img.width as usize;
+    let height = self.img.height as usize;
+    let (mut film, mut aov_films, target) = match self.resumed.take() {
+      Some(checkpoint) => (checkpoint.film, AovFilms {aovs: self.aov_list(), films: checkpoint.aov_films}, checkpoint.target),
+      None => (Film::new(width, height), AovFilms::new(&self.aov_list(), width, height), 0)
+    };
+    println!("Threads: {}", thread::available_parallelism().map_or(4, |c| c.get()));
+    let (target, _) = self.render_passes(&world, &mut film, &mut aov_films, target);
+    self.write(&film, &aov_films);
+    // the last checkpoint also lets a finished render be continued with a larger budget
+    if interrupted() || self.checkpoint_interval.is_some() {
+      self.save_checkpoint(&film, &aov_films, target);
+    }
+  }
+
+  // takes passes from target samples per pixel on until the sample or the time budget is
+  // used up, every pixel has converged or the render is interrupted. returns the samples
+  // per pixel reached and how many passes were taken
+  fn render_passes(&mut self, world: &Arc<HittableList>, film: &mut Film, aov_films: &mut AovFilms, mut target: u32) -> (u32, u32) {
+    let budget = self.adaptive.map_or(self.samples_per_pixel as u32, |a| a.max_samples);
+    let pass_samples = match (self.progressive, self.checkpoint_interval) {
+      (Some(progressive), _) => progressive.pass_samples.max(1),
+      (None, Some(_)) => CHECKPOINT_PASS_SAMPLES,
+      (None, None) => self.integrator.pass_samples().unwrap_or(budget)
+    };
+    let mut passes = 0;
+    let start = Instant::now();
+    let mut last_write = start;
+    let mut last_checkpoint = start;
+    while target < budget {
+      let pass_target = (target + pass_samples).min(budget);
+      self.integrator.start_pass(world, target / pass_samples, pass_target - target, &self.sampler);
+      let taken = self.render_pass(world, film, aov_films, pass_target);
+      passes += 1;
+      if interrupted() {
+        break;
+      }
+      target = pass_target;
+      if self.checkpoint_interval.is_some_and(|interval| target < budget && last_checkpoint.elapsed() >= interval) {
+        self.save_checkpoint(film, aov_films, target);
+        last_checkpoint = Instant::now();
+      }
+      // with adaptive sampling every pixel may have converged before the budget
+      if taken == 0 {
+        break;
+      }
+      let Some(progressive) = self.progressive else {
+        continue;
+      };
+      println!("{} samples per pixel after {:.1}s", target, start.elapsed().as_secs_f64());
+      if progressive.time_budget.is_some_and(|budget| start.elapsed() >= budget) {
+        break;
+      }
+      if target < budget && progressive.write_interval.is_none_or(|interval| last_write.elapsed() >= interval) {
+        self.write(film, aov_films);
+        last_write = Instant::now();
+      }
+    }
+    (target, passes)
+  }
+
+  fn save_checkpoint(&self, film: &Film, aov_films: &AovFilms, target: u32) {
+    if let Err(e) = Checkpoint::save(&self.img.checkpoint_path(), &self.settings(), target, film, &aov_films.films, self.integrator.splats()) {
+      println!("Error writing the checkpoint: {}", e);
+    }
+  }
+
+  // takes samples until every pixel has target of them (or has converged), returns
+  // how many were taken. the image is cut into tiles of a few rows that the threads
+  // take one after another, each tile with its own film reaching a filter radius
+  // further so samples near the edge can splat into the next tile. the tile films are
+  // merged in order once all threads are done, so the sums come out bit for bit the
+  // same however many threads there were
+  fn render_pass(&self, world: &Arc<HittableList>, film: &mut Film, aov_films: &mut AovFilms, target: u32) -> u64 {
+    let width = film.width;
+    let height = self.img.height as usize;
+    let thread_count = thread::available_parallelism().map_or(4, |c| c.get());
+    let tile_count = height.div_ceil(TILE_ROWS);
+    let next_tile = AtomicUsize::new(0);
+
+    let aovs = self.aov_list();
+    let done = &*film;
+    let mut tiles = thread::scope(|scope| {
+      let handles = (0..thread_count.min(tile_count)).map(|_| {
+        let world = Arc::clone(world);
+        let next_tile = &next_tile;
+        let aovs = &aovs;
+        scope.spawn(move || {
+          let mut sampler = self.sampler.clone();
+          let mut tiles = Vec::new();
+          loop {
+            let tile = next_tile.fetch_add(1, Ordering::Relaxed);
+            if tile >= tile_count {
+              break;
+            }
+            let start_row = tile * TILE_ROWS;
+            let end_row = (start_row + TILE_ROWS).min(height);
+            let mut band = Film::band_for_rows(width, height, start_row, end_row, &self.filter);
+            let mut aov_band = AovFilms::band_for_rows(aovs, width, height, start_row, end_row, &self.filter);
+            let mut taken = 0;
+            for row in start_row..end_row {
+              for col in 0..width {
+                let mut stats = *done.stats(col, row);
+                while !self.pixel_done(&stats, target) && !interrupted() {
+                  sampler.start_pixel_sample(col as u32, row as u32, stats.samples);
+                  let offset = Self::sample_square(sampler.get_2d());
+                  let x = col as f64 + 0.5 + offset.x;
+                  let y = row as f64 + 0.5 + offset.y;
+                  let sample = self.sample(x, y, world.as_ref(), &mut sampler);
+                  let color = sample.light.total();
+                  band.add_sample(x, y, color, &self.filter);
+                  aov_band.add_sample(x, y, &sample, stats.samples == 0, &self.filter);
+                  stats.add(color);
+                  taken += 1;
+                }
+              }
+            }
+            tiles.push((tile, band, aov_band, taken));
+          }
+          tiles
         })
-      );
+      }).collect::<Vec<_>>();
+      handles.into_iter().flat_map(|h| h.join().unwrap()).collect::<Vec<(usize, Film, AovFilms, u64)>>()
+    });
+
+    tiles.sort_by_key(|&(tile, _, _, _)| tile);
+    let mut taken = 0;
+    for (_, band, aov_band, band_taken) in &tiles {
+      film.merge(band);
+      aov_films.merge(aov_band);
+      taken += band_taken;
+    }
+    taken
+  }
 
+  fn write(&self, film: &Film, aov_films: &AovFilms) {
+    let mut pixels = film.resolve(&self.filter);
+    if let Some(splats) = self.integrator.splats() {
+      Self::add_splats(&mut pixels, film, splats);
+    }
+    if let (Some(denoiser), Some(albedo), Some(normal)) = (self.denoiser, aov_films.film(Aov::Albedo), aov_films.film(Aov::Normal)) {
+      pixels = denoiser.denoise(&pixels, film.width, &Guides::from_films(film, albedo, normal, &self.filter));
+    }
+    if let Err(e) = self.img.save(&pixels) {
+      println!("Error writing the image: {}", e);
+    }
+    for ((aov, image), aov_film) in self.aovs.iter().zip(aov_films.films.iter()) {
+      let pixels = aov_film.resolve(&self.filter);
+      if let Err(e) = image.save(&pixels) {
+        println!("Error writing the {} aov: {}", aov.name(), e);
+      }
+      if let Some((_, view)) = self.aov_views.iter().find(|(view, _)| view == aov) {
+        if let Err(e) = view.save(&aov::false_color(*aov, pixels)) {
+          println!("Error writing the {} aov view: {}", aov.name(), e);
+        }
+      }
+    }
+    if let (Some(heatmap), Some(adaptive)) = (&self.heatmap, self.adaptive) {
+      let counts = film.sample_counts();
+      let colors = counts.iter().map(|&samples| adaptive::heatmap_color(samples, adaptive.max_samples)).collect::<Vec<Color>>();
+      if let Err(e) = heatmap.save(&colors) {
+        println!("Error writing the sample heatmap: {}", e);
+      }
+    }
+  }
 
-  //   for row in 0..self.img.height {
-  //     for col in 0..self.img.width {
-  //       let mut color = Color::new(0.0, 0.0, 0.0);
-  //       for s in 0..self.samples_per_pixel {
-  //         let ray = self.get_ray(col, row);
-  //         color += self.ray_color(&ray, self.max_depth, world);
-  //       };
-  //       self.img.write_color(self.pixel_samples_scale * color);      
-  //     }
-  //   }
-  // }
-      
+  // splats are averaged over all paths of the image, not those of their pixel. unless
+  // the integrator counts its own, every camera sample traced one of them
+  fn add_splats(pixels: &mut [Color], film: &Film, splats: &SplatBuffer) {
+    let samples = match splats.paths() {
+      0 => film.sample_counts().iter().map(|&s| s as f64).sum::<f64>(),
+      paths => paths as f64
+    };
+    if samples == 0.0 {
+      return;
+    }
+    let scale = pixels.len() as f64 / samples;
+    for (pixel, splat) in pixels.iter_mut().zip(splats.resolve(scale)) {
+      *pixel += splat;
     }
-    
+  }
 
-    // add a function to return a color instead of writing it to the buffer // self.img.get_color_threaded()
-    // let mut image = RgbImage::new(IMAGE_WIDTH.into(), IMAGE_HEIGHT.into());
+  fn pixel_done(&self, stats: &PixelStats, target: u32) -> bool {
+    stats.samples >= target || self.adaptive.is_some_and(|adaptive| adaptive.converged(stats))
   }
 
-  pub fn get_ray(&mut self, col: u16, row: u16) -> Ray {
-    let offset = self.sample_square();
+  // x and y are image coordinates, the pixel offset has already been drawn by the caller
+  // and the lens position is always drawn next, so the sampler dimensions line up the
+  // same way for every camera sample
+  pub fn get_ray(&self, x: f64, y: f64, sampler: &mut SamplerType) -> Ray {
+    let lens = sampler.get_2d();
     let pixel_sample = self.pixel00_loc
-                   + ((col as f64 + offset.x) * self.pixel_delta_u)
-                   + ((row as f64 + offset.y) * self.pixel_delta_v);
-    let ray_origin = if self.defocus_angle <= 0.0 {self.camera_center} else {self.defocus_disk_sample()};
+                   + ((x - 0.5) * self.pixel_delta_u)
+                   + ((y - 0.5) * self.pixel_delta_v);
+    let ray_origin = if self.defocus_angle <= 0.0 {self.camera_center} else {self.defocus_disk_sample(lens)};
     let ray_direction = pixel_sample - ray_origin;
     Ray::new(ray_origin, ray_direction)
   }
 
-  pub fn defocus_disk_sample(&self) -> Point {
-    let p = Vector3::random_in_unit_disk();
+  pub fn defocus_disk_sample(&self, u: (f64, f64)) -> Point {
+    let p = Vector3::sample_in_unit_disk(u);
     self.camera_center + (p.x * self.defocus_disk_u) + (p.y * self.defocus_disk_v)
   }
 
-  pub fn sample_square(&mut self) -> Vector3 {
-    Vector3::new(
-      self.rng.random::<f64>() - 0.5,
-      self.rng.random::<f64>() - 0.5,
-      0.0
-    )
-  } 
+  pub fn sample_square((u1, u2): (f64, f64)) -> Vector3 {
+    Vector3::new(u1 - 0.5, u2 - 0.5, 0.0)
+  }
 
   pub fn linear_to_gamma(linear_component: f64) -> f64 {
     if linear_component > 0.0 {
@@ -229,4 +498,140 @@ impl Camera {
       0.0
     }
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod camera_tests {
+  use super::*;
+  use crate::hittable::HittableObject;
+  use crate::material::{Lambertian, MaterialType};
+  use crate::mlt::Metropolis;
+  use crate::sphere::Sphere;
+
+  fn camera(spp: i32, progressive: Option<Progressive>) -> Camera {
+    let mut cam = Camera::new(Image::scratch(8, 8, "camera.ppm"));
+    cam.samples_per_pixel = spp;
+    cam.max_depth = 4;
+    cam.integrator = IntegratorType::Path(PathTracer {max_depth: 4, spectral: false});
+    cam.sampler = SamplerType::from_name("independent", spp as u32, 7).unwrap();
+    cam.progressive = progressive;
+    cam
+  }
+
+  // no writes in between, the tests only look at the film
+  fn passes_of(pass_samples: u32) -> Option<Progressive> {
+    Some(Progressive {pass_samples, write_interval: Some(Duration::MAX), time_budget: None})
+  }
+
+  fn world() -> Arc<HittableList> {
+    let mut world = HittableList::new();
+    let gray = MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    world.list.push(HittableObject::Sphere(Sphere::new(Point::new(0.0, 0.0, -1.0), 0.5, gray)));
+    Arc::new(world)
+  }
+
+  fn render(cam: &mut Camera, world: &Arc<HittableList>) -> (Film, u32, u32) {
+    let mut film = Film::new(8, 8);
+    let mut aov_films = AovFilms::new(&cam.aov_list(), 8, 8);
+    let (target, passes) = cam.render_passes(world, &mut film, &mut aov_films, 0);
+    (film, target, passes)
+  }
+
+  #[test]
+  fn test_pass_budget_stops_after_its_passes() {
+    let world = world();
+    for (spp, pass_samples) in [(10, 3), (12, 4), (5, 8)] {
+      let (film, target, passes) = render(&mut camera(spp, passes_of(pass_samples)), &world);
+      assert_eq!(passes, (spp as u32).div_ceil(pass_samples));
+      assert_eq!(target, spp as u32);
+      assert!(film.sample_counts().iter().all(|&n| n == spp as u32));
+    }
+  }
+
+  #[test]
+  fn test_converged_pixels_end_the_passes() {
+    // nothing to hit, every pixel converges at its first check
+    let world = Arc::new(HittableList::new());
+    let mut cam = camera(64, passes_of(8));
+    cam.adaptive = Some(AdaptiveSampling {min_samples: 8, max_samples: 64, threshold: 1.0});
+    let (film, target, passes) = render(&mut cam, &world);
+    assert_eq!(passes, 2);
+    assert_eq!(target, 16);
+    assert!(film.sample_counts().iter().all(|&n| n == 8));
+  }
+
+  #[test]
+  fn test_resume_needs_the_same_scene_depth_and_seed() {
+    let on = |scene: &str| {
+      let mut cam = camera(4, None);
+      cam.scene = String::from(scene);
+      cam
+    };
+    let checkpoint = |cam: &Camera| Checkpoint {settings: cam.settings(), target: 4, film: Film::new(8, 8), aov_films: Vec::new(), splats: None};
+    let saved = on("spheres");
+    let mut deeper = on("spheres");
+    deeper.max_depth = 8;
+    let mut reseeded = on("spheres");
+    reseeded.seed = 3;
+    for mut other in [on("smoke"), on("spheres 2"), deeper, reseeded] {
+      assert!(other.resume(checkpoint(&saved)).is_err());
+    }
+    assert!(on("spheres").resume(checkpoint(&saved)).is_ok());
+  }
+
+  #[test]
+  fn test_interrupted_mlt_pass_resumes_as_bright() {
+    let world = world();
+    let mlt = |cam: &Camera| {
+      let mut mlt = Metropolis::new(4, &world, cam.projection(), 7);
+      (mlt.chains, mlt.bootstrap) = (16, 2000);
+      IntegratorType::Metropolis(Box::new(mlt))
+    };
+    let brightness = |cam: &Camera, film: &Film| {
+      let mut pixels = vec![Color::new(0.0, 0.0, 0.0); 64];
+      Camera::add_splats(&mut pixels, film, cam.integrator.splats().unwrap());
+      pixels.iter().map(|c| c.y).sum::<f64>() / 64.0
+    };
+    let pass = |cam: &mut Camera, film: &mut Film, samples: u32| {
+      let mut aov_films = AovFilms::new(&[], 8, 8);
+      cam.integrator.start_pass(&world, 0, 16, &cam.sampler);
+      cam.render_pass(&world, film, &mut aov_films, samples);
+    };
+    let mut full = camera(16, None);
+    full.integrator = mlt(&full);
+    let mut full_film = Film::new(8, 8);
+    pass(&mut full, &mut full_film, 16);
+    let expected = brightness(&full, &full_film);
+    // stopped after the chains ran but a quarter of the way through the camera samples
+    let mut cam = camera(16, None);
+    cam.img = Image::scratch(8, 8, "mlt.ppm");
+    cam.integrator = mlt(&cam);
+    let mut film = Film::new(8, 8);
+    pass(&mut cam, &mut film, 4);
+    assert!((brightness(&cam, &film) / expected - 1.0).abs() < 0.2, "{} {}", brightness(&cam, &film), expected);
+    let path = cam.img.checkpoint_path();
+    Checkpoint::save(&path, &cam.settings(), 0, &film, &[], cam.integrator.splats()).unwrap();
+    let checkpoint = Checkpoint::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    // the resumed render runs the chains of the pass again
+    cam.integrator = mlt(&cam);
+    cam.resume(checkpoint).unwrap();
+    let mut film = cam.resumed.take().unwrap().film;
+    pass(&mut cam, &mut film, 16);
+    assert!((brightness(&cam, &film) / expected - 1.0).abs() < 0.2, "{} {}", brightness(&cam, &film), expected);
+  }
+
+  #[test]
+  fn test_passes_render_the_single_pass_image() {
+    let world = world();
+    let (single, _, single_passes) = render(&mut camera(12, None), &world);
+    let (passes, _, pass_count) = render(&mut camera(12, passes_of(5)), &world);
+    assert_eq!(single_passes, 1);
+    assert_eq!(pass_count, 3);
+    assert_eq!(single.sample_counts(), passes.sample_counts());
+    // the same samples, only summed in a different order
+    for (a, b) in single.resolve(&Filter::Box(0.5)).iter().zip(passes.resolve(&Filter::Box(0.5)).iter()) {
+      assert!((*a - *b).length() < 1e-12, "{:?} {:?}", a, b);
+    }
+  }
+}
@@ -0,0 +1,183 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::film::{Film, SplatBuffer};
+
+// everything needed to pick a render up where it stopped. the samplers are indexed by
+// pixel and sample number, so the sample counts kept in the film are their whole state:
+// a resumed render continues every pixel at the sample it would have taken next
+pub struct Checkpoint {
+  // sampler, filter and whatever else has to match for the samples to be combined
+  pub settings: String,
+  // samples per pixel of the last pass that finished
+  pub target: u32,
+  pub film: Film,
+  // in the order of the aovs listed in the settings
+  pub aov_films: Vec<Film>,
+  // for integrators that splat light onto the image
+  pub splats: Option<SplatBuffer>
+}
+
+
+const MAGIC: &[u8; 8] = b"RAYCKPT3";
+
+
+impl Checkpoint {
+  // written next to the old checkpoint and renamed over it, so dying halfway
+  // through leaves the previous one intact
+  pub fn save(path: &Path, settings: &str, target: u32, film: &Film, aov_films: &[Film], splats: Option<&SplatBuffer>) -> io::Result<()> {
+    let temporary = path.with_extension("ckpt.tmp");
+    let mut writer = BufWriter::new(File::create(&temporary)?);
+    writer.write_all(MAGIC)?;
+    write_u32(&mut writer, settings.len() as u32)?;
+    writer.write_all(settings.as_bytes())?;
+    write_u32(&mut writer, target)?;
+    film.write_to(&mut writer)?;
+    write_u32(&mut writer, aov_films.len() as u32)?;
+    for aov_film in aov_films {
+      aov_film.write_to(&mut writer)?;
+    }
+    write_u32(&mut writer, splats.is_some() as u32)?;
+    if let Some(splats) = splats {
+      splats.write_to(&mut writer)?;
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&temporary, path)
+  }
+
+  pub fn load(path: &Path) -> io::Result<Self> {
+    let file = File::open(path)?;
+    // no size read from the file may promise more than the file holds
+    let limit = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "not a render checkpoint"));
+    }
+    let length = checked_size(&[read_u32(&mut reader)? as usize], 1, limit)?;
+    let mut settings = vec![0; length];
+    reader.read_exact(&mut settings)?;
+    let settings = String::from_utf8(settings).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let target = read_u32(&mut reader)?;
+    let film = Film::read_from(&mut reader, limit)?;
+    let aov_count = read_u32(&mut reader)?;
+    let aov_films = (0..aov_count).map(|_| Film::read_from(&mut reader, limit)).collect::<io::Result<Vec<Film>>>()?;
+    let splats = match read_u32(&mut reader)? {
+      0 => None,
+      _ => Some(SplatBuffer::read_from(&mut reader, limit)?)
+    };
+    Ok(Self {settings, target, film, aov_films, splats})
+  }
+}
+
+
+// the number of items of item_bytes each that dims multiply out to, refused when
+// that is more than the limit bytes of the file could hold
+pub fn checked_size(dims: &[usize], item_bytes: usize, limit: u64) -> io::Result<usize> {
+  dims.iter()
+    .try_fold(1usize, |n, &d| n.checked_mul(d))
+    .filter(|&n| n.checked_mul(item_bytes).is_some_and(|bytes| bytes as u64 <= limit))
+    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "a size in the checkpoint is larger than the file"))
+}
+
+// everything is stored little endian
+pub fn write_u32<W: Write>(writer: &mut W, v: u32) -> io::Result<()> {
+  writer.write_all(&v.to_le_bytes())
+}
+
+pub fn write_f64<W: Write>(writer: &mut W, v: f64) -> io::Result<()> {
+  writer.write_all(&v.to_le_bytes())
+}
+
+pub fn write_u64<W: Write>(writer: &mut W, v: u64) -> io::Result<()> {
+  writer.write_all(&v.to_le_bytes())
+}
+
+pub fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+  let mut bytes = [0; 4];
+  reader.read_exact(&mut bytes)?;
+  Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+  let mut bytes = [0; 8];
+  reader.read_exact(&mut bytes)?;
+  Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn read_f64<R: Read>(reader: &mut R) -> io::Result<f64> {
+  let mut bytes = [0; 8];
+  reader.read_exact(&mut bytes)?;
+  Ok(f64::from_le_bytes(bytes))
+}
+
+
+
+#[cfg(test)]
+mod checkpoint_tests {
+  use super::*;
+  use crate::filter::Filter;
+  use crate::vector3::Color;
+
+  #[test]
+  fn test_round_trip() {
+    let filter = Filter::Tent(1.0);
+    let mut film = Film::new(4, 3);
+    film.add_sample(1.3, 0.8, Color::new(0.25, 0.5, 2.0), &filter);
+    film.add_sample(3.9, 2.1, Color::new(1.0, 0.0, 0.125), &filter);
+    let path = std::env::temp_dir().join(format!("ray_checkpoint_test_{}.ckpt", std::process::id()));
+    let splats = SplatBuffer::new(4, 3);
+    splats.add(2.5, 1.5, Color::new(0.75, 0.0, 3.0));
+    splats.add_paths(12);
+    Checkpoint::save(&path, "sobol box", 8, &film, &[Film::new(4, 3)], Some(&splats)).unwrap();
+    let loaded = Checkpoint::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.settings, "sobol box");
+    assert_eq!(loaded.target, 8);
+    assert_eq!(loaded.aov_films.len(), 1);
+    assert_eq!(loaded.film.sample_counts(), film.sample_counts());
+    for (a, b) in loaded.film.resolve(&filter).iter().zip(film.resolve(&filter)) {
+      assert_eq!((a.x, a.y, a.z), (b.x, b.y, b.z));
+    }
+    assert_eq!(loaded.film.stats(1, 0), film.stats(1, 0));
+    let loaded_splats = loaded.splats.unwrap();
+    assert_eq!(loaded_splats.resolve(1.0)[6].z, 3.0);
+    assert_eq!(loaded_splats.paths(), 12);
+  }
+
+  #[test]
+  fn test_rejects_sizes_larger_than_the_file() {
+    let path = std::env::temp_dir().join(format!("ray_checkpoint_truncated_{}.ckpt", std::process::id()));
+    let mut film = Film::new(4, 3);
+    film.add_sample(1.3, 0.8, Color::new(0.25, 0.5, 2.0), &Filter::Box(0.5));
+    Checkpoint::save(&path, "sobol box", 8, &film, &[], None).unwrap();
+    let bytes = fs::read(&path).unwrap();
+    let settings_end = MAGIC.len() + 4 + "sobol box".len();
+    let film_start = settings_end + 4;
+    let corrupt = |at: usize, value: u32| {
+      let mut bytes = bytes.clone();
+      bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+      fs::write(&path, bytes).unwrap();
+      Checkpoint::load(&path).err().map(|e| e.kind())
+    };
+    // the settings length, the film width and its rows
+    assert_eq!(corrupt(MAGIC.len(), u32::MAX), Some(io::ErrorKind::InvalidData));
+    assert_eq!(corrupt(film_start, u32::MAX), Some(io::ErrorKind::InvalidData));
+    assert_eq!(corrupt(film_start + 8, 1 << 30), Some(io::ErrorKind::InvalidData));
+    fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+    assert!(Checkpoint::load(&path).is_err());
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn test_rejects_other_files() {
+    let path = std::env::temp_dir().join(format!("ray_checkpoint_garbage_{}.ckpt", std::process::id()));
+    fs::write(&path, b"P3\n1 1\n255\n0 0 0\n").unwrap();
+    let result = Checkpoint::load(&path);
+    fs::remove_file(&path).unwrap();
+    assert!(result.is_err());
+  }
+}
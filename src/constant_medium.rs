@@ -0,0 +1,98 @@
+use std::borrow::Cow;
+
+use crate::material::{Isotropic, MaterialType};
+use crate::vector3::{Color, Vector3};
+use crate::hittable::{Hittable, HitRecord, HittableObject};
+use crate::ray::Ray;
+use crate::interval::Interval;
+use crate::rng::Rng;
+
+pub struct ConstantMedium {
+  pub boundary: Box<HittableObject>,
+  pub density: f64,
+  pub phase_function: MaterialType
+}
+
+
+impl ConstantMedium {
+  pub fn new(boundary: HittableObject, density: f64, albedo: Color) -> Self {
+    Self {
+      boundary: Box::new(boundary),
+      density,
+      phase_function: MaterialType::Isotropic(Isotropic::new(albedo))
+    }
+  }
+}
+
+
+// entry and exit of the ray through a closed boundary. both are searched along the
+// whole line, so for a ray that starts inside the entry lies behind its origin
+pub fn boundary_crossings<'a>(boundary: &'a HittableObject, ray: &Ray, rng: &mut Rng) -> Option<(HitRecord<'a>, HitRecord<'a>)> {
+  let rec1 = boundary.hit(ray, Interval::universe(), rng)?;
+  let rec2 = boundary.hit(ray, Interval::new(rec1.t + 0.0001, f64::INFINITY), rng)?;
+  Some((rec1, rec2))
+}
+
+
+impl Hittable for ConstantMedium {
+  fn hit(&self, ray: &Ray, ray_t: Interval, rng: &mut Rng) -> Option<HitRecord<'_>> {
+    // nothing to scatter off, and the distances below would come out nan or negative
+    if self.density <= 0.0 {
+      return None;
+    }
+    let (rec1, rec2) = boundary_crossings(&self.boundary, ray, rng)?;
+    let t_enter = rec1.t.max(ray_t.min).max(0.0);
+    let t_exit = rec2.t.min(ray_t.max);
+    if t_enter >= t_exit {
+      return None;
+    }
+    let ray_length = ray.direction.length();
+    let distance_inside_boundary = (t_exit - t_enter) * ray_length;
+    let hit_distance = -(1.0 - rng.uniform()).ln() / self.density;
+    if hit_distance > distance_inside_boundary {
+      return None;
+    }
+    let t = t_enter + hit_distance / ray_length;
+    // normal and front_face are meaningless inside a volume, the phase function ignores them
+    Some(HitRecord::new(ray, t, Vector3::new(1.0, 0.0, 0.0), Cow::Borrowed(&self.phase_function)))
+  }
+}
+
+
+#[cfg(test)]
+mod constant_medium_tests {
+  use super::*;
+  use crate::material::Lambertian;
+  use crate::sphere::Sphere;
+  use crate::vector3::Point;
+
+  fn fog(density: f64) -> ConstantMedium {
+    let boundary = Sphere::new(Point::new(0.0, 0.0, 0.0), 1000.0, MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+    ConstantMedium::new(HittableObject::Sphere(boundary), density, Color::new(1.0, 1.0, 1.0))
+  }
+
+  #[test]
+  fn test_mean_free_path_is_one_over_density() {
+    let medium = fog(2.0);
+    let mut rng = Rng::new(3);
+    let n = 100_000;
+    let ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 2.0));
+    let mut sum = 0.0;
+    for _ in 0..n {
+      let rec = medium.hit(&ray, Interval::new(0.0, f64::INFINITY), &mut rng).expect("the box is far larger than the free path");
+      sum += rec.t * ray.direction.length();
+    }
+    let mean = sum / n as f64;
+    assert!((mean - 0.5).abs() < 0.01, "{}", mean);
+  }
+
+  #[test]
+  fn test_no_hits_without_density() {
+    let mut rng = Rng::new(3);
+    let ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0));
+    for density in [0.0, -1.0] {
+      let medium = fog(density);
+      assert!((0..1000).all(|_| medium.hit(&ray, Interval::new(0.0, f64::INFINITY), &mut rng).is_none()));
+    }
+  }
+}
@@ -0,0 +1,227 @@
+use crate::adaptive;
+use crate::aov::{self, LightPaths, SurfaceInfo};
+use crate::hittable::{Hittable, HittableList, HittableObject};
+use crate::integrator::Integrator;
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::rng::Rng;
+use crate::sampler::{Sampler, SamplerType};
+use crate::vector3::{Color, Vector3};
+
+// views that replace the light with something about the geometry, to see what the
+// renderer sees when a scene looks wrong. everything but the ray depth only looks
+// at the first hit, nothing hit is black
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugMode {
+  // world space shading normal facing out of the surface, xyz as rgb
+  Normals,
+  // half bright at the focus distance, brighter in front of it
+  Depth,
+  // u as red and v as green
+  Uv,
+  // how many objects the camera ray was tested against. there is no bvh, the scene
+  // is a flat list, so this shows what volumes and nested objects add on top of it
+  Cost,
+  // how many times the path scattered before it escaped, was absorbed or ran into
+  // the max depth
+  RayDepth,
+  MaterialId,
+  // the edges of quads and triangles over the surfaces shaded by how much they face
+  // the camera
+  Wireframe,
+  // the albedo of materials without any lighting
+  Albedo
+}
+
+// wireframe edges are this close to the border in the coordinates of the quad or triangle
+const EDGE_WIDTH: f64 = 0.02;
+
+
+// the debug views as an integrator, the color comes back as emission
+pub struct DebugIntegrator {
+  pub mode: DebugMode,
+  pub max_depth: u32,
+  // the depth view is scaled to the focus distance of the camera
+  pub focus_dist: f64
+}
+
+
+impl DebugMode {
+  pub const ALL: [DebugMode; 8] = [
+    DebugMode::Normals, DebugMode::Depth, DebugMode::Uv, DebugMode::Cost,
+    DebugMode::RayDepth, DebugMode::MaterialId, DebugMode::Wireframe, DebugMode::Albedo
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      DebugMode::Normals    => "normals",
+      DebugMode::Depth      => "depth",
+      DebugMode::Uv         => "uv",
+      DebugMode::Cost       => "cost",
+      DebugMode::RayDepth   => "ray-depth",
+      DebugMode::MaterialId => "material",
+      DebugMode::Wireframe  => "wireframe",
+      DebugMode::Albedo     => "albedo"
+    }
+  }
+
+  pub fn from_name(name: &str) -> Result<Self, String> {
+    Self::ALL.iter().find(|mode| mode.name() == name).copied().ok_or_else(|| format!("unknown debug mode {}", name))
+  }
+}
+
+
+impl DebugIntegrator {
+  // the color of one camera ray, in linear rgb like the light it replaces. data is
+  // squared so it shows up as it is after the gamma the image is written with
+  fn color(&self, ray: &Ray, world: &HittableList, sampler: &mut SamplerType) -> Color {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let squared = |c: Color| c * c;
+    match self.mode {
+      DebugMode::Cost => {
+        let tests: u64 = world.list.iter().map(|object| hit_tests(object, ray, Interval::new(0.001, f64::INFINITY), sampler.rng())).sum();
+        // a list the size of the scene is green, twice that red
+        adaptive::heatmap_color(tests as u32, 2 * world.list.len() as u32)
+      },
+      DebugMode::RayDepth => {
+        let mut ray = *ray;
+        let mut bounces = 0;
+        while bounces < self.max_depth {
+          let Some(rec) = world.hit(&ray, Interval::new(0.001, f64::INFINITY), sampler.rng()) else {
+            break;
+          };
+          let Some(scatter) = rec.material.scatter(&ray, &rec, sampler) else {
+            break;
+          };
+          ray = scatter.ray;
+          bounces += 1;
+        }
+        adaptive::heatmap_color(bounces, self.max_depth)
+      },
+      _ => {
+        let Some(rec) = world.hit(ray, Interval::new(0.001, f64::INFINITY), sampler.rng()) else {
+          return black;
+        };
+        match self.mode {
+          DebugMode::Normals => {
+            let n = if rec.front_face {rec.shading_normal} else {-rec.shading_normal};
+            squared(0.5 * (n + Color::new(1.0, 1.0, 1.0)))
+          },
+          DebugMode::Depth => {
+            let d = self.focus_dist / (self.focus_dist + rec.t * ray.direction.length());
+            squared(Color::new(d, d, d))
+          },
+          DebugMode::Uv => squared(Color::new(rec.u.clamp(0.0, 1.0), rec.v.clamp(0.0, 1.0), 0.0)),
+          DebugMode::MaterialId => aov::id_color(rec.material.id() as usize),
+          DebugMode::Wireframe => {
+            if world.list[rec.object_id].edge_distance(&rec).is_some_and(|d| d < EDGE_WIDTH) {
+              return Color::new(1.0, 1.0, 1.0);
+            }
+            let facing = Vector3::dot(Vector3::unit_vector(&ray.direction), rec.normal).abs();
+            squared(Color::new(0.1 + 0.4 * facing, 0.1 + 0.4 * facing, 0.1 + 0.4 * facing))
+          },
+          _ => rec.material.albedo(&rec)
+        }
+      }
+    }
+  }
+}
+
+
+// how many objects a hit on object tests, itself and the ones nested in it. the calls
+// mirror what the hit of each wrapper does, so nothing is counted outside this view
+fn hit_tests(object: &HittableObject, ray: &Ray, ray_t: Interval, rng: &mut Rng) -> u64 {
+  // the two boundary tests of boundary_crossings, the second only if the first hit
+  let crossings = |boundary: &HittableObject, rng: &mut Rng| {
+    let tests = hit_tests(boundary, ray, Interval::universe(), rng);
+    let crossed = boundary.hit(ray, Interval::universe(), rng).is_some();
+    (if crossed {2 * tests} else {tests}, crossed)
+  };
+  match object {
+    HittableObject::Sphere(_) | HittableObject::Quad(_) => 1,
+    HittableObject::ConstantMedium(c)      => 1 + crossings(&c.boundary, rng).0,
+    HittableObject::HeterogeneousMedium(h) => 1 + crossings(&h.boundary, rng).0,
+    HittableObject::Subsurface(s) => {
+      // a ray from outside looks for the surface once more
+      let (tests, crossed) = crossings(&s.boundary, rng);
+      1 + tests + if crossed {hit_tests(&s.boundary, ray, ray_t, rng)} else {0}
+    },
+    HittableObject::AlphaMasked(a) => {
+      // every rejected hit asks the object again
+      let mut tests = 1;
+      let mut t_min = ray_t.min;
+      loop {
+        tests += hit_tests(&a.object, ray, Interval::new(t_min, ray_t.max), rng);
+        match a.object.hit(ray, Interval::new(t_min, ray_t.max), rng) {
+          Some(rec) if !a.opaque_at(&rec, rng) => t_min = rec.t,
+          _ => return tests
+        }
+      }
+    }
+  }
+}
+
+
+impl Integrator for DebugIntegrator {
+  // there is no surface to report, the aovs would only repeat the views
+  fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut SamplerType, _surface: Option<&mut Option<SurfaceInfo>>) -> LightPaths<Color> {
+    let black = Color::new(0.0, 0.0, 0.0);
+    LightPaths {emission: self.color(ray, world, sampler), ..LightPaths::new(black)}
+  }
+}
+
+
+
+#[cfg(test)]
+mod debug_tests {
+  use super::*;
+  use crate::constant_medium::ConstantMedium;
+  use crate::material::{Lambertian, MaterialType};
+  use crate::quad::Quad;
+  use crate::sphere::Sphere;
+  use crate::vector3::Point;
+
+  fn gray() -> MaterialType {
+    MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+  }
+
+  #[test]
+  fn test_names_round_trip() {
+    for mode in DebugMode::ALL {
+      assert_eq!(DebugMode::from_name(mode.name()), Ok(mode));
+    }
+    assert!(DebugMode::from_name("bvh").is_err());
+  }
+
+  #[test]
+  fn test_every_object_tested_counts() {
+    let mut world = HittableList::new();
+    for z in [-2.0, -4.0, -6.0] {
+      world.list.push(HittableObject::Sphere(Sphere::new(Point::new(0.0, 0.0, z), 0.5, gray())));
+    }
+    let ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0));
+    let tests: u64 = world.list.iter().map(|object| hit_tests(object, &ray, Interval::new(0.001, f64::INFINITY), &mut Rng::new(0))).sum();
+    assert_eq!(tests, 3);
+  }
+
+  #[test]
+  fn test_media_count_their_boundary() {
+    let boundary = HittableObject::Sphere(Sphere::new(Point::new(0.0, 0.0, -2.0), 0.5, gray()));
+    let fog = HittableObject::ConstantMedium(ConstantMedium::new(boundary, 0.1, Color::new(1.0, 1.0, 1.0)));
+    let through = Ray::new(Point::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0));
+    let past = Ray::new(Point::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+    let t = || Interval::new(0.001, f64::INFINITY);
+    assert_eq!(hit_tests(&fog, &through, t(), &mut Rng::new(0)), 3);
+    assert_eq!(hit_tests(&fog, &past, t(), &mut Rng::new(0)), 2);
+  }
+
+  #[test]
+  fn test_triangle_edges() {
+    let triangle = Quad::triangle(Point::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), gray());
+    let quad = Quad::new(Point::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), gray());
+    // close to the hypotenuse of the triangle, in the middle of the quad
+    assert!((triangle.edge_distance(0.5, 0.49) - 0.01).abs() < 1e-9);
+    assert!((quad.edge_distance(0.5, 0.49) - 0.49).abs() < 1e-9);
+  }
+}
@@ -0,0 +1,269 @@
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::vector3::{Color, Vector3};
+
+// edge avoiding filters that smooth the noise out of the resolved image. they are
+// guided by the albedo and normals of what the camera saw, which are noise free
+// compared to the light, so edges and texture survive while the lighting is blurred.
+// the light is divided by the albedo before filtering and multiplied back after
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Denoiser {
+  // five passes of a 5x5 b-spline kernel with holes that double every pass, stopped
+  // by the normals, the albedo and the luminance relative to its noise (svgf without
+  // the temporal part)
+  Atrous,
+  // joint bilateral filter over a square window, the luminance distance is measured
+  // against the noise of both pixels
+  Bilateral
+}
+
+// how strongly the edges in each guide stop the filter
+const NORMAL_POWER: i32 = 128;
+const ALBEDO_SIGMA: f64 = 0.1;
+const ATROUS_LUMINANCE_SIGMA: f64 = 4.0;
+const ATROUS_PASSES: u32 = 5;
+const BILATERAL_RADIUS: i64 = 7;
+const BILATERAL_SPATIAL_SIGMA: f64 = 3.0;
+const BILATERAL_LUMINANCE_SIGMA: f64 = 3.0;
+// the variance of a pixel with a single sample is unknown, it gets this much
+const UNKNOWN_VARIANCE: f64 = 1e3;
+
+
+// what the filters look at besides the image, one value per pixel row by row
+pub struct Guides {
+  albedo: Vec<Color>,
+  // unit length, zero where nothing was hit
+  normal: Vec<Vector3>,
+  // of the luminance of the pixel value
+  variance: Vec<f64>
+}
+
+
+impl Guides {
+  // the image film gives the noise of every pixel, the other two are the albedo and
+  // normal aovs rendered along with it
+  pub fn from_films(image: &Film, albedo: &Film, normal: &Film, filter: &Filter) -> Self {
+    let height = image.height();
+    let variance = (0..height)
+      .flat_map(|row| (0..image.width).map(move |col| image.stats(col, row)))
+      .map(|stats| if stats.samples < 2 {UNKNOWN_VARIANCE} else {stats.variance() / stats.samples as f64})
+      .collect();
+    // a pixel on an edge averages the normals on both sides
+    let normal = normal.resolve(filter).into_iter()
+      .map(|n| if n.near_zero() {n} else {Vector3::unit_vector(&n)})
+      .collect();
+    Self {albedo: albedo.resolve(filter), normal, variance}
+  }
+
+  // how much the normals and albedo of two pixels allow them to be averaged
+  fn weight(&self, p: usize, q: usize) -> f64 {
+    let (np, nq) = (self.normal[p], self.normal[q]);
+    let normal = match (np.near_zero(), nq.near_zero()) {
+      (true, true) => 1.0,
+      (false, false) => Vector3::dot(np, nq).max(0.0).powi(NORMAL_POWER),
+      _ => 0.0
+    };
+    let d = self.albedo[p] - self.albedo[q];
+    normal * (-d.length_squared() / (ALBEDO_SIGMA * ALBEDO_SIGMA)).exp()
+  }
+}
+
+
+impl Denoiser {
+  pub fn from_name(name: &str) -> Result<Self, String> {
+    match name {
+      "atrous"    => Ok(Denoiser::Atrous),
+      "bilateral" => Ok(Denoiser::Bilateral),
+      _           => Err(format!("unknown denoiser {}", name))
+    }
+  }
+
+  // pixels are the linear image row by row, width wide
+  pub fn denoise(&self, pixels: &[Color], width: usize, guides: &Guides) -> Vec<Color> {
+    // the albedo is only divided out where it is there, the sky and black
+    // channels are filtered as they are
+    let demodulate = |albedo: Color| Color::new(
+      if albedo.x > 0.01 {albedo.x} else {1.0},
+      if albedo.y > 0.01 {albedo.y} else {1.0},
+      if albedo.z > 0.01 {albedo.z} else {1.0}
+    );
+    let albedo = guides.albedo.iter().map(|&a| demodulate(a)).collect::<Vec<Color>>();
+    let irradiance = pixels.iter().zip(albedo.iter()).map(|(&c, &a)| Color::new(c.x / a.x, c.y / a.y, c.z / a.z)).collect::<Vec<Color>>();
+    // the variance scales with the square of what the luminance was divided by
+    let variance = guides.variance.iter().zip(albedo.iter())
+      .map(|(&v, &a)| v / a.luminance().powi(2))
+      .collect::<Vec<f64>>();
+    let filtered = match self {
+      Denoiser::Atrous    => Self::atrous(irradiance, variance, width, guides),
+      Denoiser::Bilateral => Self::bilateral(&irradiance, &variance, width, guides)
+    };
+    filtered.iter().zip(albedo.iter()).map(|(&c, &a)| c * a).collect()
+  }
+
+  fn atrous(mut color: Vec<Color>, mut variance: Vec<f64>, width: usize, guides: &Guides) -> Vec<Color> {
+    const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+    let height = color.len() / width;
+    for pass in 0..ATROUS_PASSES {
+      let step = 1 << pass;
+      // the luminance weights use a blurred variance, a single pixel estimate is too noisy
+      let blurred = Self::blur_variance(&variance, width);
+      let mut next_color = vec![Color::new(0.0, 0.0, 0.0); color.len()];
+      let mut next_variance = vec![0.0; variance.len()];
+      for row in 0..height {
+        for col in 0..width {
+          let p = row * width + col;
+          let lp = color[p].luminance();
+          let scale = ATROUS_LUMINANCE_SIGMA * blurred[p].sqrt() + 1e-6;
+          let (mut sum, mut sum_variance, mut total) = (Color::new(0.0, 0.0, 0.0), 0.0, 0.0);
+          for (i, &ky) in KERNEL.iter().enumerate() {
+            let y = row as i64 + (i as i64 - 2) * step;
+            if y < 0 || y >= height as i64 {
+              continue;
+            }
+            for (j, &kx) in KERNEL.iter().enumerate() {
+              let x = col as i64 + (j as i64 - 2) * step;
+              if x < 0 || x >= width as i64 {
+                continue;
+              }
+              let q = y as usize * width + x as usize;
+              let w = kx * ky * guides.weight(p, q) * (-(lp - color[q].luminance()).abs() / scale).exp();
+              sum += w * color[q];
+              sum_variance += w * w * variance[q];
+              total += w;
+            }
+          }
+          // the center always has a weight, total can't be zero
+          next_color[p] = sum / total;
+          next_variance[p] = sum_variance / (total * total);
+        }
+      }
+      color = next_color;
+      variance = next_variance;
+    }
+    color
+  }
+
+  fn bilateral(color: &[Color], variance: &[f64], width: usize, guides: &Guides) -> Vec<Color> {
+    let height = color.len() / width;
+    let mut filtered = vec![Color::new(0.0, 0.0, 0.0); color.len()];
+    for row in 0..height {
+      for col in 0..width {
+        let p = row * width + col;
+        let lp = color[p].luminance();
+        let (mut sum, mut total) = (Color::new(0.0, 0.0, 0.0), 0.0);
+        for dy in -BILATERAL_RADIUS..=BILATERAL_RADIUS {
+          let y = row as i64 + dy;
+          if y < 0 || y >= height as i64 {
+            continue;
+          }
+          for dx in -BILATERAL_RADIUS..=BILATERAL_RADIUS {
+            let x = col as i64 + dx;
+            if x < 0 || x >= width as i64 {
+              continue;
+            }
+            let q = y as usize * width + x as usize;
+            let spatial = -((dx * dx + dy * dy) as f64) / (2.0 * BILATERAL_SPATIAL_SIGMA * BILATERAL_SPATIAL_SIGMA);
+            // a difference the noise of the two pixels explains costs little
+            let d = lp - color[q].luminance();
+            let range = -d * d / (BILATERAL_LUMINANCE_SIGMA * BILATERAL_LUMINANCE_SIGMA * (variance[p] + variance[q]) + 1e-6);
+            let w = (spatial + range).exp() * guides.weight(p, q);
+            sum += w * color[q];
+            total += w;
+          }
+        }
+        filtered[p] = sum / total;
+      }
+    }
+    filtered
+  }
+
+  // 3x3 gaussian
+  fn blur_variance(variance: &[f64], width: usize) -> Vec<f64> {
+    const KERNEL: [f64; 3] = [0.25, 0.5, 0.25];
+    let height = variance.len() / width;
+    let mut blurred = vec![0.0; variance.len()];
+    for row in 0..height {
+      for col in 0..width {
+        let (mut sum, mut total) = (0.0, 0.0);
+        for (i, &ky) in KERNEL.iter().enumerate() {
+          for (j, &kx) in KERNEL.iter().enumerate() {
+            let (y, x) = ((row + i).wrapping_sub(1), (col + j).wrapping_sub(1));
+            if y < height && x < width {
+              sum += kx * ky * variance[y * width + x];
+              total += kx * ky;
+            }
+          }
+        }
+        blurred[row * width + col] = sum / total;
+      }
+    }
+    blurred
+  }
+}
+
+
+
+
+
+#[cfg(test)]
+mod denoise_tests {
+  use super::*;
+  use crate::rng::Rng;
+
+  // a flat white wall on the left and a black one on the right, facing the camera,
+  // lit evenly with noise on top
+  fn noisy_walls(width: usize, height: usize) -> (Vec<Color>, Guides) {
+    let mut rng = Rng::new(7);
+    let mut pixels = Vec::new();
+    let mut albedo = Vec::new();
+    for _ in 0..height {
+      for col in 0..width {
+        let a = if col < width / 2 {0.8} else {0.2};
+        let light = 1.0 + 0.5 * (rng.uniform() - 0.5);
+        pixels.push(Color::new(a * light, a * light, a * light));
+        albedo.push(Color::new(a, a, a));
+      }
+    }
+    let guides = Guides {
+      albedo,
+      normal: vec![Vector3::new(0.0, 0.0, 1.0); width * height],
+      variance: vec![0.02; width * height]
+    };
+    (pixels, guides)
+  }
+
+  fn error(pixels: &[Color], width: usize) -> f64 {
+    pixels.iter().enumerate()
+      .map(|(i, c)| (c.x - if i % width < width / 2 {0.8} else {0.2}).abs())
+      .sum::<f64>() / pixels.len() as f64
+  }
+
+  #[test]
+  fn test_noise_goes_down_and_the_edge_stays() {
+    let (width, height) = (32, 16);
+    let (pixels, guides) = noisy_walls(width, height);
+    for denoiser in [Denoiser::Atrous, Denoiser::Bilateral] {
+      let denoised = denoiser.denoise(&pixels, width, &guides);
+      assert!(error(&denoised, width) < 0.3 * error(&pixels, width), "{:?}", denoiser);
+      // no white bleeds over the edge
+      let row = &denoised[8 * width..9 * width];
+      assert!(row[width / 2].x < 0.25 && row[width / 2 - 1].x > 0.7, "{:?}", denoiser);
+    }
+  }
+
+  #[test]
+  fn test_normals_stop_the_filter() {
+    let mut guides = Guides {
+      albedo: vec![Color::new(1.0, 1.0, 1.0); 8],
+      normal: vec![Vector3::new(0.0, 1.0, 0.0); 8],
+      variance: vec![UNKNOWN_VARIANCE; 8]
+    };
+    for n in &mut guides.normal[4..] {
+      *n = Vector3::new(1.0, 0.0, 0.0);
+    }
+    let pixels = (0..8).map(|i| if i < 4 {Color::new(1.0, 1.0, 1.0)} else {Color::new(0.0, 0.0, 0.0)}).collect::<Vec<Color>>();
+    for denoiser in [Denoiser::Atrous, Denoiser::Bilateral] {
+      assert_eq!(denoiser.denoise(&pixels, 8, &guides), pixels);
+    }
+  }
+}
@@ -0,0 +1,330 @@
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::adaptive::PixelStats;
+use crate::checkpoint::{checked_size, read_f64, read_u32, read_u64, write_f64, write_u32, write_u64};
+use crate::filter::Filter;
+use crate::vector3::Color;
+
+// accumulates filtered samples for a band of image rows. every sample is splatted
+// into the pixels around it with the filter weight, the pixel value is the weighted
+// average. threads render into their own band, which reaches a filter radius beyond
+// the rows they own, and the bands get merged into the full image film at the end.
+// the statistics of the samples are kept for the pixel each sample was taken in
+pub struct Film {
+  pub width: usize,
+  first_row: usize,
+  rows: usize,
+  sum: Vec<Color>,
+  weight: Vec<f64>,
+  stats: Vec<PixelStats>
+}
+
+
+impl Film {
+  pub fn new(width: usize, height: usize) -> Self {
+    Self::band(width, height, 0, height)
+  }
+
+  // rows [first_row, first_row + rows) of an image height rows tall
+  pub fn band(width: usize, height: usize, first_row: usize, rows: usize) -> Self {
+    let first_row = first_row.min(height);
+    let rows = rows.min(height - first_row);
+    Self {
+      width,
+      first_row,
+      rows,
+      sum: vec![Color::new(0.0, 0.0, 0.0); width * rows],
+      weight: vec![0.0; width * rows],
+      stats: vec![PixelStats::default(); width * rows]
+    }
+  }
+
+  // the band a thread rendering rows [first_row, last_row) splats into
+  pub fn band_for_rows(width: usize, height: usize, first_row: usize, last_row: usize, filter: &Filter) -> Self {
+    let margin = filter.radius().ceil() as usize;
+    let start = first_row.saturating_sub(margin);
+    Self::band(width, height, start, last_row + margin - start)
+  }
+
+  // x and y are continuous image coordinates, pixel (i, j) has its center at (i + 0.5, j + 0.5)
+  pub fn add_sample(&mut self, x: f64, y: f64, color: Color, filter: &Filter) {
+    if x >= 0.0 && y >= self.first_row as f64 {
+      let (col, row) = (x as usize, y as usize);
+      if col < self.width && row < self.first_row + self.rows {
+        self.stats[(row - self.first_row) * self.width + col].add(color);
+      }
+    }
+    let radius = filter.radius();
+    let x0 = ((x - 0.5 - radius).ceil().max(0.0)) as usize;
+    let x1 = ((x - 0.5 + radius).floor().min(self.width as f64 - 1.0)).max(-1.0) as isize;
+    let y0 = ((y - 0.5 - radius).ceil().max(self.first_row as f64)) as usize;
+    let y1 = ((y - 0.5 + radius).floor().min((self.first_row + self.rows) as f64 - 1.0)).max(-1.0) as isize;
+    if x1 < x0 as isize || y1 < y0 as isize {
+      return;
+    }
+    // the filter is separable, so the weights along x are shared by all rows
+    let weights_x: Vec<f64> = (x0..=x1 as usize).map(|i| filter.evaluate(i as f64 + 0.5 - x)).collect();
+    for j in y0..=y1 as usize {
+      let weight_y = filter.evaluate(j as f64 + 0.5 - y);
+      if weight_y == 0.0 {
+        continue;
+      }
+      let row = (j - self.first_row) * self.width;
+      for (i, &weight_x) in (x0..=x1 as usize).zip(weights_x.iter()) {
+        let weight = weight_x * weight_y;
+        self.sum[row + i] += weight * color;
+        self.weight[row + i] += weight;
+      }
+    }
+  }
+
+  pub fn merge(&mut self, other: &Film) {
+    for j in other.first_row..other.first_row + other.rows {
+      if j < self.first_row || j >= self.first_row + self.rows {
+        continue;
+      }
+      let src = (j - other.first_row) * other.width;
+      let dst = (j - self.first_row) * self.width;
+      for i in 0..self.width.min(other.width) {
+        self.sum[dst + i] += other.sum[src + i];
+        self.weight[dst + i] += other.weight[src + i];
+        self.stats[dst + i].merge(&other.stats[src + i]);
+      }
+    }
+  }
+
+  pub fn stats(&self, col: usize, row: usize) -> &PixelStats {
+    &self.stats[(row - self.first_row) * self.width + col]
+  }
+
+  // number of samples taken in each pixel of the band, row by row
+  pub fn sample_counts(&self) -> Vec<u32> {
+    self.stats.iter().map(|s| s.samples).collect()
+  }
+
+  // the whole state of the film, for checkpoints
+  pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    for v in [self.width, self.first_row, self.rows] {
+      write_u32(writer, v as u32)?;
+    }
+    for ((sum, &weight), stats) in self.sum.iter().zip(self.weight.iter()).zip(self.stats.iter()) {
+      for v in [sum.x, sum.y, sum.z, weight] {
+        write_f64(writer, v)?;
+      }
+      stats.write_to(writer)?;
+    }
+    Ok(())
+  }
+
+  // limit is the size of the file, every pixel takes at least its four sums
+  pub fn read_from<R: Read>(reader: &mut R, limit: u64) -> io::Result<Self> {
+    let width = read_u32(reader)? as usize;
+    let first_row = read_u32(reader)? as usize;
+    let rows = read_u32(reader)? as usize;
+    checked_size(&[width, rows], 4 * 8, limit)?;
+    let mut film = Self::band(width, first_row + rows, first_row, rows);
+    for i in 0..width * rows {
+      film.sum[i] = Color::new(read_f64(reader)?, read_f64(reader)?, read_f64(reader)?);
+      film.weight[i] = read_f64(reader)?;
+      film.stats[i] = PixelStats::read_from(reader)?;
+    }
+    Ok(film)
+  }
+
+  pub fn height(&self) -> usize {
+    self.first_row + self.rows
+  }
+
+  // final pixel values of the band, row by row, for the filter the samples were added with
+  pub fn resolve(&self, filter: &Filter) -> Vec<Color> {
+    let min_weight = filter.min_weight();
+    self.sum.iter().zip(self.weight.iter())
+      .map(|(&sum, &weight)| {
+        let weight = weight.max(min_weight);
+        if weight > 0.0 {sum / weight} else {Color::new(0.0, 0.0, 0.0)}
+      })
+      .collect()
+  }
+}
+
+
+
+// light that lands on the image away from the pixel being sampled, which light
+// tracing puts wherever its paths reach the camera. every thread adds to the whole
+// image at once, so the sums are atomic, and in fixed point so they come out the
+// same whatever order the threads add in. splats go to the pixel they fall in,
+// without the filter
+pub struct SplatBuffer {
+  pub width: usize,
+  height: usize,
+  // three channels per pixel, row by row
+  sum: Vec<AtomicU64>,
+  // paths splatted, for integrators that don't trace one per camera sample. 0 leaves
+  // the camera samples to average over
+  paths: AtomicU64
+}
+
+// fractional bits of the fixed point sums
+const SPLAT_SCALE: f64 = (1u64 << 32) as f64;
+
+
+impl SplatBuffer {
+  pub fn new(width: usize, height: usize) -> Self {
+    Self {width, height, sum: (0..3 * width * height).map(|_| AtomicU64::new(0)).collect(), paths: AtomicU64::new(0)}
+  }
+
+  pub fn add_paths(&self, paths: u64) {
+    self.paths.fetch_add(paths, Ordering::Relaxed);
+  }
+
+  pub fn paths(&self) -> u64 {
+    self.paths.load(Ordering::Relaxed)
+  }
+
+  // x and y are continuous image coordinates like for Film::add_sample, light is never
+  // negative and whatever is not finite is dropped
+  pub fn add(&self, x: f64, y: f64, color: Color) {
+    if !(x >= 0.0 && y >= 0.0 && (x as usize) < self.width && (y as usize) < self.height) {
+      return;
+    }
+    let pixel = 3 * (y as usize * self.width + x as usize);
+    for (i, c) in [color.x, color.y, color.z].into_iter().enumerate() {
+      if c.is_finite() && c > 0.0 {
+        self.sum[pixel + i].fetch_add((c * SPLAT_SCALE).round() as u64, Ordering::Relaxed);
+      }
+    }
+  }
+
+  // the sums times scale, row by row
+  pub fn resolve(&self, scale: f64) -> Vec<Color> {
+    self.sum.chunks(3)
+      .map(|c| scale * Color::new(Self::value(&c[0]), Self::value(&c[1]), Self::value(&c[2])))
+      .collect()
+  }
+
+  fn value(sum: &AtomicU64) -> f64 {
+    sum.load(Ordering::Relaxed) as f64 / SPLAT_SCALE
+  }
+
+  // takes over the sums of other, for resuming from a checkpoint
+  pub fn copy_from(&self, other: &SplatBuffer) {
+    for (a, b) in self.sum.iter().zip(other.sum.iter()) {
+      a.store(b.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+    self.paths.store(other.paths(), Ordering::Relaxed);
+  }
+
+  pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    write_u32(writer, self.width as u32)?;
+    write_u32(writer, self.height as u32)?;
+    write_u64(writer, self.paths())?;
+    for sum in &self.sum {
+      write_u64(writer, sum.load(Ordering::Relaxed))?;
+    }
+    Ok(())
+  }
+
+  pub fn read_from<R: Read>(reader: &mut R, limit: u64) -> io::Result<Self> {
+    let width = read_u32(reader)? as usize;
+    let height = read_u32(reader)? as usize;
+    checked_size(&[width, height, 3], 8, limit)?;
+    let buffer = Self::new(width, height);
+    buffer.add_paths(read_u64(reader)?);
+    for sum in &buffer.sum {
+      sum.store(read_u64(reader)?, Ordering::Relaxed);
+    }
+    Ok(buffer)
+  }
+}
+
+
+
+#[cfg(test)]
+mod film_tests {
+  use super::*;
+
+  #[test]
+  fn test_box_is_a_pixel_average() {
+    let filter = Filter::Box(0.5);
+    let mut film = Film::new(2, 1);
+    film.add_sample(0.25, 0.5, Color::new(1.0, 1.0, 1.0), &filter);
+    film.add_sample(0.75, 0.5, Color::new(3.0, 3.0, 3.0), &filter);
+    film.add_sample(1.5, 0.5, Color::new(5.0, 5.0, 5.0), &filter);
+    let pixels = film.resolve(&filter);
+    assert_eq!(pixels[0].x, 2.0);
+    assert_eq!(pixels[1].x, 5.0);
+    assert_eq!(film.sample_counts(), vec![2, 1]);
+  }
+
+  #[test]
+  fn test_splat_reaches_neighbours() {
+    let filter = Filter::Tent(1.0);
+    let mut film = Film::new(3, 3);
+    film.add_sample(1.5, 1.5, Color::new(1.0, 1.0, 1.0), &filter);
+    film.add_sample(1.2, 1.5, Color::new(0.0, 0.0, 0.0), &filter);
+    let pixels = film.resolve(&filter);
+    // the left neighbour only sees the dark sample, the center pixel both
+    assert!(pixels[3].x == 0.0);
+    assert!(pixels[4].x > 0.0 && pixels[4].x < 1.0);
+    assert_eq!(pixels[0].x, 0.0);
+  }
+
+  #[test]
+  fn test_lanczos_at_one_sample_per_pixel_stays_in_range() {
+    let filter = Filter::Lanczos(3.0);
+    let mut rng = crate::rng::Rng::new(5);
+    let (width, height) = (64, 64);
+    let mut film = Film::new(width, height);
+    for row in 0..height {
+      for col in 0..width {
+        let (x, y) = (col as f64 + rng.uniform(), row as f64 + rng.uniform());
+        let c = rng.uniform();
+        film.add_sample(x, y, Color::new(c, c, c), &filter);
+      }
+    }
+    // ringing overshoots a little, but no pixel may blow up on a weight that cancelled out
+    for pixel in film.resolve(&filter) {
+      assert!(pixel.x.is_finite() && pixel.x.abs() < 2.0, "{:?}", pixel);
+    }
+  }
+
+  #[test]
+  fn test_merged_bands_match_one_film() {
+    let filter = Filter::Gaussian(1.5);
+    let samples = [(0.3, 0.7), (1.9, 1.1), (2.5, 2.9), (0.1, 3.6)];
+    let mut whole = Film::new(3, 4);
+    let mut top = Film::band_for_rows(3, 4, 0, 2, &filter);
+    let mut bottom = Film::band_for_rows(3, 4, 2, 4, &filter);
+    for (k, &(x, y)) in samples.iter().enumerate() {
+      let c = Color::new(k as f64, 1.0, 2.0);
+      whole.add_sample(x, y, c, &filter);
+      if y < 2.0 {top.add_sample(x, y, c, &filter)} else {bottom.add_sample(x, y, c, &filter)}
+    }
+    let mut merged = Film::new(3, 4);
+    merged.merge(&top);
+    merged.merge(&bottom);
+    for (a, b) in whole.resolve(&filter).iter().zip(merged.resolve(&filter)) {
+      assert!((a.x - b.x).abs() < 0.000001);
+    }
+  }
+
+  #[test]
+  fn test_splats_from_threads_add_up_exactly() {
+    let splats = SplatBuffer::new(2, 2);
+    let values = (0..400).map(|i| 0.1 + i as f64 * 0.37).collect::<Vec<f64>>();
+    std::thread::scope(|scope| {
+      for chunk in values.chunks(100) {
+        let splats = &splats;
+        scope.spawn(move || chunk.iter().for_each(|&v| splats.add(1.5, 0.2, Color::new(v, 0.0, 1.0))));
+      }
+    });
+    let serial = SplatBuffer::new(2, 2);
+    values.iter().rev().for_each(|&v| serial.add(1.5, 0.2, Color::new(v, 0.0, 1.0)));
+    let (a, b) = (splats.resolve(1.0), serial.resolve(1.0));
+    assert_eq!((a[1].x, a[1].z), (b[1].x, b[1].z));
+    assert!((a[1].x - values.iter().sum::<f64>()).abs() < 1e-6);
+    assert_eq!(a[1].z, 400.0);
+    assert_eq!(a[0].x + a[2].x + a[3].x, 0.0);
+  }
+}
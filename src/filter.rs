@@ -0,0 +1,139 @@
+use std::f64::consts::PI;
+
+// pixel reconstruction filters. they are separable, the weight of a sample at offset
+// (x, y) from a pixel center is evaluate(x) * evaluate(y). the radius is in pixels and
+// every sample contributes to all pixels whose center lies within it
+#[derive(Clone, Copy, Debug)]
+pub enum Filter {
+  Box(f64),
+  Tent(f64),
+  // the standard deviation is a third of the radius, the tail is cut off at the radius
+  Gaussian(f64),
+  // radius, B and C of the Mitchell-Netravali cubic
+  Mitchell(f64, f64, f64),
+  // windowed sinc with as many lobes as the radius
+  Lanczos(f64)
+}
+
+
+impl Filter {
+  pub fn from_name(name: &str, radius: Option<f64>) -> Result<Self, String> {
+    if let Some(r) = radius {
+      if r <= 0.0 {
+        return Err(format!("filter radius has to be positive, got {}", r));
+      }
+    }
+    match name {
+      "box"      => Ok(Filter::Box(radius.unwrap_or(0.5))),
+      "tent"     => Ok(Filter::Tent(radius.unwrap_or(1.0))),
+      "gaussian" => Ok(Filter::Gaussian(radius.unwrap_or(1.5))),
+      "mitchell" => Ok(Filter::Mitchell(radius.unwrap_or(2.0), 1.0 / 3.0, 1.0 / 3.0)),
+      "lanczos"  => Ok(Filter::Lanczos(radius.unwrap_or(3.0))),
+      _          => Err(format!("unknown filter {}", name))
+    }
+  }
+
+  pub fn radius(&self) -> f64 {
+    match *self {
+      Filter::Box(r) | Filter::Tent(r) | Filter::Gaussian(r) | Filter::Mitchell(r, _, _) | Filter::Lanczos(r) => r
+    }
+  }
+
+  pub fn evaluate(&self, x: f64) -> f64 {
+    let x = x.abs();
+    if x > self.radius() {
+      return 0.0;
+    }
+    match *self {
+      Filter::Box(_) => 1.0,
+      Filter::Tent(r) => r - x,
+      Filter::Gaussian(r) => {
+        let sigma = r / 3.0;
+        let gaussian = |d: f64| (-d * d / (2.0 * sigma * sigma)).exp();
+        (gaussian(x) - gaussian(r)).max(0.0)
+      },
+      Filter::Mitchell(r, b, c) => {
+        // the cubic is defined on [0, 2], stretched to the radius
+        let x = 2.0 * x / r;
+        if x < 1.0 {
+          ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b)) / 6.0
+        } else {
+          ((-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c)) / 6.0
+        }
+      },
+      Filter::Lanczos(r) => Self::sinc(x) * Self::sinc(x / r)
+    }
+  }
+
+  // area under evaluate, the weight of the 2d filter integrates to its square
+  pub fn integral(&self) -> f64 {
+    let steps = 256;
+    let dx = 2.0 * self.radius() / steps as f64;
+    (0..steps).map(|i| self.evaluate(-self.radius() + (i as f64 + 0.5) * dx)).sum::<f64>() * dx
+  }
+
+  // the smallest weight a pixel is divided by. with negative lobes the weights of a
+  // few samples can cancel out to almost nothing, those pixels are divided by half
+  // the weight one sample per pixel gives on average instead
+  pub fn min_weight(&self) -> f64 {
+    match self {
+      Filter::Mitchell(..) | Filter::Lanczos(_) => 0.5 * self.integral() * self.integral(),
+      _ => 0.0
+    }
+  }
+
+  fn sinc(x: f64) -> f64 {
+    if x < 1e-5 {
+      return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+  }
+}
+
+
+
+#[cfg(test)]
+mod filter_tests {
+  use super::*;
+
+  #[test]
+  fn test_support() {
+    for name in ["box", "tent", "gaussian", "mitchell", "lanczos"] {
+      let filter = Filter::from_name(name, None).unwrap();
+      assert!(filter.evaluate(0.0) > 0.0, "{}", name);
+      assert_eq!(filter.evaluate(filter.radius() + 0.01), 0.0, "{}", name);
+    }
+  }
+
+  #[test]
+  fn test_mitchell_is_continuous() {
+    let filter = Filter::Mitchell(2.0, 1.0 / 3.0, 1.0 / 3.0);
+    assert!((filter.evaluate(0.999999) - filter.evaluate(1.000001)).abs() < 0.0001);
+    assert!(filter.evaluate(1.999999).abs() < 0.0001);
+  }
+
+  #[test]
+  fn test_lanczos_zero_crossings() {
+    let filter = Filter::Lanczos(3.0);
+    assert!(filter.evaluate(1.0).abs() < 0.000001);
+    assert!(filter.evaluate(2.0).abs() < 0.000001);
+  }
+
+  #[test]
+  fn test_integral() {
+    assert!((Filter::Box(0.5).integral() - 1.0).abs() < 1e-9);
+    assert!((Filter::Tent(2.0).integral() - 4.0).abs() < 1e-3);
+    assert!((Filter::Mitchell(2.0, 1.0 / 3.0, 1.0 / 3.0).integral() - 1.0).abs() < 1e-3);
+  }
+
+  #[test]
+  fn test_invalid_radius() {
+    assert!(Filter::from_name("tent", Some(0.0)).is_err());
+    assert!(Filter::from_name("sinc", None).is_err());
+  }
+}
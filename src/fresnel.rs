@@ -0,0 +1,90 @@
+use crate::vector3::Color;
+
+// unpolarized fresnel reflectance of a dielectric interface, eta is the ratio of the
+// index on the transmitted side over the incident side, a negative cos_i means the
+// direction is on the inner side of the interface
+pub fn dielectric(cos_i: f64, eta: f64) -> f64 {
+  let mut cos_i = cos_i.clamp(-1.0, 1.0);
+  let mut eta = eta;
+  if cos_i < 0.0 {
+    eta = 1.0 / eta;
+    cos_i = -cos_i;
+  }
+  let sin2_i = 1.0 - cos_i * cos_i;
+  let sin2_t = sin2_i / (eta * eta);
+  if sin2_t >= 1.0 {
+    // total internal reflection
+    return 1.0;
+  }
+  let cos_t = (1.0 - sin2_t).sqrt();
+  let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+  let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+  (r_parl * r_parl + r_perp * r_perp) / 2.0
+}
+
+
+// fresnel reflectance of a conductor with complex index of refraction eta + i*k,
+// evaluated per channel in closed form, cos_i is measured against the surface normal
+pub fn conductor(cos_i: f64, eta: Color, k: Color) -> Color {
+  Color::new(
+    conductor_channel(cos_i, eta.x, k.x),
+    conductor_channel(cos_i, eta.y, k.y),
+    conductor_channel(cos_i, eta.z, k.z)
+  )
+}
+
+
+fn conductor_channel(cos_i: f64, eta: f64, k: f64) -> f64 {
+  let cos_i = cos_i.clamp(0.0, 1.0);
+  let cos2 = cos_i * cos_i;
+  let sin2 = 1.0 - cos2;
+  let eta2 = eta * eta;
+  let k2 = k * k;
+  let t0 = eta2 - k2 - sin2;
+  let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+  let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+  let t1 = a2_plus_b2 + cos2;
+  let t2 = 2.0 * a * cos_i;
+  let rs = (t1 - t2) / (t1 + t2);
+  let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+  let t4 = t2 * sin2;
+  let rp = rs * (t3 - t4) / (t3 + t4);
+  0.5 * (rs + rp)
+}
+
+
+
+#[cfg(test)]
+mod fresnel_tests {
+  use super::*;
+
+  #[test]
+  fn test_dielectric_normal_incidence() {
+    let r = dielectric(1.0, 1.5);
+    assert!((r - 0.04).abs() < 0.000001);
+    assert!((dielectric(-1.0, 1.5) - r).abs() < 0.000001);
+  }
+
+  #[test]
+  fn test_dielectric_total_internal_reflection() {
+    // leaving glass at 60 degrees is past the critical angle of about 41.8
+    assert_eq!(dielectric(-0.5, 1.5), 1.0);
+    assert!(dielectric(0.5, 1.5) < 1.0);
+  }
+
+  #[test]
+  fn test_conductor_normal_incidence() {
+    // at normal incidence reflectance is ((n - 1)^2 + k^2) / ((n + 1)^2 + k^2)
+    let (n, k) = (0.2, 3.9);
+    let expected = ((n - 1.0) * (n - 1.0) + k * k) / ((n + 1.0) * (n + 1.0) + k * k);
+    let r = conductor(1.0, Color::new(n, n, n), Color::new(k, k, k));
+    assert!((r.x - expected).abs() < 0.000001);
+  }
+
+  #[test]
+  fn test_conductor_grazing() {
+    let r = conductor(0.0, Color::new(0.2, 0.9, 1.1), Color::new(3.9, 2.4, 2.1));
+    assert!((r.x - 1.0).abs() < 0.000001);
+    assert!((r.z - 1.0).abs() < 0.000001);
+  }
+}
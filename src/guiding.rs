@@ -0,0 +1,575 @@
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::aov::{LightPaths, SurfaceInfo};
+use crate::hittable::{Hittable, HittableList};
+use crate::integrator::{evaluable, Integrator};
+use crate::interval::Interval;
+use crate::light::{self, Lights};
+use crate::material::{Lobe, Material};
+use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerType};
+use crate::vector3::{Color, Point, Vector3};
+
+pub const DEFAULT_TRAINING_PASSES: u32 = 8;
+// share of the directions still sampled from the material once the guide has learned
+// something, keeps the paths the guide doesn't know about possible
+const BSDF_FRACTION: f64 = 0.5;
+// a spatial leaf that recorded more paths than this in a pass is split in two
+const SPATIAL_THRESHOLD: u64 = 4000;
+const MAX_SPATIAL_DEPTH: u32 = 30;
+// a direction quadrant holding more than this share of the energy is split in four
+const ENERGY_THRESHOLD: f64 = 0.01;
+const MAX_DIRECTIONAL_DEPTH: u32 = 20;
+// recorded energy is summed in fixed point so threads can add to it without a lock
+// and the sums don't depend on the order they get there in. the scale leaves room for
+// hundreds of millions of records at MAX_RECORD, the sums saturate beyond that
+const ENERGY_SCALE: f64 = (1u64 << 16) as f64;
+const MAX_RECORD: f64 = 1e6;
+
+
+// equal area map of the unit sphere onto the unit square, cos theta along u and
+// phi along v
+fn to_square(direction: Vector3) -> (f64, f64) {
+  let d = Vector3::unit_vector(&direction);
+  let u = ((d.z + 1.0) / 2.0).clamp(0.0, 1.0);
+  let phi = d.y.atan2(d.x);
+  let v = if phi < 0.0 {phi / (2.0 * PI) + 1.0} else {phi / (2.0 * PI)};
+  (u, v.clamp(0.0, 1.0))
+}
+
+
+fn from_square(u: f64, v: f64) -> Vector3 {
+  let cos_theta = 2.0 * u - 1.0;
+  let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+  let phi = 2.0 * PI * v;
+  Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+
+// the quadrant of the unit square (u, v) is in, and (u, v) stretched over that quadrant
+fn quadrant(u: f64, v: f64) -> (usize, f64, f64) {
+  let (right, top) = (u >= 0.5, v >= 0.5);
+  let q = right as usize + 2 * top as usize;
+  (q, 2.0 * u - right as u8 as f64, 2.0 * v - top as u8 as f64)
+}
+
+
+struct DirectionNode {
+  // the node splitting each quadrant further, 0 where the quadrant isn't split
+  children: [usize; 4],
+  // energy recorded in each quadrant
+  sums: [AtomicU64; 4]
+}
+
+
+impl DirectionNode {
+  fn new() -> Self {
+    Self {children: [0; 4], sums: Default::default()}
+  }
+
+  fn sum(&self, q: usize) -> f64 {
+    self.sums[q].load(Ordering::Relaxed) as f64 / ENERGY_SCALE
+  }
+
+  fn total(&self) -> f64 {
+    (0..4).map(|q| self.sum(q)).sum()
+  }
+}
+
+
+// how the light arriving at a region is spread over directions, a quadtree over the
+// square the directions are mapped onto. it is split finer where more energy arrives
+struct DirectionTree {
+  nodes: Vec<DirectionNode>
+}
+
+
+impl DirectionTree {
+  fn new() -> Self {
+    Self {nodes: vec![DirectionNode::new()]}
+  }
+
+  fn total(&self) -> f64 {
+    self.nodes[0].total()
+  }
+
+  // the same splits with nothing recorded
+  fn structure(&self) -> Self {
+    let nodes = self.nodes.iter()
+      .map(|node| DirectionNode {children: node.children, sums: Default::default()})
+      .collect();
+    Self {nodes}
+  }
+
+  fn record(&self, direction: Vector3, energy: f64) {
+    let amount = (energy.min(MAX_RECORD) * ENERGY_SCALE).round() as u64;
+    let (mut u, mut v) = to_square(direction);
+    let mut node = 0;
+    loop {
+      let (q, next_u, next_v) = quadrant(u, v);
+      let _ = self.nodes[node].sums[q].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| Some(sum.saturating_add(amount)));
+      (u, v) = (next_u, next_v);
+      match self.nodes[node].children[q] {
+        0 => break,
+        child => node = child
+      }
+    }
+  }
+
+  // density over solid angle, 0 when nothing was recorded
+  fn pdf(&self, direction: Vector3) -> f64 {
+    let (mut u, mut v) = to_square(direction);
+    let mut node = 0;
+    let mut density = 1.0;
+    loop {
+      let total = self.nodes[node].total();
+      if total <= 0.0 {
+        return 0.0;
+      }
+      let (q, next_u, next_v) = quadrant(u, v);
+      density *= 4.0 * self.nodes[node].sum(q) / total;
+      (u, v) = (next_u, next_v);
+      match self.nodes[node].children[q] {
+        0 => break,
+        child => node = child
+      }
+    }
+    density / (4.0 * PI)
+  }
+
+  // picks a quadrant by its energy with u1 at every level, then a point of the last
+  // quadrant uniformly. only called when something was recorded
+  fn sample(&self, (mut u1, u2): (f64, f64)) -> Vector3 {
+    let (mut origin_u, mut origin_v, mut size) = (0.0, 0.0, 1.0);
+    let mut node = 0;
+    loop {
+      let total = self.nodes[node].total();
+      let mut q = 0;
+      let mut below = 0.0;
+      while q < 3 && below + self.nodes[node].sum(q) <= u1 * total {
+        below += self.nodes[node].sum(q);
+        q += 1;
+      }
+      let share = self.nodes[node].sum(q) / total;
+      u1 = if share > 0.0 {((u1 * total - below) / total / share).clamp(0.0, 1.0 - f64::EPSILON)} else {0.5};
+      size /= 2.0;
+      origin_u += size * (q % 2) as f64;
+      origin_v += size * (q / 2) as f64;
+      match self.nodes[node].children[q] {
+        0 => break,
+        child => node = child
+      }
+    }
+    from_square(origin_u + size * u1, origin_v + size * u2)
+  }
+
+  // splits for the next pass: quadrants that got more than ENERGY_THRESHOLD of the
+  // energy are split, the others are merged. nothing is recorded in the result
+  fn refined(&self) -> Self {
+    let total = self.total();
+    let mut nodes = vec![DirectionNode::new()];
+    // the node of this tree covering the same square if it is split that far, the new
+    // node, its depth and the energy recorded in it
+    let mut stack = vec![(Some(0), 0, 1, total)];
+    while let Some((old, new, depth, energy)) = stack.pop() {
+      for q in 0..4 {
+        let (share, old_child) = match old {
+          Some(old) => (self.nodes[old].sum(q), Some(self.nodes[old].children[q]).filter(|&c| c != 0)),
+          None => (energy / 4.0, None)
+        };
+        if total > 0.0 && share / total > ENERGY_THRESHOLD && depth < MAX_DIRECTIONAL_DEPTH {
+          let child = nodes.len();
+          nodes.push(DirectionNode::new());
+          nodes[new].children[q] = child;
+          stack.push((old_child, child, depth + 1, share));
+        }
+      }
+    }
+    Self {nodes}
+  }
+}
+
+
+enum SpatialNode {
+  // split in half across an axis, the lower half first
+  Inner {axis: usize, children: [usize; 2]},
+  Leaf(usize)
+}
+
+
+struct SpatialLeaf {
+  directions: DirectionTree,
+  // paths recorded here
+  samples: AtomicU64
+}
+
+
+// splits the box around the scene in halves, cycling through the axes, where more
+// paths went, and keeps a direction tree for every region it ends up with
+pub struct SdTree {
+  min: [f64; 3],
+  size: [f64; 3],
+  nodes: Vec<SpatialNode>,
+  leaves: Vec<SpatialLeaf>
+}
+
+
+impl SdTree {
+  // a cube around the scene so that the splits stay close to cubes too
+  pub fn new(world: &HittableList) -> Self {
+    let (min, max) = world.bounds().unwrap_or((Point::new(0.0, 0.0, 0.0), Point::new(1.0, 1.0, 1.0)));
+    let size = (max - min).x.max((max - min).y).max((max - min).z).max(1e-3) * 1.001;
+    let center = 0.5 * (min + max);
+    Self {
+      min: [center.x - size / 2.0, center.y - size / 2.0, center.z - size / 2.0],
+      size: [size; 3],
+      nodes: vec![SpatialNode::Leaf(0)],
+      leaves: vec![SpatialLeaf {directions: DirectionTree::new(), samples: AtomicU64::new(0)}]
+    }
+  }
+
+  // points outside the box go to the leaf closest to them
+  fn leaf(&self, p: Point) -> &SpatialLeaf {
+    let p = [p.x, p.y, p.z];
+    let (mut min, mut size) = (self.min, self.size);
+    let mut node = 0;
+    loop {
+      match self.nodes[node] {
+        SpatialNode::Inner {axis, children} => {
+          size[axis] /= 2.0;
+          if p[axis] < min[axis] + size[axis] {
+            node = children[0];
+          } else {
+            min[axis] += size[axis];
+            node = children[1];
+          }
+        },
+        SpatialNode::Leaf(leaf) => return &self.leaves[leaf]
+      }
+    }
+  }
+
+  fn record(&self, p: Point, direction: Vector3, energy: f64) {
+    let leaf = self.leaf(p);
+    leaf.samples.fetch_add(1, Ordering::Relaxed);
+    if energy > 0.0 {
+      leaf.directions.record(direction, energy);
+    }
+  }
+
+  // the tree for the next pass to record into: leaves that got many paths are split
+  // and every direction tree is refined by what it recorded
+  pub fn refined(&self) -> Self {
+    let mut tree = Self {min: self.min, size: self.size, nodes: Vec::new(), leaves: Vec::new()};
+    self.copy_refined(0, 0, &mut tree);
+    tree
+  }
+
+  fn copy_refined(&self, node: usize, depth: u32, tree: &mut Self) -> usize {
+    match self.nodes[node] {
+      SpatialNode::Inner {axis, children} => {
+        let index = tree.nodes.len();
+        tree.nodes.push(SpatialNode::Leaf(0));
+        let low = self.copy_refined(children[0], depth + 1, tree);
+        let high = self.copy_refined(children[1], depth + 1, tree);
+        tree.nodes[index] = SpatialNode::Inner {axis, children: [low, high]};
+        index
+      },
+      SpatialNode::Leaf(leaf) => {
+        let leaf = &self.leaves[leaf];
+        tree.split(&leaf.directions.refined(), leaf.samples.load(Ordering::Relaxed), depth)
+      }
+    }
+  }
+
+  // halves get half the samples, so a leaf is split until the halves would be under
+  // the threshold
+  fn split(&mut self, directions: &DirectionTree, samples: u64, depth: u32) -> usize {
+    let index = self.nodes.len();
+    if samples > SPATIAL_THRESHOLD && depth < MAX_SPATIAL_DEPTH {
+      self.nodes.push(SpatialNode::Leaf(0));
+      let low = self.split(directions, samples / 2, depth + 1);
+      let high = self.split(directions, samples / 2, depth + 1);
+      self.nodes[index] = SpatialNode::Inner {axis: depth as usize % 3, children: [low, high]};
+    } else {
+      self.leaves.push(SpatialLeaf {directions: directions.structure(), samples: AtomicU64::new(0)});
+      self.nodes.push(SpatialNode::Leaf(self.leaves.len() - 1));
+    }
+    index
+  }
+}
+
+
+// a vertex a path was guided from, the light that comes back along the path is
+// recorded in its direction when the path is done
+struct GuidedVertex {
+  p: Point,
+  direction: Vector3,
+  // throughput of the path including the scatter at this vertex
+  throughput: Color,
+  pdf: f64,
+  radiance: Color
+}
+
+
+// next event estimation that learns where the light comes from (practical path
+// guiding). the first training_passes passes record the light arriving at every
+// vertex into an sd-tree, each pass after the first scatters by a mix of the
+// material and what the pass before it recorded
+pub struct GuidedPathTracer {
+  pub max_depth: u32,
+  lights: Lights,
+  pub training_passes: u32,
+  // passes started so far, counted here so a resumed render trains again
+  passes: u32,
+  // what the last training pass recorded, directions are guided by it
+  sampling: Option<SdTree>,
+  // what this pass records into, None once the training is done
+  recording: Option<SdTree>
+}
+
+
+impl GuidedPathTracer {
+  // without training passes nothing is recorded and it stays plain nee
+  pub fn new(max_depth: u32, world: &HittableList, training_passes: u32) -> Self {
+    Self {
+      max_depth,
+      lights: Lights::new(world),
+      training_passes,
+      passes: 0,
+      sampling: None,
+      recording: (training_passes > 0).then(|| SdTree::new(world))
+    }
+  }
+
+  pub fn start_pass(&mut self) {
+    if self.passes > 0 {
+      if let Some(recorded) = self.recording.take() {
+        let next = recorded.refined();
+        self.sampling = Some(recorded);
+        if self.passes < self.training_passes {
+          self.recording = Some(next);
+        }
+      }
+    }
+    self.passes += 1;
+  }
+}
+
+
+
+
+
+
+fn divide(a: Color, b: Color) -> Color {
+  let div = |a: f64, b: f64| if b > 0.0 {a / b} else {0.0};
+  Color::new(div(a.x, b.x), div(a.y, b.y), div(a.z, b.z))
+}
+
+
+impl Integrator for GuidedPathTracer {
+  fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut SamplerType, surface: Option<&mut Option<SurfaceInfo>>) -> LightPaths<Color> {
+    let mut paths = LightPaths::new(Color::new(0.0, 0.0, 0.0));
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = *ray;
+    let mut first_lobe = None;
+    let mut surface = surface;
+    let mut light_sampled_at: Option<(Point, f64)> = None;
+    let mut vertices: Vec<GuidedVertex> = Vec::new();
+    // light found along the path, also handed to every guided vertex before it
+    let add = |paths: &mut LightPaths<Color>, vertices: &mut Vec<GuidedVertex>, c: Color, bounces: u32, lobe: Option<Lobe>| {
+      paths.add(c, bounces, lobe);
+      for vertex in vertices.iter_mut() {
+        vertex.radiance += divide(c, vertex.throughput);
+      }
+    };
+    for bounces in 0..self.max_depth {
+      let Some(rec) = world.hit(&ray, Interval::new(0.001, f64::INFINITY), sampler.rng()) else {
+        let weight = light_sampled_at.map_or(1.0, |(_, pdf)| light::power_heuristic(pdf, self.lights.sky_pdf()));
+        add(&mut paths, &mut vertices, throughput * weight * light::sky(&ray), bounces, first_lobe);
+        break;
+      };
+      if let Some(surface) = surface.take() {
+        *surface = Some(SurfaceInfo::new(&ray, &rec));
+      }
+      let weight = light_sampled_at.map_or(1.0, |(origin, pdf)| {
+        light::power_heuristic(pdf, self.lights.object_pdf(world, rec.object_id, origin, ray.direction))
+      });
+      add(&mut paths, &mut vertices, throughput * weight * rec.material.emitted(&rec), bounces, first_lobe);
+      // the light sample and the guide take their dimensions first so they stay put
+      // whatever scatter draws
+      let (u_light, u) = (sampler.get_1d(), sampler.get_2d());
+      let (u_guide, u_direction) = (sampler.get_1d(), sampler.get_2d());
+      let scatter = rec.material.scatter(&ray, &rec, sampler);
+      let lit = bounces + 1 < self.max_depth && evaluable(&ray, &rec);
+      // phase functions are left to themselves, the tree only learns about surfaces
+      let guided = lit && !rec.material.is_phase_function();
+      let guide = self.sampling.as_ref()
+        .filter(|_| guided)
+        .map(|tree| &tree.leaf(rec.p).directions)
+        .filter(|directions| directions.total() > 0.0);
+      // density of a direction under the mix, from the material's density of it
+      let mixed_pdf = |direction: Vector3, bsdf_pdf: f64| match guide {
+        Some(guide) => BSDF_FRACTION * bsdf_pdf + (1.0 - BSDF_FRACTION) * guide.pdf(direction),
+        None => bsdf_pdf
+      };
+      let lobe = scatter.as_ref().map_or(Lobe::Diffuse, |s| s.lobe);
+      if lit {
+        if let Some(sample) = self.lights.sample(world, rec.p, u_light, u) {
+          let eval = rec.material.eval(&ray, &rec, sample.direction).expect("evaluable");
+          if !eval.f.near_zero() {
+            let incoming = self.lights.incoming(world, rec.p, &sample, sampler.rng());
+            let weight = light::power_heuristic(sample.pdf, mixed_pdf(sample.direction, eval.pdf)) / sample.pdf;
+            add(&mut paths, &mut vertices, throughput * eval.f * incoming * weight, bounces + 1, first_lobe.or(Some(lobe)));
+          }
+        }
+      }
+      // the direction to go on in, what the throughput is multiplied by and the
+      // density of the direction, 0 when it has none (a mirror-like part of the material)
+      let (next, factor, pdf) = match (guide, scatter) {
+        (Some(guide), _) if u_guide >= BSDF_FRACTION => {
+          let direction = guide.sample(u_direction);
+          let Some(eval) = rec.material.eval(&ray, &rec, direction) else {
+            break;
+          };
+          let pdf = mixed_pdf(direction, eval.pdf);
+          if eval.f.near_zero() || pdf <= 0.0 {
+            break;
+          }
+          (Ray::new(rec.p, direction), eval.f / pdf, pdf)
+        },
+        (Some(_), Some(scatter)) => match rec.material.eval(&ray, &rec, scatter.ray.direction) {
+          Some(eval) if eval.pdf > 0.0 => {
+            let pdf = mixed_pdf(scatter.ray.direction, eval.pdf);
+            (scatter.ray, eval.f / pdf, pdf)
+          },
+          _ => (scatter.ray, scatter.attenuation / BSDF_FRACTION, 0.0)
+        },
+        (None, Some(scatter)) => {
+          let pdf = if lit {rec.material.eval(&ray, &rec, scatter.ray.direction).map_or(0.0, |eval| eval.pdf)} else {0.0};
+          (scatter.ray, scatter.attenuation, pdf)
+        },
+        (_, None) => break
+      };
+      light_sampled_at = if lit {Some((rec.p, pdf))} else {None};
+      throughput *= factor;
+      first_lobe = first_lobe.or(Some(lobe));
+      if self.recording.is_some() && guided && pdf > 0.0 {
+        vertices.push(GuidedVertex {p: rec.p, direction: next.direction, throughput, pdf, radiance: Color::new(0.0, 0.0, 0.0)});
+      }
+      ray = next;
+    }
+    if let Some(tree) = &self.recording {
+      for vertex in vertices {
+        tree.record(vertex.p, vertex.direction, vertex.radiance.luminance() / vertex.pdf);
+      }
+    }
+    paths
+  }
+}
+
+
+#[cfg(test)]
+mod guiding_tests {
+  use super::*;
+  use crate::hittable::HittableObject;
+  use crate::integrator::{IntegratorType, NeePathTracer};
+  use crate::material::{DiffuseLight, Lambertian, MaterialType};
+  use crate::quad::Quad;
+  use crate::rng::Rng;
+  use crate::test_scenes;
+
+  #[test]
+  fn test_energy_sums_dont_wrap() {
+    let tree = DirectionTree::new();
+    let direction = Vector3::new(0.0, 0.0, 1.0);
+    for _ in 0..100_000 {
+      tree.record(direction, 2.0 * MAX_RECORD);
+    }
+    assert!((tree.total() / (1e5 * MAX_RECORD) - 1.0).abs() < 1e-9, "{}", tree.total());
+    // full sums stay full
+    let (u, v) = to_square(direction);
+    let (q, _, _) = quadrant(u, v);
+    tree.nodes[0].sums[q].store(u64::MAX - 1, Ordering::Relaxed);
+    tree.record(direction, 1.0);
+    assert_eq!(tree.nodes[0].sums[q].load(Ordering::Relaxed), u64::MAX);
+  }
+
+  #[test]
+  fn test_directions_sampled_have_the_density_pdf_gives() {
+    let mut tree = DirectionTree::new();
+    let mut rng = Rng::new(5);
+    let hot = Vector3::unit_vector(&Vector3::new(0.3, 0.8, -0.2));
+    for _ in 0..6 {
+      for _ in 0..20_000 {
+        let direction = if rng.uniform() < 0.8 {hot} else {Vector3::sample_unit_vector((rng.uniform(), rng.uniform()))};
+        tree.record(direction, 1.0);
+      }
+      let refined = tree.refined();
+      if refined.nodes.len() == tree.nodes.len() {
+        break;
+      }
+      tree = refined;
+    }
+    for _ in 0..20_000 {
+      let direction = if rng.uniform() < 0.8 {hot} else {Vector3::sample_unit_vector((rng.uniform(), rng.uniform()))};
+      tree.record(direction, 1.0);
+    }
+    // the mean of 1 / pdf over sampled directions is the area of the sphere, and most
+    // of them land close to where the energy came from
+    let n = 100_000;
+    let (mut inverse, mut close) = (0.0, 0);
+    for _ in 0..n {
+      let direction = tree.sample((rng.uniform(), rng.uniform()));
+      inverse += 1.0 / tree.pdf(direction);
+      if Vector3::dot(direction, hot) > 0.99 {
+        close += 1;
+      }
+    }
+    let area = inverse / n as f64;
+    assert!((area / (4.0 * PI) - 1.0).abs() < 0.02, "{}", area);
+    assert!(close as f64 / n as f64 > 0.6, "{}", close);
+  }
+
+  // a lamp facing the ceiling, the floor only sees it through the ceiling
+  fn lamp_under_ceiling() -> HittableList {
+    let mut world = HittableList::new();
+    let gray = || MaterialType::Lambertian(Lambertian::new(Color::new(0.7, 0.7, 0.7)));
+    let lamp = MaterialType::DiffuseLight(DiffuseLight::new(Color::new(20.0, 20.0, 20.0)));
+    world.list.push(HittableObject::Quad(Quad::new(Point::new(-3.0, 0.0, 3.0), Vector3::new(6.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -6.0), gray())));
+    world.list.push(HittableObject::Quad(Quad::new(Point::new(-3.0, 2.0, -3.0), Vector3::new(6.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 6.0), gray())));
+    world.list.push(HittableObject::Quad(Quad::new(Point::new(-3.0, 0.0, -3.0), Vector3::new(0.0, 2.0, 0.0), Vector3::new(0.0, 0.0, 6.0), gray())));
+    world.list.push(HittableObject::Quad(Quad::new(Point::new(3.0, 0.0, -3.0), Vector3::new(0.0, 0.0, 6.0), Vector3::new(0.0, 2.0, 0.0), gray())));
+    world.list.push(HittableObject::Quad(Quad::new(Point::new(-3.0, 0.0, -3.0), Vector3::new(6.0, 0.0, 0.0), Vector3::new(0.0, 2.0, 0.0), gray())));
+    world.list.push(HittableObject::Quad(Quad::new(Point::new(-3.0, 0.0, 3.0), Vector3::new(0.0, 2.0, 0.0), Vector3::new(6.0, 0.0, 0.0), gray())));
+    world.list.push(HittableObject::Quad(Quad::new(Point::new(-0.25, 1.5, -0.25), Vector3::new(0.0, 0.0, 0.5), Vector3::new(0.5, 0.0, 0.0), lamp)));
+    world
+  }
+
+  #[test]
+  fn test_no_training_passes_records_nothing() {
+    let world = lamp_under_ceiling();
+    let mut guided = GuidedPathTracer::new(4, &world, 0);
+    let sampler = SamplerType::from_name("independent", 1, 3).unwrap();
+    for _ in 0..3 {
+      guided.start_pass();
+      let mut sampler = sampler.clone();
+      sampler.start_pixel_sample(0, 0, 0);
+      guided.li(&Ray::new(Point::new(-1.0, 1.0, 1.0), Vector3::new(1.0, -1.0, -1.0)), &world, &mut sampler, None);
+      assert!(guided.recording.is_none() && guided.sampling.is_none());
+    }
+  }
+
+  #[test]
+  fn test_agrees_with_next_event_estimation_with_less_noise() {
+    let world = lamp_under_ceiling();
+    let mut nee = IntegratorType::Nee(NeePathTracer {max_depth: 4, lights: Lights::new(&world)});
+    let mut guided = IntegratorType::Guided(Box::new(GuidedPathTracer::new(4, &world, DEFAULT_TRAINING_PASSES)));
+    let eye = Point::new(-1.0, 1.0, 1.0);
+    let (expected, nee_variance) = test_scenes::floor_patch(&mut nee, &world, eye, 2.0, 20, 20_000);
+    let (mean, variance) = test_scenes::floor_patch(&mut guided, &world, eye, 2.0, 20, 20_000);
+    assert!((mean / expected - 1.0).abs() < 0.02, "{} {}", mean, expected);
+    assert!(variance < nee_variance, "{} {}", variance, nee_variance);
+  }
+}
@@ -0,0 +1,297 @@
+use std::{borrow::Cow, fs, io, path::Path};
+
+use crate::constant_medium::boundary_crossings;
+use crate::material::{HenyeyGreenstein, MaterialType};
+use crate::perlin::Perlin;
+use crate::rng::Rng;
+use crate::vector3::{Point, Vector3};
+use crate::hittable::{Hittable, HitRecord, HittableObject};
+use crate::ray::Ray;
+use crate::interval::Interval;
+
+// density on a regular grid of voxels spanning the box [min, max]
+pub struct DensityGrid {
+  pub nx: usize,
+  pub ny: usize,
+  pub nz: usize,
+  pub data: Vec<f32>,
+  pub min: Point,
+  pub max: Point
+}
+
+
+impl DensityGrid {
+  pub fn new(nx: usize, ny: usize, nz: usize, data: Vec<f32>, min: Point, max: Point) -> io::Result<Self> {
+    let count = Self::voxel_count(nx, ny, nz)?;
+    // also false for nan, which would make every lookup nan
+    if !(min.x < max.x && min.y < max.y && min.z < max.z) {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "the grid bounds are empty on some axis"));
+    }
+    if data.len() != count {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("expected {} values for a {}x{}x{} grid, found {}", count, nx, ny, nz, data.len())
+      ));
+    }
+    Ok(Self {nx, ny, nz, data, min, max})
+  }
+
+  fn voxel_count(nx: usize, ny: usize, nz: usize) -> io::Result<usize> {
+    if nx == 0 || ny == 0 || nz == 0 {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, format!("a {}x{}x{} grid has no voxels", nx, ny, nz)));
+    }
+    nx.checked_mul(ny)
+      .and_then(|n| n.checked_mul(nz))
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("a {}x{}x{} grid is too large", nx, ny, nz)))
+  }
+
+  // raw file is nx * ny * nz little-endian f32 values, x varies fastest
+  pub fn load_raw<P: AsRef<Path>>(path: P, nx: usize, ny: usize, nz: usize, min: Point, max: Point) -> io::Result<Self> {
+    let expected = Self::voxel_count(nx, ny, nz)?
+      .checked_mul(4)
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("a {}x{}x{} grid is too large", nx, ny, nz)))?;
+    let bytes = fs::read(path)?;
+    if bytes.len() != expected {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("expected {} bytes for a {}x{}x{} grid, found {}", expected, nx, ny, nz, bytes.len())
+      ));
+    }
+    let data = bytes.chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect();
+    Self::new(nx, ny, nz, data, min, max)
+  }
+
+  pub fn max_value(&self) -> f64 {
+    self.data.iter().fold(0.0f32, |m, &d| m.max(d)) as f64
+  }
+
+  fn voxel(&self, x: usize, y: usize, z: usize) -> f64 {
+    self.data[x + self.nx * (y + self.ny * z)] as f64
+  }
+
+  // trilinear interpolation between voxel centers, zero outside the grid
+  pub fn lookup(&self, p: Point) -> f64 {
+    let extent = self.max - self.min;
+    let local = p - self.min;
+    let gx = local.x / extent.x * self.nx as f64 - 0.5;
+    let gy = local.y / extent.y * self.ny as f64 - 0.5;
+    let gz = local.z / extent.z * self.nz as f64 - 0.5;
+    if gx < -0.5 || gy < -0.5 || gz < -0.5
+      || gx > self.nx as f64 - 0.5 || gy > self.ny as f64 - 0.5 || gz > self.nz as f64 - 0.5 {
+      return 0.0;
+    }
+    let clamp_axis = |g: f64, n: usize| -> (usize, usize, f64) {
+      let g = g.clamp(0.0, (n - 1) as f64);
+      let i0 = g.floor() as usize;
+      let i1 = (i0 + 1).min(n - 1);
+      (i0, i1, g - i0 as f64)
+    };
+    let (x0, x1, fx) = clamp_axis(gx, self.nx);
+    let (y0, y1, fy) = clamp_axis(gy, self.ny);
+    let (z0, z1, fz) = clamp_axis(gz, self.nz);
+    let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+    let c00 = lerp(self.voxel(x0, y0, z0), self.voxel(x1, y0, z0), fx);
+    let c10 = lerp(self.voxel(x0, y1, z0), self.voxel(x1, y1, z0), fx);
+    let c01 = lerp(self.voxel(x0, y0, z1), self.voxel(x1, y0, z1), fx);
+    let c11 = lerp(self.voxel(x0, y1, z1), self.voxel(x1, y1, z1), fx);
+    lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+  }
+}
+
+
+
+// turbulent perlin noise, density in [0, max_density]
+pub struct NoiseDensity {
+  pub noise: Perlin,
+  pub scale: f64,
+  pub max_density: f64
+}
+
+
+impl NoiseDensity {
+  pub fn new(scale: f64, max_density: f64, rng: &mut Rng) -> Self {
+    Self {
+      noise: Perlin::new(rng),
+      scale,
+      max_density
+    }
+  }
+
+  pub fn lookup(&self, p: Point) -> f64 {
+    self.max_density * self.noise.turb(self.scale * p, 7).min(1.0)
+  }
+}
+
+
+
+pub enum Density {
+  Grid(DensityGrid, f64),
+  Noise(NoiseDensity)
+}
+
+
+impl Density {
+  pub fn at(&self, p: Point) -> f64 {
+    match self {
+      Density::Grid(g, multiplier) => multiplier * g.lookup(p),
+      Density::Noise(n)            => n.lookup(p)
+    }
+  }
+
+  // upper bound on the density anywhere in the medium
+  pub fn majorant(&self) -> f64 {
+    match self {
+      Density::Grid(g, multiplier) => multiplier * g.max_value(),
+      Density::Noise(n)            => n.max_density
+    }
+  }
+}
+
+
+
+pub struct HeterogeneousMedium {
+  pub boundary: Box<HittableObject>,
+  pub density: Density,
+  pub majorant: f64,
+  pub phase_function: HenyeyGreenstein
+}
+
+
+impl HeterogeneousMedium {
+  pub fn new(boundary: HittableObject, density: Density, phase_function: HenyeyGreenstein) -> Self {
+    Self {
+      boundary: Box::new(boundary),
+      majorant: density.majorant(),
+      density,
+      phase_function
+    }
+  }
+}
+
+
+impl Hittable for HeterogeneousMedium {
+  fn hit(&self, ray: &Ray, ray_t: Interval, rng: &mut Rng) -> Option<HitRecord<'_>> {
+    if self.majorant <= 0.0 {
+      return None;
+    }
+    let (rec1, rec2) = boundary_crossings(&self.boundary, ray, rng)?;
+    let t_enter = rec1.t.max(ray_t.min).max(0.0);
+    let t_exit = rec2.t.min(ray_t.max);
+    if t_enter >= t_exit {
+      return None;
+    }
+    // delta tracking: sample tentative collisions against the majorant and accept
+    // each one with probability density / majorant, the rejected ones are null collisions
+    let ray_length = ray.direction.length();
+    let mut t = t_enter;
+    loop {
+      t -= (1.0 - rng.uniform()).ln() / (self.majorant * ray_length);
+      if t >= t_exit {
+        return None;
+      }
+      let density = self.density.at(ray.at(t));
+      if rng.uniform() * self.majorant < density {
+        // real collisions already happen in proportion to the density, so every one of
+        // them glows the same and dense regions of a fire come out brightest
+        let material = MaterialType::HenyeyGreenstein(self.phase_function);
+        return Some(HitRecord::new(ray, t, Vector3::new(1.0, 0.0, 0.0), Cow::Owned(material)));
+      }
+    }
+  }
+}
+
+
+
+#[cfg(test)]
+mod heterogeneous_medium_tests {
+  use super::*;
+  use crate::material::{Isotropic, Material};
+  use crate::sphere::Sphere;
+  use crate::vector3::Color;
+
+  #[test]
+  fn test_load_raw() {
+    let path = std::env::temp_dir().join(format!("ray_test_load_raw_{}.raw", std::process::id()));
+    let values: Vec<f32> = (0..8).map(|i| i as f32).collect();
+    let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    fs::write(&path, bytes).unwrap();
+    let grid = DensityGrid::load_raw(&path, 2, 2, 2, Point::new(0.0, 0.0, 0.0), Point::new(1.0, 1.0, 1.0)).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(grid.data, values);
+    assert_eq!(grid.max_value(), 7.0);
+  }
+
+  #[test]
+  fn test_load_raw_wrong_size() {
+    let path = std::env::temp_dir().join(format!("ray_test_load_raw_wrong_size_{}.raw", std::process::id()));
+    fs::write(&path, [0u8; 12]).unwrap();
+    let grid = DensityGrid::load_raw(&path, 2, 2, 2, Point::new(0.0, 0.0, 0.0), Point::new(1.0, 1.0, 1.0));
+    fs::remove_file(&path).unwrap();
+    assert_eq!(grid.err().unwrap().kind(), io::ErrorKind::InvalidData);
+  }
+
+  #[test]
+  fn test_lookup() {
+    let data = vec![0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0];
+    let grid = DensityGrid::new(2, 2, 2, data, Point::new(0.0, 0.0, 0.0), Point::new(2.0, 2.0, 2.0)).unwrap();
+    // voxel centers sit at 0.5 and 1.5
+    assert!((grid.lookup(Point::new(0.5, 0.5, 0.5)) - 0.0).abs() < 0.000001);
+    assert!((grid.lookup(Point::new(1.5, 0.5, 0.5)) - 1.0).abs() < 0.000001);
+    assert!((grid.lookup(Point::new(1.0, 1.0, 1.0)) - 0.5).abs() < 0.000001);
+    assert_eq!(grid.lookup(Point::new(3.0, 1.0, 1.0)), 0.0);
+  }
+
+  #[test]
+  fn test_new_rejects_mismatched_data() {
+    let grid = DensityGrid::new(2, 2, 2, vec![0.0; 7], Point::new(0.0, 0.0, 0.0), Point::new(1.0, 1.0, 1.0));
+    assert_eq!(grid.err().unwrap().kind(), io::ErrorKind::InvalidData);
+  }
+
+  #[test]
+  fn test_new_rejects_empty_grids() {
+    let (min, max) = (Point::new(0.0, 0.0, 0.0), Point::new(1.0, 1.0, 1.0));
+    assert!(DensityGrid::new(0, 4, 4, Vec::new(), min, max).is_err());
+    assert!(DensityGrid::new(2, 2, 2, vec![0.0; 8], min, Point::new(1.0, 0.0, 1.0)).is_err());
+    assert!(DensityGrid::new(2, 2, 2, vec![0.0; 8], min, Point::new(1.0, f64::NAN, 1.0)).is_err());
+    assert!(DensityGrid::new(usize::MAX, 2, 1, Vec::new(), min, max).is_err());
+  }
+
+  #[test]
+  fn test_load_raw_rejects_overflowing_sizes() {
+    let path = std::env::temp_dir().join(format!("ray_test_load_raw_overflow_{}.raw", std::process::id()));
+    fs::write(&path, [0u8; 4]).unwrap();
+    let (min, max) = (Point::new(0.0, 0.0, 0.0), Point::new(1.0, 1.0, 1.0));
+    let grid = DensityGrid::load_raw(&path, usize::MAX / 2, 1, 1, min, max);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(grid.err().unwrap().kind(), io::ErrorKind::InvalidData);
+  }
+
+  // mean light picked up at the first collision of rays through the middle of the ball
+  fn mean_emission(medium: &HeterogeneousMedium) -> f64 {
+    let mut rng = Rng::new(9);
+    let ray = Ray::new(Point::new(0.0, 0.0, -2.0), Vector3::new(0.0, 0.0, 1.0));
+    let n = 200_000;
+    let sum: f64 = (0..n).map(|_| {
+      medium.hit(&ray, Interval::new(0.0, f64::INFINITY), &mut rng).map_or(0.0, |rec| rec.material.emitted(&rec).x)
+    }).sum();
+    sum / n as f64
+  }
+
+  #[test]
+  fn test_emission_does_not_depend_on_the_majorant() {
+    let glowing = || {
+      let grid = DensityGrid::new(2, 2, 2, vec![1.0; 8], Point::new(-1.0, -1.0, -1.0), Point::new(1.0, 1.0, 1.0)).unwrap();
+      let boundary = HittableObject::Sphere(Sphere::new(Point::new(0.0, 0.0, 0.0), 1.0, MaterialType::Isotropic(Isotropic::new(Color::new(1.0, 1.0, 1.0)))));
+      HeterogeneousMedium::new(boundary, Density::Grid(grid, 0.5), HenyeyGreenstein::emissive(Color::new(0.5, 0.5, 0.5), 0.0, Color::new(2.0, 2.0, 2.0)))
+    };
+    let tight = glowing();
+    let mut loose = glowing();
+    loose.majorant *= 4.0;
+    let (a, b) = (mean_emission(&tight), mean_emission(&loose));
+    // a collision happens with probability 1 - e^-1 over the diameter
+    assert!((a / (2.0 * (1.0 - (-1.0f64).exp())) - 1.0).abs() < 0.01, "{}", a);
+    assert!((a / b - 1.0).abs() < 0.01, "{} {}", a, b);
+  }
+}
@@ -1,38 +1,172 @@
-use crate::{material::{Material, MaterialType}, ray::Ray, vector3::{Point, Vector3}};
+use std::borrow::Cow;
+
+use crate::{material::MaterialType, onb::Onb, ray::Ray, vector3::{Point, Vector3}};
 use crate::sphere::Sphere;
+use crate::constant_medium::ConstantMedium;
+use crate::heterogeneous_medium::HeterogeneousMedium;
+use crate::quad::Quad;
+use crate::alpha_mask::AlphaMasked;
+use crate::subsurface::SubsurfaceObject;
 use crate::interval::Interval;
+use crate::rng::Rng;
 
 pub enum HittableObject {
-  Sphere(Sphere)
+  Sphere(Sphere),
+  ConstantMedium(ConstantMedium),
+  HeterogeneousMedium(HeterogeneousMedium),
+  Quad(Quad),
+  AlphaMasked(AlphaMasked),
+  Subsurface(SubsurfaceObject)
+}
+
+impl HittableObject {
+  // a direction from origin toward the object and its solid angle density, for
+  // sampling it as a light. only spheres and quads can be sampled
+  pub fn sample_direction(&self, origin: Point, u: (f64, f64)) -> Option<(Vector3, f64)> {
+    match self {
+      HittableObject::Sphere(s) => s.sample_direction(origin, u),
+      HittableObject::Quad(q)   => q.sample_direction(origin, u),
+      _ => None
+    }
+  }
+
+  // density of sample_direction for a direction from origin that hits the object
+  pub fn direction_pdf(&self, origin: Point, direction: Vector3) -> f64 {
+    match self {
+      HittableObject::Sphere(s) => s.pdf(origin),
+      HittableObject::Quad(q)   => q.pdf(origin, direction),
+      _ => 0.0
+    }
+  }
+
+  // a point picked uniformly over the surface of the object with the normal pointing
+  // out there, for starting light paths on it. only spheres and quads can be sampled
+  pub fn sample_point(&self, u: (f64, f64)) -> Option<(Point, Vector3)> {
+    match self {
+      HittableObject::Sphere(s) => Some(s.sample_point(u)),
+      HittableObject::Quad(q)   => Some(q.sample_point(u)),
+      _ => None
+    }
+  }
+
+  pub fn area(&self) -> f64 {
+    match self {
+      HittableObject::Sphere(s) => s.area(),
+      HittableObject::Quad(q)   => q.area(),
+      _ => 0.0
+    }
+  }
+
+  // the corners of a box around the object, smallest coordinates first
+  pub fn bounds(&self) -> (Point, Point) {
+    match self {
+      HittableObject::Sphere(s) => {
+        let r = s.radius.abs() * Vector3::new(1.0, 1.0, 1.0);
+        (s.center - r, s.center + r)
+      },
+      HittableObject::Quad(q) => [q.q + q.u, q.q + q.v, q.q + q.u + q.v].into_iter()
+        .fold((q.q, q.q), |(min, max), p| (Vector3::min(min, p), Vector3::max(max, p))),
+      HittableObject::ConstantMedium(c)      => c.boundary.bounds(),
+      HittableObject::HeterogeneousMedium(h) => h.boundary.bounds(),
+      HittableObject::AlphaMasked(a)         => a.object.bounds(),
+      HittableObject::Subsurface(s)          => s.boundary.bounds()
+    }
+  }
+
+  pub fn material(&self) -> Option<&MaterialType> {
+    match self {
+      HittableObject::Sphere(s) => Some(&s.mat),
+      HittableObject::Quad(q)   => Some(&q.mat),
+      _ => None
+    }
+  }
+
+  // how close the hit is to an edge of the object, in the coordinates of its shape.
+  // None for objects without edges
+  pub fn edge_distance(&self, rec: &HitRecord) -> Option<f64> {
+    match self {
+      HittableObject::Quad(q)        => Some(q.edge_distance(rec.u, rec.v)),
+      HittableObject::AlphaMasked(a) => a.object.edge_distance(rec),
+      _ => None
+    }
+  }
 }
 
 impl Hittable for HittableObject {
-  fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+  fn hit(&self, ray: &Ray, ray_t: Interval, rng: &mut Rng) -> Option<HitRecord<'_>> {
     match self {
-      HittableObject::Sphere(s) => s.hit(ray, ray_t)
+      HittableObject::Sphere(s)              => s.hit(ray, ray_t, rng),
+      HittableObject::ConstantMedium(c)      => c.hit(ray, ray_t, rng),
+      HittableObject::HeterogeneousMedium(h) => h.hit(ray, ray_t, rng),
+      HittableObject::Quad(q)                => q.hit(ray, ray_t, rng),
+      HittableObject::AlphaMasked(a)         => a.hit(ray, ray_t, rng),
+      HittableObject::Subsurface(s)          => s.hit(ray, ray_t, rng)
     }
   }
 }
 
-pub struct HitRecord {
+// surfaces lend their material to the record, volumes may build one per hit.
+// normal is the geometric normal and decides front_face, materials shade with
+// shading_normal, which normal or bump maps may tilt. both face the incoming ray.
+// dpdu and dpdv are the (unnormalized) surface derivatives along the uv directions.
+// object_id is the index of the object in the scene list
+#[derive(Clone)]
+pub struct HitRecord<'a> {
   pub p: Point,
   pub normal: Vector3,
+  pub shading_normal: Vector3,
+  pub dpdu: Vector3,
+  pub dpdv: Vector3,
   pub t: f64,
+  pub u: f64,
+  pub v: f64,
   pub front_face: bool,
-  pub material: MaterialType
+  pub material: Cow<'a, MaterialType>,
+  pub object_id: usize
 }
 
 
-impl HitRecord {
+impl<'a> HitRecord<'a> {
+  // uv is zero and the tangents are an arbitrary frame around the normal,
+  // primitives with a parametrization overwrite them
+  pub fn new(ray: &Ray, t: f64, outward_normal: Vector3, material: Cow<'a, MaterialType>) -> Self {
+    let frame = Onb::new(outward_normal);
+    let mut rec = Self {
+      p: ray.at(t),
+      normal: outward_normal,
+      shading_normal: outward_normal,
+      dpdu: frame.u,
+      dpdv: frame.v,
+      t,
+      u: 0.0,
+      v: 0.0,
+      front_face: true,
+      material,
+      object_id: 0
+    };
+    rec.set_face_normal(ray, outward_normal);
+    rec
+  }
+
   pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: Vector3) {
     self.front_face = Vector3::dot(ray.direction, outward_normal) < 0.0;
     self.normal = if self.front_face {outward_normal} else {-outward_normal};
+    self.shading_normal = self.normal;
+  }
+
+  // sets the shading normal from one that points out of the surface
+  pub fn set_shading_normal(&mut self, outward_shading_normal: Vector3) {
+    self.shading_normal = if self.front_face {outward_shading_normal} else {-outward_shading_normal};
+  }
+
+  pub fn outward_normal(&self) -> Vector3 {
+    if self.front_face {self.normal} else {-self.normal}
   }
 }
 
 
 pub trait Hittable {
-  fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord>;
+  fn hit(&self, ray: &Ray, ray_t: Interval, rng: &mut Rng) -> Option<HitRecord<'_>>;
 }
 
 
@@ -48,14 +182,21 @@ impl HittableList {
       list: Vec::new()
     }
   }
+
+  // around every object, None for an empty scene
+  pub fn bounds(&self) -> Option<(Point, Point)> {
+    self.list.iter().map(HittableObject::bounds)
+      .reduce(|(min_a, max_a), (min_b, max_b)| (Vector3::min(min_a, min_b), Vector3::max(max_a, max_b)))
+  }
 }
 
 impl Hittable for HittableList {
-  fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+  fn hit(&self, ray: &Ray, ray_t: Interval, rng: &mut Rng) -> Option<HitRecord<'_>> {
     let mut hit_rec: Option<HitRecord> = None;
     let mut closest_so_far = ray_t.max;
-    for obj in &self.list {
-      if let Some(temp_rec) = obj.hit(ray, Interval::new(ray_t.min, closest_so_far)) {
+    for (object_id, obj) in self.list.iter().enumerate() {
+      if let Some(mut temp_rec) = obj.hit(ray, Interval::new(ray_t.min, closest_so_far), rng) {
+        temp_rec.object_id = object_id;
         closest_so_far = temp_rec.t;
         hit_rec = Some(temp_rec); 
       }
@@ -6,30 +6,66 @@ use crate::camera::Camera;
 pub struct Image {
   pub width: u16,
   pub height: u16,
-  writer: BufWriter<File>,
   path: PathBuf
 }
 
 
 impl Image {
+  // creates the file right away so a bad path shows up before rendering starts
   pub fn new(width: u16, height: u16, name: &str) -> io::Result<Self> {
-    let path = PathBuf::from(format!("C:\\Users\\sereb\\main_files\\coding\\ray\\images\\{}.ppm", name));
-    let img_file = File::create(&path)?;
-    let mut writer = BufWriter::new(img_file);
-    writeln!(writer, "P3\n{width} {height}\n255")?;
+    Self::create(width, height, name, "ppm")
+  }
+
+  // linear floats as they are, for data that has to keep its values
+  pub fn new_pfm(width: u16, height: u16, name: &str) -> io::Result<Self> {
+    Self::create(width, height, name, "pfm")
+  }
+
+  fn create(width: u16, height: u16, name: &str, extension: &str) -> io::Result<Self> {
+    let path = PathBuf::from(format!("C:\\Users\\sereb\\main_files\\coding\\ray\\images\\{}.{}", name, extension));
+    File::create(&path)?;
     Ok(Self {
       width,
       height,
-      writer,
       path
     })
   }
 
-  pub fn draw() {
+  // an image in the temp directory, for renders in tests that should not leave files behind
+  #[cfg(test)]
+  pub fn scratch(width: u16, height: u16, file: &str) -> Self {
+    Self {width, height, path: std::env::temp_dir().join(format!("ray_{}_{}", std::process::id(), file))}
+  }
+
+  // writes the whole image, replacing what the file held before, so it
+  // can be called again with a more converged buffer
+  pub fn save(&self, pixels: &[Color]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(&self.path)?);
+    if self.path.extension().is_some_and(|e| e == "pfm") {
+      // little endian is marked by the negative scale, the rows go from the bottom up
+      write!(writer, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+      for row in pixels.chunks(self.width as usize).rev() {
+        for color in row {
+          for v in [color.x, color.y, color.z] {
+            writer.write_all(&(v as f32).to_le_bytes())?;
+          }
+        }
+      }
+    } else {
+      writeln!(writer, "P3\n{} {}\n255", self.width, self.height)?;
+      for &color in pixels {
+        Self::write_color(&mut writer, color)?;
+      }
+    }
+    writer.flush()
+  }
 
+  // where the render state of this image is checkpointed
+  pub fn checkpoint_path(&self) -> PathBuf {
+    self.path.with_extension("ckpt")
   }
 
-  pub fn write_color(&mut self, color: Color) {
+  fn write_color<W: Write>(writer: &mut W, color: Color) -> io::Result<()> {
     let mut r = color.x;
     let mut g = color.y;
     let mut b = color.z;
@@ -40,29 +76,10 @@ impl Image {
     let rbyte = (256.0 * intensity.clamp(r)) as i16;
     let gbyte = (256.0 * intensity.clamp(g)) as i16;
     let bbyte = (256.0 * intensity.clamp(b)) as i16;
-    let pixel_string = format!("{} {} {}", rbyte, gbyte, bbyte);
-    writeln!(self.writer, "{pixel_string}").unwrap_or_else(|e| {
-      println!("Error writing a pixel: {}", e);
-      std::process::exit(0);
-    }); 
-  }
-
-  pub fn get_color_threaded(&mut self, color: Color) -> (u8, u8, u8) {
-    let mut r = color.x;
-    let mut g = color.y;
-    let mut b = color.z;
-    r = Camera::linear_to_gamma(r);
-    g = Camera::linear_to_gamma(g);
-    b = Camera::linear_to_gamma(b);
-    let intensity = Interval::new(0.000, 0.999);
-    let rbyte = (256.0 * intensity.clamp(r)) as u8;
-    let gbyte = (256.0 * intensity.clamp(g)) as u8;
-    let bbyte = (256.0 * intensity.clamp(b)) as u8;
-    (rbyte, gbyte, bbyte)
+    writeln!(writer, "{} {} {}", rbyte, gbyte, bbyte)
   }
 
   pub fn open_image(self) {
-    std::mem::drop(self.writer);
     std::process::Command::new("cmd").args([
       "/C",
       "start",
@@ -72,4 +89,28 @@ impl Image {
       std::process::exit(0);
     });
   }
-}
\ No newline at end of file
+}
+
+
+
+#[cfg(test)]
+mod image_tests {
+  use super::*;
+
+  #[test]
+  fn test_pfm_keeps_the_values() {
+    let image = Image::scratch(2, 2, "values.pfm");
+    let pixels = [
+      Color::new(123.5, -2.0, 0.0), Color::new(1.0, 1.0, 1.0),
+      Color::new(0.25, 1e6, -1e-3), Color::new(0.0, 0.0, 0.0)
+    ];
+    image.save(&pixels).unwrap();
+    let bytes = std::fs::read(&image.path).unwrap();
+    std::fs::remove_file(&image.path).unwrap();
+    let header = b"PF\n2 2\n-1.0\n";
+    assert_eq!(&bytes[..header.len()], header);
+    let floats = bytes[header.len()..].chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect::<Vec<f32>>();
+    // the bottom row comes first
+    assert_eq!(floats, vec![0.25, 1e6, -1e-3, 0.0, 0.0, 0.0, 123.5, -2.0, 0.0, 1.0, 1.0, 1.0]);
+  }
+}
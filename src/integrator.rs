@@ -0,0 +1,474 @@
+use crate::aov::{LightPaths, SurfaceInfo};
+use crate::bdpt::Bdpt;
+use crate::camera::Projection;
+use crate::debug::{DebugIntegrator, DebugMode};
+use crate::film::SplatBuffer;
+use crate::guiding::{self, GuidedPathTracer};
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::interval::Interval;
+use crate::light::{self, Lights};
+use crate::material::{Lobe, Material};
+use crate::mlt::Metropolis;
+use crate::options::Options;
+use crate::photon::PhotonMapper;
+use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerType};
+use crate::spectrum::{self, SampledSpectrum, SampledWavelengths};
+use crate::vector3::{Color, Point, Vector3};
+
+// light transport: how much light arrives along a camera ray. the camera only makes
+// the rays and puts what comes back on the film
+pub trait Integrator {
+  // the light is split up by how it reached the camera for the aovs, surface gets
+  // what the ray hit first when they need it
+  fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut SamplerType, surface: Option<&mut Option<SurfaceInfo>>) -> LightPaths<Color>;
+}
+
+
+pub enum IntegratorType {
+  Path(PathTracer),
+  Nee(NeePathTracer),
+  AmbientOcclusion(AmbientOcclusion),
+  Whitted(Whitted),
+  Bdpt(Box<Bdpt>),
+  PhotonMapping(PhotonMapper),
+  Metropolis(Box<Metropolis>),
+  Guided(Box<GuidedPathTracer>),
+  Debug(DebugIntegrator)
+}
+
+
+impl IntegratorType {
+  // the integrators and the debug views by name, with their settings from the options.
+  // camera is where bdpt and mlt splat their light
+  pub fn from_name(name: &str, max_depth: u32, world: &HittableList, camera: Projection, options: &Options) -> Result<Self, String> {
+    if options.spectral && name != "path" {
+      return Err(String::from("spectral rendering only works with the path integrator"));
+    }
+    match name {
+      "path"    => Ok(IntegratorType::Path(PathTracer {max_depth, spectral: options.spectral})),
+      "nee"     => Ok(IntegratorType::Nee(NeePathTracer {max_depth, lights: Lights::new(world)})),
+      "ao"      => Ok(IntegratorType::AmbientOcclusion(AmbientOcclusion {
+        samples: options.ao_samples.max(1),
+        max_distance: options.ao_distance.unwrap_or(f64::INFINITY)
+      })),
+      "whitted" => Ok(IntegratorType::Whitted(Whitted {max_depth, lights: Lights::new(world)})),
+      "bdpt"    => Ok(IntegratorType::Bdpt(Box::new(Bdpt::new(max_depth, world, camera)))),
+      "sppm"    => {
+        let mut sppm = PhotonMapper::new(max_depth, world);
+        sppm.photons = options.photons.map_or(sppm.photons, |n| n.max(1));
+        sppm.radius = options.photon_radius.map_or(sppm.radius, |r| r.max(1e-6));
+        Ok(IntegratorType::PhotonMapping(sppm))
+      },
+      "mlt"     => {
+        // the chains take as many steps as there are camera samples, which has to be
+        // the same for every pixel
+        if options.adaptive_threshold.is_some() {
+          return Err(String::from("adaptive sampling doesn't work with the mlt integrator"));
+        }
+        let mut mlt = Metropolis::new(max_depth, world, camera, options.seed);
+        mlt.chains = options.mlt_chains.map_or(mlt.chains, |n| n.max(1));
+        mlt.bootstrap = options.mlt_bootstrap.map_or(mlt.bootstrap, |n| n.max(1));
+        Ok(IntegratorType::Metropolis(Box::new(mlt)))
+      },
+      "guided"  => {
+        let training_passes = options.guide_passes.unwrap_or(guiding::DEFAULT_TRAINING_PASSES);
+        Ok(IntegratorType::Guided(Box::new(GuidedPathTracer::new(max_depth, world, training_passes))))
+      },
+      _ => DebugMode::from_name(name)
+        .map(|mode| IntegratorType::Debug(DebugIntegrator {mode, max_depth, focus_dist: camera.focus_dist}))
+        .map_err(|_| format!("unknown integrator {}", name))
+    }
+  }
+
+  pub fn name(&self) -> String {
+    match self {
+      IntegratorType::Path(p)             => String::from(if p.spectral {"path spectral"} else {"path"}),
+      IntegratorType::Nee(_)              => String::from("nee"),
+      IntegratorType::AmbientOcclusion(a) => format!("ao {} {}", a.samples, a.max_distance),
+      IntegratorType::Whitted(_)          => String::from("whitted"),
+      IntegratorType::Bdpt(_)             => String::from("bdpt"),
+      IntegratorType::PhotonMapping(p)    => format!("sppm {} {}", p.photons, p.radius),
+      IntegratorType::Metropolis(m)       => format!("mlt {} {}", m.chains, m.bootstrap),
+      IntegratorType::Guided(g)           => format!("guided {}", g.training_passes),
+      IntegratorType::Debug(d)            => format!("debug {}", d.mode.name())
+    }
+  }
+
+  // light that lands away from the camera sample, added to the image when it is written
+  pub fn splats(&self) -> Option<&SplatBuffer> {
+    match self {
+      IntegratorType::Bdpt(b) => Some(&b.splats),
+      IntegratorType::Metropolis(m) => Some(&m.splats),
+      _ => None
+    }
+  }
+
+  // passes this many samples long at most, for integrators that change between passes
+  pub fn pass_samples(&self) -> Option<u32> {
+    match self {
+      IntegratorType::PhotonMapping(_) => Some(1),
+      IntegratorType::Guided(_) => Some(1),
+      _ => None
+    }
+  }
+
+  // called before every pass with the number of passes before it and the samples
+  // per pixel the pass takes
+  pub fn start_pass(&mut self, world: &HittableList, pass: u32, samples: u32, sampler: &SamplerType) {
+    match self {
+      IntegratorType::PhotonMapping(p) => p.start_pass(world, pass, sampler),
+      IntegratorType::Metropolis(m) => m.start_pass(world, pass, samples),
+      IntegratorType::Guided(g) => g.start_pass(),
+      _ => ()
+    }
+  }
+}
+
+
+impl Integrator for IntegratorType {
+  fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut SamplerType, surface: Option<&mut Option<SurfaceInfo>>) -> LightPaths<Color> {
+    match self {
+      IntegratorType::Path(p)             => p.li(ray, world, sampler, surface),
+      IntegratorType::Nee(n)              => n.li(ray, world, sampler, surface),
+      IntegratorType::AmbientOcclusion(a) => a.li(ray, world, sampler, surface),
+      IntegratorType::Whitted(w)          => w.li(ray, world, sampler, surface),
+      IntegratorType::Bdpt(b)             => b.li(ray, world, sampler, surface),
+      IntegratorType::PhotonMapping(p)    => p.li(ray, world, sampler, surface),
+      IntegratorType::Metropolis(m)       => m.li(ray, world, sampler, surface),
+      IntegratorType::Guided(g)           => g.li(ray, world, sampler, surface),
+      IntegratorType::Debug(d)            => d.li(ray, world, sampler, surface)
+    }
+  }
+}
+
+
+// whether the lights can be sampled at rec, materials that can be evaluated at all
+// can be evaluated in every direction
+pub fn evaluable(ray: &Ray, rec: &HitRecord) -> bool {
+  rec.material.eval(ray, rec, rec.shading_normal).is_some()
+}
+
+
+// follows one path per camera ray, picking up light only when it runs into it
+pub struct PathTracer {
+  pub max_depth: u32,
+  // follows a few wavelengths per path instead of rgb
+  pub spectral: bool
+}
+
+
+impl PathTracer {
+  // same as li but carrying a few wavelengths instead of rgb, the rgb colors of
+  // materials and emitters get upsampled at the sampled wavelengths
+  pub fn li_spectral(&self, ray: &Ray, world: &HittableList, wavelengths: &mut SampledWavelengths, sampler: &mut SamplerType, surface: Option<&mut Option<SurfaceInfo>>) -> LightPaths<SampledSpectrum> {
+    let mut paths = LightPaths::new(SampledSpectrum::constant(0.0));
+    let mut throughput = SampledSpectrum::constant(1.0);
+    let mut ray = *ray;
+    let mut first_lobe = None;
+    let mut surface = surface;
+    for bounces in 0..self.max_depth {
+      let Some(rec) = world.hit(&ray, Interval::new(0.001, f64::INFINITY), sampler.rng()) else {
+        paths.add(throughput * SampledSpectrum::from_rgb(light::sky(&ray), wavelengths), bounces, first_lobe);
+        break;
+      };
+      if let Some(surface) = surface.take() {
+        *surface = Some(SurfaceInfo::new(&ray, &rec));
+      }
+      paths.add(throughput * SampledSpectrum::from_rgb(rec.material.emitted(&rec), wavelengths), bounces, first_lobe);
+      if rec.material.dispersive() {
+        wavelengths.terminate_secondary();
+      }
+      let Some(scatter) = rec.material.scatter(&ray, &rec, sampler) else {
+        break;
+      };
+      throughput = throughput * SampledSpectrum::from_rgb(scatter.attenuation, wavelengths);
+      first_lobe = first_lobe.or(Some(scatter.lobe));
+      let wavelength = ray.wavelength;
+      ray = scatter.ray;
+      ray.wavelength = wavelength;
+    }
+    paths
+  }
+}
+
+
+impl Integrator for PathTracer {
+  fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut SamplerType, surface: Option<&mut Option<SurfaceInfo>>) -> LightPaths<Color> {
+    if self.spectral {
+      let mut wavelengths = SampledWavelengths::sample_uniform(sampler.get_1d());
+      let ray = Ray {wavelength: Some(wavelengths.hero()), ..*ray};
+      let radiance = self.li_spectral(&ray, world, &mut wavelengths, sampler, surface);
+      return radiance.map(|s| spectrum::xyz_to_rgb(wavelengths.to_xyz(s)));
+    }
+    let mut paths = LightPaths::new(Color::new(0.0, 0.0, 0.0));
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = *ray;
+    let mut first_lobe = None;
+    let mut surface = surface;
+    for bounces in 0..self.max_depth {
+      let Some(rec) = world.hit(&ray, Interval::new(0.001, f64::INFINITY), sampler.rng()) else {
+        paths.add(throughput * light::sky(&ray), bounces, first_lobe);
+        break;
+      };
+      if let Some(surface) = surface.take() {
+        *surface = Some(SurfaceInfo::new(&ray, &rec));
+      }
+      paths.add(throughput * rec.material.emitted(&rec), bounces, first_lobe);
+      let Some(scatter) = rec.material.scatter(&ray, &rec, sampler) else {
+        break;
+      };
+      throughput *= scatter.attenuation;
+      first_lobe = first_lobe.or(Some(scatter.lobe));
+      ray = scatter.ray;
+    }
+    paths
+  }
+}
+
+
+// path tracing that also aims a shadow ray at a light from every vertex the material
+// can be evaluated at (next event estimation). light found both ways is weighted
+// with multiple importance sampling, mirrors and glass only find it by scattering
+pub struct NeePathTracer {
+  pub max_depth: u32,
+  pub lights: Lights
+}
+
+
+impl Integrator for NeePathTracer {
+  fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut SamplerType, surface: Option<&mut Option<SurfaceInfo>>) -> LightPaths<Color> {
+    let mut paths = LightPaths::new(Color::new(0.0, 0.0, 0.0));
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = *ray;
+    let mut first_lobe = None;
+    let mut surface = surface;
+    // where the ray was scattered from and the density of its direction, when the
+    // lights were sampled there too
+    let mut light_sampled_at: Option<(Point, f64)> = None;
+    for bounces in 0..self.max_depth {
+      let Some(rec) = world.hit(&ray, Interval::new(0.001, f64::INFINITY), sampler.rng()) else {
+        let weight = light_sampled_at.map_or(1.0, |(_, pdf)| light::power_heuristic(pdf, self.lights.sky_pdf()));
+        paths.add(throughput * weight * light::sky(&ray), bounces, first_lobe);
+        break;
+      };
+      if let Some(surface) = surface.take() {
+        *surface = Some(SurfaceInfo::new(&ray, &rec));
+      }
+      let weight = light_sampled_at.map_or(1.0, |(origin, pdf)| {
+        light::power_heuristic(pdf, self.lights.object_pdf(world, rec.object_id, origin, ray.direction))
+      });
+      paths.add(throughput * weight * rec.material.emitted(&rec), bounces, first_lobe);
+      // the light sample takes its dimensions first so they stay put whatever scatter draws
+      let (u_light, u) = (sampler.get_1d(), sampler.get_2d());
+      let scatter = rec.material.scatter(&ray, &rec, sampler);
+      let lit = bounces + 1 < self.max_depth && evaluable(&ray, &rec);
+      if lit {
+        let lobe = scatter.as_ref().map_or(Lobe::Diffuse, |s| s.lobe);
+        if let Some(sample) = self.lights.sample(world, rec.p, u_light, u) {
+          let eval = rec.material.eval(&ray, &rec, sample.direction).expect("evaluable");
+          if !eval.f.near_zero() {
+            let incoming = self.lights.incoming(world, rec.p, &sample, sampler.rng());
+            let weight = light::power_heuristic(sample.pdf, eval.pdf) / sample.pdf;
+            paths.add(throughput * eval.f * incoming * weight, bounces + 1, first_lobe.or(Some(lobe)));
+          }
+        }
+      }
+      let Some(scatter) = scatter else {
+        break;
+      };
+      light_sampled_at = if lit {
+        rec.material.eval(&ray, &rec, scatter.ray.direction).map(|eval| (rec.p, eval.pdf))
+      } else {
+        None
+      };
+      throughput *= scatter.attenuation;
+      first_lobe = first_lobe.or(Some(scatter.lobe));
+      ray = scatter.ray;
+    }
+    paths
+  }
+}
+
+
+// grayscale, white where the surface seen from the camera is open all around and
+// darker the more of it is blocked. directions are cosine weighted, so what is in
+// front of the surface counts more than what is off to the side, and only things
+// closer than max_distance block it. nothing hit is white
+pub struct AmbientOcclusion {
+  // shadow rays per camera ray
+  pub samples: u32,
+  pub max_distance: f64
+}
+
+
+impl AmbientOcclusion {
+  // the share of rays from rec that get away
+  fn open(&self, rec: &HitRecord, world: &HittableList, sampler: &mut SamplerType) -> f64 {
+    let samples = self.samples.max(1);
+    let mut open = 0;
+    for _ in 0..samples {
+      // cosine weighted around the side of the surface the camera sees
+      let mut direction = rec.shading_normal + Vector3::sample_unit_vector(sampler.get_2d());
+      if direction.near_zero() {
+        direction = rec.shading_normal;
+      }
+      let direction = Vector3::unit_vector(&direction);
+      if world.hit(&Ray::new(rec.p, direction), Interval::new(0.001, self.max_distance), sampler.rng()).is_none() {
+        open += 1;
+      }
+    }
+    open as f64 / samples as f64
+  }
+}
+
+
+impl Integrator for AmbientOcclusion {
+  fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut SamplerType, surface: Option<&mut Option<SurfaceInfo>>) -> LightPaths<Color> {
+    let mut paths = LightPaths::new(Color::new(0.0, 0.0, 0.0));
+    let Some(rec) = world.hit(ray, Interval::new(0.001, f64::INFINITY), sampler.rng()) else {
+      paths.add(Color::new(1.0, 1.0, 1.0), 0, None);
+      return paths;
+    };
+    if let Some(surface) = surface {
+      *surface = Some(SurfaceInfo::new(ray, &rec));
+    }
+    let open = self.open(&rec, world, sampler);
+    paths.add(Color::new(open, open, open), 0, None);
+    paths
+  }
+}
+
+
+// the classic ray tracer: mirrors and glass are followed, every other surface is
+// lit directly by one light sample and ends the path, so there is no indirect light
+pub struct Whitted {
+  pub max_depth: u32,
+  pub lights: Lights
+}
+
+
+impl Integrator for Whitted {
+  fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut SamplerType, surface: Option<&mut Option<SurfaceInfo>>) -> LightPaths<Color> {
+    let mut paths = LightPaths::new(Color::new(0.0, 0.0, 0.0));
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = *ray;
+    let mut first_lobe = None;
+    let mut surface = surface;
+    for bounces in 0..self.max_depth {
+      let Some(rec) = world.hit(&ray, Interval::new(0.001, f64::INFINITY), sampler.rng()) else {
+        paths.add(throughput * light::sky(&ray), bounces, first_lobe);
+        break;
+      };
+      if let Some(surface) = surface.take() {
+        *surface = Some(SurfaceInfo::new(&ray, &rec));
+      }
+      paths.add(throughput * rec.material.emitted(&rec), bounces, first_lobe);
+      let (u_light, u) = (sampler.get_1d(), sampler.get_2d());
+      if evaluable(&ray, &rec) {
+        if bounces + 1 == self.max_depth {
+          break;
+        }
+        if let Some(sample) = self.lights.sample(world, rec.p, u_light, u) {
+          let eval = rec.material.eval(&ray, &rec, sample.direction).expect("evaluable");
+          let incoming = self.lights.incoming(world, rec.p, &sample, sampler.rng());
+          paths.add(throughput * eval.f * incoming / sample.pdf, bounces + 1, first_lobe.or(Some(Lobe::Diffuse)));
+        }
+        break;
+      }
+      let Some(scatter) = rec.material.scatter(&ray, &rec, sampler) else {
+        break;
+      };
+      throughput *= scatter.attenuation;
+      first_lobe = first_lobe.or(Some(scatter.lobe));
+      ray = scatter.ray;
+    }
+    paths
+  }
+}
+
+
+
+#[cfg(test)]
+mod integrator_tests {
+  use super::*;
+  use crate::camera::Camera;
+  use crate::image::Image;
+  use crate::hittable::HittableObject;
+  use crate::material::{HenyeyGreenstein, Lambertian, MaterialType};
+  use crate::quad::Quad;
+  use crate::sphere::Sphere;
+
+  // a gray floor under the sky and a small glowing ball
+  fn lamp_over_floor() -> HittableList {
+    let mut world = HittableList::new();
+    let gray = MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let glow = MaterialType::HenyeyGreenstein(HenyeyGreenstein::emissive(Color::new(0.0, 0.0, 0.0), 0.0, Color::new(8.0, 8.0, 8.0)));
+    world.list.push(HittableObject::Quad(Quad::new(Point::new(-50.0, 0.0, 50.0), Vector3::new(100.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -100.0), gray)));
+    world.list.push(HittableObject::Sphere(Sphere::new(Point::new(0.0, 1.5, 0.0), 0.3, glow)));
+    world
+  }
+
+  // mean and variance of the light along a ray looking down at the floor under the ball
+  fn estimate(integrator: &IntegratorType, world: &HittableList, n: u32) -> (f64, f64) {
+    let mut sampler = SamplerType::from_name("independent", n, 1).unwrap();
+    let ray = Ray::new(Point::new(0.0, 1.0, 2.0), Vector3::new(0.0, -1.0, -1.5));
+    let (mut sum, mut sum_sq) = (0.0, 0.0);
+    for i in 0..n {
+      sampler.start_pixel_sample(0, 0, i);
+      let l = integrator.li(&ray, world, &mut sampler, None).total().y;
+      sum += l;
+      sum_sq += l * l;
+    }
+    let mean = sum / n as f64;
+    (mean, sum_sq / n as f64 - mean * mean)
+  }
+
+  #[test]
+  fn test_integrators_by_name() {
+    let world = lamp_over_floor();
+    let camera = Camera::new(Image::scratch(4, 4, "integrators.ppm")).projection();
+    let (mut options, _) = Options::parse(&[]).unwrap();
+    for name in ["path", "nee", "ao", "whitted", "bdpt", "sppm", "mlt", "guided", "normals", "cost"] {
+      let integrator = IntegratorType::from_name(name, 4, &world, camera, &options).unwrap();
+      assert!(integrator.name().contains(name));
+    }
+    assert!(IntegratorType::from_name("bvh", 4, &world, camera, &options).is_err());
+    options.ao_samples = 8;
+    options.ao_distance = Some(2.0);
+    assert_eq!(IntegratorType::from_name("ao", 4, &world, camera, &options).unwrap().name(), "ao 8 2");
+    options.spectral = true;
+    assert_eq!(IntegratorType::from_name("path", 4, &world, camera, &options).unwrap().name(), "path spectral");
+    assert!(IntegratorType::from_name("nee", 4, &world, camera, &options).is_err());
+  }
+
+  #[test]
+  fn test_nee_agrees_with_path_tracing() {
+    let world = lamp_over_floor();
+    let n = 40_000;
+    let (path_mean, path_variance) = estimate(&IntegratorType::Path(PathTracer {max_depth: 4, spectral: false}), &world, n);
+    let (nee_mean, nee_variance) = estimate(&IntegratorType::Nee(NeePathTracer {max_depth: 4, lights: Lights::new(&world)}), &world, n);
+    assert!((nee_mean / path_mean - 1.0).abs() < 0.03, "{} {}", nee_mean, path_mean);
+    assert!(nee_variance < 0.5 * path_variance, "{} {}", nee_variance, path_variance);
+  }
+
+  #[test]
+  fn test_ao_only_sees_what_is_close() {
+    // a floor with a ceiling one unit above it
+    let mut world = HittableList::new();
+    let gray = MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    world.list.push(HittableObject::Quad(Quad::new(Point::new(-50.0, 0.0, 50.0), Vector3::new(100.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -100.0), gray.clone())));
+    world.list.push(HittableObject::Quad(Quad::new(Point::new(-50.0, 1.0, 50.0), Vector3::new(100.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -100.0), gray)));
+    let ray = Ray::new(Point::new(0.0, 0.5, 0.0), Vector3::new(0.0, -1.0, -0.5));
+    let mut sampler = SamplerType::from_name("independent", 1, 1).unwrap();
+    sampler.start_pixel_sample(0, 0, 0);
+    let ao = |max_distance: f64, sampler: &mut SamplerType| {
+      AmbientOcclusion {samples: 64, max_distance}.li(&ray, &world, sampler, None).total().x
+    };
+    assert_eq!(ao(0.9, &mut sampler), 1.0);
+    assert_eq!(ao(f64::INFINITY, &mut sampler), 0.0);
+    // rays that leave at a low angle reach the ceiling beyond 1.5 units
+    let partly = ao(1.5, &mut sampler);
+    assert!(partly > 0.1 && partly < 0.9, "{}", partly);
+  }
+}
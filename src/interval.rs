@@ -1,5 +1,3 @@
-use std::f64::{INFINITY, NEG_INFINITY};
-
 pub struct Interval {
   pub min: f64,
   pub max: f64
@@ -10,28 +8,13 @@ impl Interval {
     Self {min, max}
   }
 
-  pub fn empty() -> Self {
-    Self {
-      min: INFINITY,
-      max: NEG_INFINITY
-    }
-  }
-
   pub fn universe() -> Self {
     Self {
-      min: NEG_INFINITY, 
-      max: INFINITY 
+      min: f64::NEG_INFINITY, 
+      max: f64::INFINITY 
     }
   }
 
-  pub fn size(&self) -> f64 {
-    self.max - self.min
-  }
-
-  pub fn contains(&self, x: f64) -> bool {
-    self.min <= x && x <= self.max
-  }
-
   pub fn surrounds(&self, x: f64) -> bool {
     self.min < x && x < self.max
   }
@@ -0,0 +1,190 @@
+use std::f64::consts::PI;
+
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::rng::Rng;
+use crate::vector3::{Color, Point, Vector3};
+
+// what the integrators can aim rays at to find light: the sky, and the spheres and
+// quads of the scene with an emissive material. glowing volumes are only found by
+// scattering into them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Light {
+  Sky,
+  // index of the object in the scene list
+  Object(usize)
+}
+
+// a direction toward a light and the density of picking it, which includes the
+// probability of picking that light
+pub struct LightSample {
+  pub light: Light,
+  pub direction: Vector3,
+  pub pdf: f64
+}
+
+// where a light path starts: a point on one of the object lights and the direction
+// it leaves in, cosine weighted around the outward normal
+pub struct Emission<'a> {
+  // the light seen from just outside the point along probe, for its material and uvs
+  pub rec: HitRecord<'a>,
+  pub probe: Ray,
+  pub direction: Vector3,
+  pub emitted: Color,
+  // area density of the point, which includes the probability of picking the light
+  pub pdf_point: f64
+}
+
+
+pub struct Lights {
+  lights: Vec<Light>
+}
+
+
+impl Lights {
+  pub fn new(world: &HittableList) -> Self {
+    let objects = world.list.iter().enumerate()
+      .filter(|(_, object)| object.material().is_some_and(|material| material.emissive()))
+      .map(|(i, _)| Light::Object(i));
+    Self {lights: std::iter::once(Light::Sky).chain(objects).collect()}
+  }
+
+  // indices of the objects that are lights
+  pub fn objects(&self) -> impl Iterator<Item = usize> + '_ {
+    self.lights.iter().filter_map(|light| match light {
+      Light::Object(i) => Some(*i),
+      Light::Sky => None
+    })
+  }
+
+  // lights are picked uniformly with u_light, u picks the direction toward it
+  pub fn sample(&self, world: &HittableList, origin: Point, u_light: f64, u: (f64, f64)) -> Option<LightSample> {
+    let pick = 1.0 / self.lights.len() as f64;
+    let light = self.lights[((u_light * self.lights.len() as f64) as usize).min(self.lights.len() - 1)];
+    let (direction, pdf) = match light {
+      Light::Sky => (Vector3::sample_unit_vector(u), 1.0 / (4.0 * PI)),
+      Light::Object(i) => world.list[i].sample_direction(origin, u)?
+    };
+    Some(LightSample {light, direction, pdf: pick * pdf})
+  }
+
+  // lights are picked uniformly with u_light and points uniformly over their area
+  // with u_point, u_direction picks the direction. the sky has no points to start from
+  pub fn sample_emission<'a>(&self, world: &'a HittableList, u_light: f64, u_point: (f64, f64), u_direction: (f64, f64), rng: &mut Rng) -> Option<Emission<'a>> {
+    let objects = self.objects().collect::<Vec<usize>>();
+    let id = *objects.get(((u_light * objects.len() as f64) as usize).min(objects.len().saturating_sub(1)))?;
+    let object = &world.list[id];
+    let (p, normal) = object.sample_point(u_point)?;
+    let probe = Ray::new(p + normal, -normal);
+    let mut rec = object.hit(&probe, Interval::new(0.5, 1.5), rng)?;
+    rec.object_id = id;
+    let emitted = rec.material.emitted(&rec);
+    if emitted.near_zero() {
+      return None;
+    }
+    let mut direction = normal + Vector3::sample_unit_vector(u_direction);
+    if direction.near_zero() {
+      direction = normal;
+    }
+    Some(Emission {rec, probe, direction, emitted, pdf_point: self.emission_pdf(world, id)})
+  }
+
+  // area density of sample_emission for a point of the object
+  pub fn emission_pdf(&self, world: &HittableList, object_id: usize) -> f64 {
+    1.0 / ((self.lights.len() - 1) as f64 * world.list[object_id].area())
+  }
+
+  // density of sample for a direction that escaped to the sky
+  pub fn sky_pdf(&self) -> f64 {
+    1.0 / (4.0 * PI * self.lights.len() as f64)
+  }
+
+  // density of sample for a direction from origin that hit the object, zero for
+  // objects that are not lights
+  pub fn object_pdf(&self, world: &HittableList, object_id: usize, origin: Point, direction: Vector3) -> f64 {
+    if !self.lights.contains(&Light::Object(object_id)) {
+      return 0.0;
+    }
+    world.list[object_id].direction_pdf(origin, direction) / self.lights.len() as f64
+  }
+
+  // the light arriving at origin from the sampled light, nothing when something
+  // else is in the way
+  pub fn incoming(&self, world: &HittableList, origin: Point, sample: &LightSample, rng: &mut Rng) -> Color {
+    let ray = Ray::new(origin, sample.direction);
+    let hit = world.hit(&ray, Interval::new(0.001, f64::INFINITY), rng);
+    match (sample.light, hit) {
+      (Light::Sky, None) => sky(&ray),
+      (Light::Object(i), Some(rec)) if rec.object_id == i => rec.material.emitted(&rec),
+      _ => Color::new(0.0, 0.0, 0.0)
+    }
+  }
+}
+
+
+// the background every scene is lit by, white at the horizon to blue straight up
+pub fn sky(ray: &Ray) -> Color {
+  let unit_direction = Vector3::unit_vector(&ray.direction);
+  let a = 0.5 * (unit_direction.y + 1.0);
+  (1.0 - a) * Color::new(1.0, 1.0, 1.0) +  a * Color::new(0.5, 0.7, 1.0)
+}
+
+
+// weight of a sample taken with density pdf when another strategy could have taken
+// it with density other_pdf, the power heuristic with beta 2
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+  let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+  if a + b == 0.0 {0.0} else {a / (a + b)}
+}
+
+
+
+#[cfg(test)]
+mod light_tests {
+  use super::*;
+  use crate::hittable::HittableObject;
+  use crate::material::{HenyeyGreenstein, Lambertian, MaterialType};
+  use crate::quad::Quad;
+  use crate::sphere::Sphere;
+
+  #[test]
+  fn test_only_emissive_objects_are_lights() {
+    let mut world = HittableList::new();
+    let glow = MaterialType::HenyeyGreenstein(HenyeyGreenstein::emissive(Color::new(0.5, 0.5, 0.5), 0.0, Color::new(4.0, 4.0, 4.0)));
+    world.list.push(HittableObject::Sphere(Sphere::new(Point::new(0.0, 0.0, -2.0), 0.5, MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))))));
+    world.list.push(HittableObject::Sphere(Sphere::new(Point::new(0.0, 3.0, -2.0), 0.5, glow)));
+    let lights = Lights::new(&world);
+    assert_eq!(lights.lights, vec![Light::Sky, Light::Object(1)]);
+    assert_eq!(lights.object_pdf(&world, 0, Point::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0)), 0.0);
+  }
+
+  // the density of directions toward a shape integrates to one over the directions
+  // that hit it, checked by estimating the solid angle the shape covers
+  #[test]
+  fn test_pdfs_match_the_solid_angle() {
+    let mat = MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let sphere = HittableObject::Sphere(Sphere::new(Point::new(0.0, 0.0, -3.0), 1.0, mat.clone()));
+    let triangle = HittableObject::Quad(Quad::triangle(Point::new(-1.0, -1.0, -2.0), Vector3::new(2.0, 0.0, 0.0), Vector3::new(0.0, 2.0, -1.0), mat));
+    let origin = Point::new(0.1, 0.2, 0.0);
+    let mut rng = Rng::new(3);
+    for object in [sphere, triangle] {
+      let n = 200_000;
+      let directions = (0..n).map(|_| Vector3::sample_unit_vector((rng.uniform(), rng.uniform()))).collect::<Vec<Vector3>>();
+      let hits = directions.iter()
+        .filter(|&&d| object.hit(&Ray::new(origin, d), Interval::new(0.001, f64::INFINITY), &mut rng).is_some())
+        .count();
+      let solid_angle = 4.0 * PI * hits as f64 / n as f64;
+      let (direction, pdf) = object.sample_direction(origin, (0.3, 0.6)).unwrap();
+      assert!((object.direction_pdf(origin, direction) - pdf).abs() < 1e-9);
+      // constant over the shape for the sphere, so one over its solid angle
+      if matches!(object, HittableObject::Sphere(_)) {
+        assert!((pdf * solid_angle - 1.0).abs() < 0.02, "{}", pdf * solid_angle);
+      }
+      // averaging 1 / pdf over samples gives the solid angle
+      let estimate = (0..n).map(|_| 1.0 / object.sample_direction(origin, (rng.uniform(), rng.uniform())).unwrap().1).sum::<f64>() / n as f64;
+      assert!((estimate / solid_angle - 1.0).abs() < 0.02, "{} {}", estimate, solid_angle);
+    }
+  }
+}
@@ -8,13 +8,47 @@ mod hittable;
 mod sphere;
 mod interval;
 mod material;
+mod constant_medium;
+mod heterogeneous_medium;
+mod perlin;
+mod onb;
+mod microfacet;
+mod fresnel;
+mod texture;
+mod quad;
+mod alpha_mask;
+mod subsurface;
+mod spectrum;
+mod options;
+mod sampler;
+mod filter;
+mod film;
+mod adaptive;
+mod scene;
+mod checkpoint;
+mod rng;
+mod aov;
+mod denoise;
+mod debug;
+mod light;
+mod integrator;
+mod bdpt;
+mod photon;
+mod mlt;
+mod guiding;
+#[cfg(test)]
+mod test_scenes;
 
 use image::Image;
-use vector3::{Color, Point};
-use camera::Camera;
-use crate::hittable::{HittableList, HittableObject};
-use crate::material::{Dielectric, Lambertian, MaterialType, Metal};
-use crate::sphere::Sphere;
+use camera::{Camera, Progressive};
+use checkpoint::Checkpoint;
+use rng::Rng;
+use options::Options;
+use sampler::SamplerType;
+use filter::Filter;
+use adaptive::AdaptiveSampling;
+use denoise::Denoiser;
+use integrator::IntegratorType;
 use std::f64::consts::PI;
 use std::sync::Arc;    
 
@@ -33,73 +67,104 @@ fn degrees_to_radians(degrees: f64) -> f64 {
 
 
 fn main() {
-  let name = std::env::args().collect::<Vec<String>>()
-                             .into_iter()
-                             .skip(1)
-                             .next()
-                             .unwrap_or_else(||{
-                                println!("No name provided");
-                                std::process::exit(0);});  
-  let img = Image::new(IMAGE_WIDTH, IMAGE_HEIGHT, &name).unwrap_or_else(|e| {
+  let (options, args) = Options::parse(&std::env::args().skip(1).collect::<Vec<String>>()).unwrap_or_else(|e| {
+    println!("Error occured while parsing the arguments: {}", e);
+    std::process::exit(0);
+  });
+  let name = args.first().unwrap_or_else(|| {
+    println!("No name provided");
+    std::process::exit(0);
+  });
+  let scene_name = args.get(1).map_or("spheres", String::as_str);
+  let world = scene::build(scene_name, &args[2.min(args.len())..], &mut Rng::new(options.seed)).unwrap_or_else(|e| {
+    println!("Error occured while building the scene: {}", e);
+    std::process::exit(0);
+  });
+  let img = Image::new(IMAGE_WIDTH, IMAGE_HEIGHT, name).unwrap_or_else(|e| {
     println!("Error occured while creating a file: {}", e);
     std::process::exit(0);
   });
-  let mut cam = Camera::new(img);  
-  let material_ground = MaterialType::Lambertian(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
-  let material_center = MaterialType::Lambertian(Lambertian::new(Color::new(0.1, 0.2, 0.5)));
-  let material_left = MaterialType::Dielectric(Dielectric::new(1.5));
-  let material_bubble = MaterialType::Dielectric(Dielectric::new(1.0 / 1.5));
-  let material_right = MaterialType::Metal(Metal::new(Color::new(0.8, 0.6, 0.2), 0.1));
-  let mut world = HittableList::new();
-  world.list.push(
-    HittableObject::Sphere(
-      Sphere::new(
-        Point::new(0.0,  0.0, -1.5), 
-        0.5,
-        material_center
-      )
-    )
-  );
-  world.list.push(
-    HittableObject::Sphere(
-      Sphere::new(
-        Point::new(0.0, -100.5, -1.0), 
-        100.0,
-        material_ground
-      )
-    )
-  );
-  world.list.push(
-  HittableObject::Sphere(
-    Sphere::new(
-      // Point::new(-1.0, 0.0, -1.0), 
-      Point::new(-0.8, 0.0, -1.0), 
-      0.5,
-      material_left
-    )
-  )
-  );
-  world.list.push(
-  HittableObject::Sphere(
-    Sphere::new(
-      // Point::new(-1.0, 0.0, -1.0), 
-      Point::new(-0.8, 0.0, -1.0), 
-      0.4,
-      material_bubble
-    )
-  )
-  );
-  world.list.push(
-    HittableObject::Sphere(
-      Sphere::new(
-        Point::new(1.0, 0.0, -1.0), 
-        0.5,
-        material_right
-      )
-    )
-  );
+  let mut cam = Camera::new(img);
+  cam.scene = [scene_name].into_iter().chain(args.iter().skip(2).map(String::as_str)).collect::<Vec<&str>>().join(" ");
+  cam.seed = options.seed;
+  if let Some(samples_per_pixel) = options.samples_per_pixel {
+    cam.samples_per_pixel = samples_per_pixel as i32;
+  }
+  if options.progressive() {
+    cam.progressive = Some(Progressive {
+      pass_samples: options.pass_samples.unwrap_or(4),
+      write_interval: options.write_interval,
+      time_budget: options.time_budget
+    });
+  }
+  if let Some(threshold) = options.adaptive_threshold {
+    cam.adaptive = Some(AdaptiveSampling {
+      min_samples: options.min_samples,
+      max_samples: options.max_samples.unwrap_or(cam.samples_per_pixel as u32),
+      threshold
+    });
+    if options.heatmap {
+      cam.heatmap = Some(Image::new(IMAGE_WIDTH, IMAGE_HEIGHT, &format!("{}_samples", name)).unwrap_or_else(|e| {
+        println!("Error occured while creating a file: {}", e);
+        std::process::exit(0);
+      }));
+    }
+  }
+  let max_samples = cam.adaptive.map_or(cam.samples_per_pixel as u32, |a| a.max_samples);
+  cam.sampler = SamplerType::from_name(&options.sampler, max_samples, options.seed).unwrap_or_else(|e| {
+    println!("Error occured while parsing the arguments: {}", e);
+    std::process::exit(0);
+  });
+  cam.filter = Filter::from_name(&options.filter, options.filter_radius).unwrap_or_else(|e| {
+    println!("Error occured while parsing the arguments: {}", e);
+    std::process::exit(0);
+  });
+  for aov in &options.aovs {
+    let file = format!("{}_{}", name, aov.name());
+    let image = if aov.is_data() {Image::new_pfm(IMAGE_WIDTH, IMAGE_HEIGHT, &file)} else {Image::new(IMAGE_WIDTH, IMAGE_HEIGHT, &file)};
+    cam.aovs.push((*aov, image.unwrap_or_else(|e| {
+      println!("Error occured while creating a file: {}", e);
+      std::process::exit(0);
+    })));
+    if options.aov_views && aov.is_data() {
+      let view = Image::new(IMAGE_WIDTH, IMAGE_HEIGHT, &format!("{}_view", file)).unwrap_or_else(|e| {
+        println!("Error occured while creating a file: {}", e);
+        std::process::exit(0);
+      });
+      cam.aov_views.push((*aov, view));
+    }
+  }
+  if let Some(denoiser) = &options.denoise {
+    cam.denoiser = Some(Denoiser::from_name(denoiser).unwrap_or_else(|e| {
+      println!("Error occured while parsing the arguments: {}", e);
+      std::process::exit(0);
+    }));
+  }
+  let integrator = options.debug.as_deref().unwrap_or(&options.integrator);
+  cam.integrator = IntegratorType::from_name(integrator, cam.max_depth.max(0) as u32, &world, cam.projection(), &options).unwrap_or_else(|e| {
+    println!("Error occured while parsing the arguments: {}", e);
+    std::process::exit(0);
+  });
+  cam.checkpoint_interval = options.checkpoint_interval;
+  if options.resume {
+    let checkpoint = Checkpoint::load(&cam.img.checkpoint_path()).unwrap_or_else(|e| {
+      println!("Error occured while reading the checkpoint: {}", e);
+      std::process::exit(0);
+    });
+    cam.resume(checkpoint).unwrap_or_else(|e| {
+      println!("Error occured while resuming the render: {}", e);
+      std::process::exit(0);
+    });
+  }
+  // the first ctrl-c lets the render write what it has, a second one exits right away
+  if let Err(e) = ctrlc::set_handler(|| if camera::interrupt() {std::process::exit(0)}) {
+    println!("Error occured while setting the ctrl-c handler: {}", e);
+  }
   let shared_world = Arc::new(world);
-  // cam.render(&world);
   cam.render_threaded(shared_world);
+  if camera::interrupted() {
+    println!("Interrupted, the partial image and a checkpoint were written, continue with --resume");
+    return;
+  }
   cam.img.open_image();
-}
\ No newline at end of file
+}
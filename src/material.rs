@@ -1,18 +1,204 @@
-use crate::{hittable::HitRecord, ray::Ray, vector3::{Color, Vector3}};
+use std::sync::Arc;
 
-#[derive(Clone, Copy)]
+use crate::{fresnel, hittable::HitRecord, microfacet::TrowbridgeReitz, onb::Onb, ray::Ray, vector3::{Color, Vector3}};
+use crate::sampler::{Sampler, SamplerType};
+use crate::spectrum::Dispersion;
+use crate::texture::{ImageTexture, ScalarTexture, Texture, TextureType};
+
+#[derive(Clone)]
 pub enum MaterialType {
   Metal(Metal),
   Lambertian(Lambertian),
-  Dielectric(Dielectric)
+  Dielectric(Dielectric),
+  Isotropic(Isotropic),
+  HenyeyGreenstein(HenyeyGreenstein),
+  Conductor(Conductor),
+  RoughDielectric(RoughDielectric),
+  Principled(Box<Principled>),
+  Mix(Mix),
+  Coated(Coated),
+  NormalMapped(NormalMapped),
+  Subsurface(Subsurface),
+  DiffuseLight(DiffuseLight)
+}
+
+impl MaterialType {
+  // volumes scatter the same whatever surface they sit on, they have no normal
+  pub fn is_phase_function(&self) -> bool {
+    matches!(self, MaterialType::Isotropic(_) | MaterialType::HenyeyGreenstein(_))
+  }
+
+  // whether scattering depends on the wavelength, spectral rendering
+  // can then only follow the hero wavelength
+  pub fn dispersive(&self) -> bool {
+    match self {
+      MaterialType::Dielectric(d) => d.dispersion.is_some(),
+      MaterialType::RoughDielectric(d) => d.dispersion.is_some(),
+      MaterialType::Mix(m) => m.first.dispersive() || m.second.dispersive(),
+      MaterialType::Coated(c) => c.base.dispersive(),
+      MaterialType::NormalMapped(n) => n.base.dispersive(),
+      _ => false
+    }
+  }
+
+  // the color of the surface under uniform white light, roughly, for the albedo
+  // aov and for guiding denoisers. glass and other clear materials are white
+  pub fn albedo(&self, rec: &HitRecord) -> Color {
+    let white = Color::new(1.0, 1.0, 1.0);
+    match self {
+      MaterialType::Metal(m)      => m.albedo,
+      MaterialType::Lambertian(l) => l.albedo,
+      MaterialType::Dielectric(_) => white,
+      MaterialType::Isotropic(i)  => i.albedo,
+      MaterialType::HenyeyGreenstein(h) => h.albedo,
+      MaterialType::Conductor(c)  => fresnel::conductor(1.0, c.eta, c.k),
+      MaterialType::RoughDielectric(_) => white,
+      MaterialType::Principled(p) => p.base_color.value(rec.u, rec.v, rec.p),
+      MaterialType::Mix(m)        => {
+        let w = m.weight(rec);
+        (1.0 - w) * m.first.albedo(rec) + w * m.second.albedo(rec)
+      },
+      MaterialType::Coated(c)     => c.base.albedo(rec),
+      MaterialType::NormalMapped(n) => n.base.albedo(rec),
+      MaterialType::Subsurface(s) => s.albedo,
+      MaterialType::DiffuseLight(_) => Color::new(0.0, 0.0, 0.0)
+    }
+  }
+
+  // whether the material gives off any light, textured emission counts as light
+  pub fn emissive(&self) -> bool {
+    match self {
+      MaterialType::HenyeyGreenstein(h) => !h.emission.near_zero(),
+      MaterialType::Principled(p) => !matches!(p.emission, TextureType::Solid(c) if c.near_zero()),
+      MaterialType::Mix(m)        => m.first.emissive() || m.second.emissive(),
+      MaterialType::Coated(c)     => c.base.emissive(),
+      MaterialType::NormalMapped(n) => n.base.emissive(),
+      MaterialType::DiffuseLight(d) => !d.emit.near_zero(),
+      _ => false
+    }
+  }
+
+  // which kind of material this is, normal mapping keeps the id of the base
+  pub fn id(&self) -> u32 {
+    match self {
+      MaterialType::Metal(_)      => 1,
+      MaterialType::Lambertian(_) => 2,
+      MaterialType::Dielectric(_) => 3,
+      MaterialType::Isotropic(_)  => 4,
+      MaterialType::HenyeyGreenstein(_) => 5,
+      MaterialType::Conductor(_)  => 6,
+      MaterialType::RoughDielectric(_) => 7,
+      MaterialType::Principled(_) => 8,
+      MaterialType::Mix(_)        => 9,
+      MaterialType::Coated(_)     => 10,
+      MaterialType::NormalMapped(n) => n.base.id(),
+      MaterialType::Subsurface(_) => 11,
+      MaterialType::DiffuseLight(_) => 12
+    }
+  }
+}
+
+// the part of a bsdf a scattered ray was sampled from. scattering in volumes
+// counts as diffuse, it spreads light out the same way
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lobe {
+  Diffuse,
+  Specular,
+  Transmission
+}
+
+// a sampled continuation of a path: the throughput weight of the new ray and its lobe
+pub struct Scatter {
+  pub attenuation: Color,
+  pub ray: Ray,
+  pub lobe: Lobe
+}
+
+// a bsdf or phase function evaluated for a given scattered direction: f is the
+// value times the cosine toward that direction, pdf the solid angle density scatter
+// picks it with
+#[derive(Clone, Copy, Debug)]
+pub struct Evaluation {
+  pub f: Color,
+  pub pdf: f64
+}
+
+impl Evaluation {
+  pub fn zero() -> Self {
+    Self {f: Color::new(0.0, 0.0, 0.0), pdf: 0.0}
+  }
+}
+
+impl Scatter {
+  pub fn new(attenuation: Color, ray: Ray, lobe: Lobe) -> Self {
+    Self {attenuation, ray, lobe}
+  }
+
+  // reflected or transmitted depending on which side of the surface the ray leaves on
+  fn specular_or_transmitted(attenuation: Color, ray: Ray, rec: &HitRecord) -> Self {
+    let lobe = if Vector3::dot(ray.direction, rec.normal) > 0.0 {Lobe::Specular} else {Lobe::Transmission};
+    Self {attenuation, ray, lobe}
+  }
+
+  fn weighted(self, weight: Color) -> Self {
+    Self {attenuation: weight * self.attenuation, ..self}
+  }
 }
 
 impl Material for MaterialType {
-  fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+  fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
+    let scatter = match self {
+      MaterialType::Metal(m)      => m.scatter(r_in, rec, sampler),
+      MaterialType::Lambertian(l) => l.scatter(r_in, rec, sampler),
+      MaterialType::Dielectric(d) => d.scatter(r_in, rec, sampler),
+      MaterialType::Isotropic(i)  => i.scatter(r_in, rec, sampler),
+      MaterialType::HenyeyGreenstein(h) => h.scatter(r_in, rec, sampler),
+      MaterialType::Conductor(c)  => c.scatter(r_in, rec, sampler),
+      MaterialType::RoughDielectric(d) => d.scatter(r_in, rec, sampler),
+      MaterialType::Principled(p) => p.scatter(r_in, rec, sampler),
+      MaterialType::Mix(m)        => m.scatter(r_in, rec, sampler),
+      MaterialType::Coated(c)     => c.scatter(r_in, rec, sampler),
+      MaterialType::NormalMapped(n) => n.scatter(r_in, rec, sampler),
+      MaterialType::Subsurface(s) => s.scatter(r_in, rec, sampler),
+      MaterialType::DiffuseLight(d) => d.scatter(r_in, rec, sampler),
+    }?;
+    // a direction on different sides of the shading and the geometric normal
+    // would cross the real surface, which shows up as light leaking through it
+    let d = scatter.ray.direction;
+    if (Vector3::dot(d, rec.normal) > 0.0) != (Vector3::dot(d, rec.shading_normal) > 0.0) {
+      return None;
+    }
+    Some(scatter)
+  }
+
+  fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vector3) -> Option<Evaluation> {
+    let evaluation = match self {
+      MaterialType::Lambertian(l) => l.eval(r_in, rec, direction),
+      MaterialType::Isotropic(i)  => i.eval(r_in, rec, direction),
+      MaterialType::HenyeyGreenstein(h) => h.eval(r_in, rec, direction),
+      MaterialType::Conductor(c)  => c.eval(r_in, rec, direction),
+      MaterialType::Principled(p) => p.eval(r_in, rec, direction),
+      MaterialType::Mix(m)        => m.eval(r_in, rec, direction),
+      MaterialType::Coated(c)     => c.eval(r_in, rec, direction),
+      MaterialType::NormalMapped(n) => n.eval(r_in, rec, direction),
+      _ => None
+    }?;
+    // the same directions scatter turns down carry nothing
+    if (Vector3::dot(direction, rec.normal) > 0.0) != (Vector3::dot(direction, rec.shading_normal) > 0.0) {
+      return Some(Evaluation::zero());
+    }
+    Some(evaluation)
+  }
+
+  fn emitted(&self, rec: &HitRecord) -> Color {
     match self {
-      MaterialType::Metal(m)      => m.scatter(r_in, rec),
-      MaterialType::Lambertian(l) => l.scatter(r_in, rec),
-      MaterialType::Dielectric(d) => d.scatter(r_in, rec),
+      MaterialType::HenyeyGreenstein(h) => h.emitted(rec),
+      MaterialType::Principled(p) => p.emitted(rec),
+      MaterialType::Mix(m)        => m.emitted(rec),
+      MaterialType::Coated(c)     => c.emitted(rec),
+      MaterialType::NormalMapped(n) => n.emitted(rec),
+      MaterialType::DiffuseLight(d) => d.emitted(rec),
+      _ => Color::new(0.0, 0.0, 0.0)
     }
   }
 }
@@ -21,7 +207,18 @@ impl Material for MaterialType {
 
 
 pub trait Material {
-  fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>;
+  // sample values come from the sampler so low discrepancy sequences reach the bsdfs too
+  fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter>;
+
+  // None for materials that can only be sampled, mirrors and glass among them,
+  // the integrators that sample lights leave those to scatter
+  fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vector3) -> Option<Evaluation> {
+    None
+  }
+
+  fn emitted(&self, _rec: &HitRecord) -> Color {
+    Color::new(0.0, 0.0, 0.0)
+  }
 }
 
 
@@ -39,14 +236,47 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-  fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-    let mut scattered_direction = rec.normal + Vector3::random_unit_vector();
+  fn scatter(&self, _r_in: &Ray, rec: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
+    let mut scattered_direction = rec.shading_normal + Vector3::sample_unit_vector(sampler.get_2d());
     if scattered_direction.near_zero() {
-      scattered_direction = rec.normal;
+      scattered_direction = rec.shading_normal;
     }
     let scattered = Ray::new(rec.p, scattered_direction);
     let attenuation = self.albedo;
-    Some((attenuation, scattered))
+    Some(Scatter::new(attenuation, scattered, Lobe::Diffuse))
+  }
+
+  fn eval(&self, _r_in: &Ray, rec: &HitRecord, direction: Vector3) -> Option<Evaluation> {
+    let cos = Vector3::dot(Vector3::unit_vector(&direction), rec.shading_normal);
+    if cos <= 0.0 {
+      return Some(Evaluation::zero());
+    }
+    Some(Evaluation {f: self.albedo * (cos / std::f64::consts::PI), pdf: cos / std::f64::consts::PI})
+  }
+}
+
+
+
+
+// gives off light from its front side and reflects nothing, the lamps of a room
+#[derive(Clone, Copy)]
+pub struct DiffuseLight {
+  emit: Color
+}
+
+impl DiffuseLight {
+  pub fn new(emit: Color) -> Self {
+    Self {emit}
+  }
+}
+
+impl Material for DiffuseLight {
+  fn scatter(&self, _r_in: &Ray, _rec: &HitRecord, _sampler: &mut SamplerType) -> Option<Scatter> {
+    None
+  }
+
+  fn emitted(&self, rec: &HitRecord) -> Color {
+    if rec.front_face {self.emit} else {Color::new(0.0, 0.0, 0.0)}
   }
 }
 
@@ -69,13 +299,13 @@ impl Metal {
 }
 
 impl Material for Metal {
-  fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-    let mut reflected = Vector3::reflect(r_in.direction, rec.normal);
-    reflected = Vector3::unit_vector(&reflected) + (self.fuzz * Vector3::random_unit_vector());
+  fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
+    let mut reflected = Vector3::reflect(r_in.direction, rec.shading_normal);
+    reflected = Vector3::unit_vector(&reflected) + (self.fuzz * Vector3::sample_unit_vector(sampler.get_2d()));
     let scattered = Ray::new(rec.p, reflected);
     let attenuation = self.albedo;
-    if Vector3::dot(scattered.direction, rec.normal) > 0.0 {
-      Some((attenuation, scattered))
+    if Vector3::dot(scattered.direction, rec.shading_normal) > 0.0 {
+      Some(Scatter::new(attenuation, scattered, Lobe::Specular))
     } else {
       None
     }
@@ -88,14 +318,109 @@ impl Material for Metal {
 
 
 
+// rough metal with a GGX microfacet distribution and Smith masking-shadowing,
+// reflectance comes from the complex index of refraction n + ik of the metal
+#[derive(Clone, Copy)]
+pub struct Conductor {
+  eta: Color,
+  k: Color,
+  distribution: TrowbridgeReitz
+}
+
+impl Conductor {
+  pub fn new(eta: Color, k: Color, roughness: f64) -> Self {
+    Self {
+      eta,
+      k,
+      distribution: TrowbridgeReitz::from_roughness(roughness)
+    }
+  }
+
+  // n and k sampled at roughly 650, 550 and 450 nm for the r, g and b channels
+
+  pub fn gold(roughness: f64) -> Self {
+    Self::new(Color::new(0.143, 0.375, 1.442), Color::new(3.983, 2.386, 1.603), roughness)
+  }
+
+  pub fn copper(roughness: f64) -> Self {
+    Self::new(Color::new(0.200, 0.924, 1.102), Color::new(3.913, 2.453, 2.142), roughness)
+  }
+
+  pub fn aluminium(roughness: f64) -> Self {
+    Self::new(Color::new(1.490, 0.960, 0.620), Color::new(7.820, 6.690, 5.470), roughness)
+  }
+
+  pub fn silver(roughness: f64) -> Self {
+    Self::new(Color::new(0.155, 0.117, 0.138), Color::new(4.828, 3.122, 2.147), roughness)
+  }
+}
+
+impl Material for Conductor {
+  fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
+    let frame = Onb::new(rec.shading_normal);
+    let wo = frame.to_local(-Vector3::unit_vector(&r_in.direction));
+    if wo.z <= 0.0 {
+      return None;
+    }
+    if self.distribution.effectively_smooth() {
+      let wi = Vector3::new(-wo.x, -wo.y, wo.z);
+      let attenuation = fresnel::conductor(wo.z, self.eta, self.k);
+      return Some(Scatter::new(attenuation, Ray::new(rec.p, frame.to_world(wi)), Lobe::Specular));
+    }
+    // with visible normal sampling the estimator weight reduces to F * G2 / G1
+    let wm = self.distribution.sample_visible(wo, sampler.get_2d());
+    let wi = Vector3::reflect(-wo, wm);
+    if wi.z <= 0.0 {
+      return None;
+    }
+    let attenuation = fresnel::conductor(Vector3::dot(wo, wm), self.eta, self.k)
+                    * (self.distribution.g(wo, wi) / self.distribution.g1(wo));
+    Some(Scatter::new(attenuation, Ray::new(rec.p, frame.to_world(wi)), Lobe::Specular))
+  }
+
+  // F D G / (4 cos_o cos_i) times cos_i, and the density of visible normal sampling
+  // moved from the half vector to the reflected direction
+  fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vector3) -> Option<Evaluation> {
+    if self.distribution.effectively_smooth() {
+      return None;
+    }
+    let frame = Onb::new(rec.shading_normal);
+    let wo = frame.to_local(-Vector3::unit_vector(&r_in.direction));
+    let wi = frame.to_local(Vector3::unit_vector(&direction));
+    if wo.z <= 0.0 || wi.z <= 0.0 {
+      return Some(Evaluation::zero());
+    }
+    let wm = Vector3::unit_vector(&(wo + wi));
+    let d = self.distribution.d(wm);
+    let f = fresnel::conductor(Vector3::dot(wo, wm), self.eta, self.k) * (d * self.distribution.g(wo, wi) / (4.0 * wo.z));
+    Some(Evaluation {f, pdf: self.distribution.g1(wo) * d / (4.0 * wo.z)})
+  }
+}
+
+
+
+
 #[derive(Clone, Copy)]
 pub struct Dielectric {
-  refraction_index: f64
+  refraction_index: f64,
+  dispersion: Option<Dispersion>
 }
 
 impl Dielectric {
   pub fn new(refraction_index: f64) -> Self {
-    Self {refraction_index}
+    Self {refraction_index, dispersion: None}
+  }
+
+  pub fn dispersive(dispersion: Dispersion) -> Self {
+    Self {refraction_index: dispersion.ior_d(), dispersion: Some(dispersion)}
+  }
+
+  // the index at the wavelength the ray carries, the fixed one when rendering in rgb
+  fn refraction_index(refraction_index: f64, dispersion: Option<Dispersion>, r_in: &Ray) -> f64 {
+    match (dispersion, r_in.wavelength) {
+      (Some(d), Some(lambda)) => d.ior(lambda),
+      _ => refraction_index
+    }
   }
 
   pub fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
@@ -106,23 +431,1006 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-  fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+  fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
     let attenuation = Color::new(1.0, 1.0, 1.0);
+    let refraction_index = Self::refraction_index(self.refraction_index, self.dispersion, r_in);
     let ri = if rec.front_face {
-      1.0 / self.refraction_index
+      1.0 / refraction_index
     } else {
-      self.refraction_index
+      refraction_index
     };
     let unit_direction = Vector3::unit_vector(&r_in.direction);
-    let cos_theta = 1.0f64.min(Vector3::dot(-unit_direction, rec.normal));
+    let cos_theta = 1.0f64.min(Vector3::dot(-unit_direction, rec.shading_normal));
     let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
     let cannot_refract = ri * sin_theta > 1.0;
-    let direction = if cannot_refract || Self::reflectance(cos_theta, ri) > rand::random() {
-      Vector3::reflect(unit_direction, rec.normal)
+    let direction = if cannot_refract || Self::reflectance(cos_theta, ri) > sampler.get_1d() {
+      Vector3::reflect(unit_direction, rec.shading_normal)
     } else {
-      Vector3::refract(unit_direction, rec.normal, ri)
+      Vector3::refract(unit_direction, rec.shading_normal, ri)
     };
     let scattered = Ray::new(rec.p, direction);
-    Some((attenuation, scattered))
+    Some(Scatter::specular_or_transmitted(attenuation, scattered, rec))
+  }
+}
+
+
+
+
+// frosted glass, a GGX microfacet interface that both reflects and transmits,
+// with Beer-Lambert absorption for light travelling inside the medium
+#[derive(Clone, Copy)]
+pub struct RoughDielectric {
+  refraction_index: f64,
+  dispersion: Option<Dispersion>,
+  distribution: TrowbridgeReitz,
+  absorption: Color
+}
+
+impl RoughDielectric {
+  pub fn new(refraction_index: f64, roughness: f64) -> Self {
+    Self::tinted(refraction_index, roughness, Color::new(1.0, 1.0, 1.0), 1.0)
+  }
+
+  // tint is the color white light takes on after travelling distance inside the medium,
+  // without a distance to take it on over the glass doesn't absorb
+  pub fn tinted(refraction_index: f64, roughness: f64, tint: Color, distance: f64) -> Self {
+    let coefficient = |c: f64| if distance > 0.0 {-c.clamp(1e-6, 1.0).ln() / distance} else {0.0};
+    Self {
+      refraction_index,
+      dispersion: None,
+      distribution: TrowbridgeReitz::from_roughness(roughness),
+      absorption: Color::new(coefficient(tint.x), coefficient(tint.y), coefficient(tint.z))
+    }
+  }
+
+  pub fn dispersive(dispersion: Dispersion, roughness: f64) -> Self {
+    Self {
+      dispersion: Some(dispersion),
+      ..Self::new(dispersion.ior_d(), roughness)
+    }
+  }
+
+  fn transmittance(&self, distance: f64) -> Color {
+    Color::new(
+      (-self.absorption.x * distance).exp(),
+      (-self.absorption.y * distance).exp(),
+      (-self.absorption.z * distance).exp()
+    )
+  }
+}
+
+impl Material for RoughDielectric {
+  fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
+    // hitting the back face means the ray has just crossed the medium
+    let absorbed = if rec.front_face {
+      Color::new(1.0, 1.0, 1.0)
+    } else {
+      self.transmittance(rec.t * r_in.direction.length())
+    };
+    if self.distribution.effectively_smooth() {
+      return Dielectric {refraction_index: self.refraction_index, dispersion: self.dispersion}
+        .scatter(r_in, rec, sampler)
+        .map(|scatter| scatter.weighted(absorbed));
+    }
+    let refraction_index = Dielectric::refraction_index(self.refraction_index, self.dispersion, r_in);
+    let eta = if rec.front_face {refraction_index} else {1.0 / refraction_index};
+    let frame = Onb::new(rec.shading_normal);
+    let wo = frame.to_local(-Vector3::unit_vector(&r_in.direction));
+    if wo.z <= 0.0 {
+      return None;
+    }
+    // pick reflection or transmission by the exact fresnel term of the sampled microfacet,
+    // the selection probability cancels it out of the weight, leaving G2 / G1 for both lobes
+    let wm = self.distribution.sample_visible(wo, sampler.get_2d());
+    let reflectance = fresnel::dielectric(Vector3::dot(wo, wm), eta);
+    let wi = if sampler.get_1d() < reflectance {
+      let wi = Vector3::reflect(-wo, wm);
+      if wi.z <= 0.0 {
+        return None;
+      }
+      wi
+    } else {
+      let wi = Vector3::refract(-wo, wm, 1.0 / eta);
+      if wi.z >= 0.0 {
+        return None;
+      }
+      wi
+    };
+    let attenuation = absorbed * (self.distribution.g(wo, wi) / self.distribution.g1(wo));
+    Some(Scatter::specular_or_transmitted(attenuation, Ray::new(rec.p, frame.to_world(wi)), rec))
+  }
+}
+
+
+
+#[derive(Clone, Copy)]
+pub struct Isotropic {
+  albedo: Color
+}
+
+impl Isotropic {
+  pub fn new(albedo: Color) -> Self {
+    Self {albedo}
+  }
+}
+
+impl Material for Isotropic {
+  fn scatter(&self, _r_in: &Ray, rec: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
+    let scattered = Ray::new(rec.p, Vector3::sample_unit_vector(sampler.get_2d()));
+    Some(Scatter::new(self.albedo, scattered, Lobe::Diffuse))
   }
-}
\ No newline at end of file
+
+  fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vector3) -> Option<Evaluation> {
+    let pdf = 1.0 / (4.0 * std::f64::consts::PI);
+    Some(Evaluation {f: self.albedo * pdf, pdf})
+  }
+}
+
+
+
+
+// anisotropic phase function, g > 0 scatters forward, g < 0 backward, g = 0 is isotropic
+#[derive(Clone, Copy)]
+pub struct HenyeyGreenstein {
+  albedo: Color,
+  g: f64,
+  emission: Color
+}
+
+impl HenyeyGreenstein {
+  pub fn new(albedo: Color, g: f64) -> Self {
+    Self::emissive(albedo, g, Color::new(0.0, 0.0, 0.0))
+  }
+
+  pub fn emissive(albedo: Color, g: f64, emission: Color) -> Self {
+    Self {
+      albedo,
+      g: g.clamp(-0.99, 0.99),
+      emission
+    }
+  }
+
+  // density over the sphere of scattering by an angle with cosine cos_theta
+  pub fn phase(g: f64, cos_theta: f64) -> f64 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * std::f64::consts::PI * denom * denom.sqrt())
+  }
+
+  // cosine of the angle between the incoming and scattered directions
+  pub fn sample_cos_theta(g: f64, u: f64) -> f64 {
+    if g.abs() < 1e-3 {
+      return 1.0 - 2.0 * u;
+    }
+    let sqr_term = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+    ((1.0 + g * g - sqr_term * sqr_term) / (2.0 * g)).clamp(-1.0, 1.0)
+  }
+}
+
+impl Material for HenyeyGreenstein {
+  fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
+    let (u1, u2) = sampler.get_2d();
+    let cos_theta = Self::sample_cos_theta(self.g, u1);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * u2;
+    let frame = Onb::new(r_in.direction);
+    let direction = frame.to_world(Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+    Some(Scatter::new(self.albedo, Ray::new(rec.p, direction), Lobe::Diffuse))
+  }
+
+  fn eval(&self, r_in: &Ray, _rec: &HitRecord, direction: Vector3) -> Option<Evaluation> {
+    let cos_theta = Vector3::dot(Vector3::unit_vector(&r_in.direction), Vector3::unit_vector(&direction));
+    let pdf = Self::phase(self.g, cos_theta);
+    Some(Evaluation {f: self.albedo * pdf, pdf})
+  }
+
+  fn emitted(&self, _rec: &HitRecord) -> Color {
+    self.emission
+  }
+}
+
+
+
+
+
+// one material with disney-style sliders, every parameter can be textured
+#[derive(Clone)]
+pub struct Principled {
+  pub base_color: TextureType,
+  pub metallic: ScalarTexture,
+  pub roughness: ScalarTexture,
+  pub specular: ScalarTexture,
+  pub specular_tint: ScalarTexture,
+  pub sheen: ScalarTexture,
+  pub clearcoat: ScalarTexture,
+  pub clearcoat_gloss: ScalarTexture,
+  pub transmission: ScalarTexture,
+  pub ior: ScalarTexture,
+  pub emission: TextureType
+}
+
+// the core glTF 2.0 metallic-roughness material plus the ior, transmission
+// and emissive values, textures are multiplied by their factors like in glTF
+pub struct GltfMetallicRoughness {
+  pub base_color_factor: Color,
+  pub base_color_texture: Option<Arc<ImageTexture>>,
+  pub metallic_factor: f64,
+  pub roughness_factor: f64,
+  pub metallic_roughness_texture: Option<Arc<ImageTexture>>,
+  pub emissive_factor: Color,
+  pub emissive_texture: Option<Arc<ImageTexture>>,
+  pub ior: f64,
+  pub transmission_factor: f64
+}
+
+struct PrincipledLobes {
+  base_color: Color,
+  metallic: f64,
+  roughness: f64,
+  transmission: f64,
+  clearcoat: f64,
+  distribution: TrowbridgeReitz,
+  coat: TrowbridgeReitz,
+  f0: Color,
+  sheen: Color
+}
+
+impl PrincipledLobes {
+  fn clearcoat_probability(&self, wo: Vector3) -> f64 {
+    self.clearcoat * fresnel::dielectric(wo.z, 1.5)
+  }
+
+  fn specular_probability(&self, wo: Vector3) -> f64 {
+    Principled::schlick(self.f0, wo.z).luminance().clamp(0.0, 1.0)
+  }
+
+  // burley diffuse with retro-reflection at grazing angles plus sheen, as the weight of a
+  // cosine sampled wi. the retro-reflection is renormalized like frostbite does, burley
+  // alone reflects up to 1.51 times the light it gets at grazing angles when it is rough
+  fn diffuse(&self, wo: Vector3, wi: Vector3) -> Color {
+    let half = Vector3::unit_vector(&(wi + wo));
+    let cos_d = Vector3::dot(wi, half);
+    let energy_bias = 0.5 * self.roughness;
+    let energy_factor = 1.0 + self.roughness * (1.0 / 1.51 - 1.0);
+    let fd90 = energy_bias + 2.0 * self.roughness * cos_d * cos_d;
+    let fd = (1.0 + (fd90 - 1.0) * Principled::schlick_weight(wi.z))
+           * (1.0 + (fd90 - 1.0) * Principled::schlick_weight(wo.z))
+           * energy_factor;
+    fd * self.base_color + std::f64::consts::PI * Principled::schlick_weight(cos_d) * self.sheen
+  }
+}
+
+impl Principled {
+  pub fn new(base_color: TextureType) -> Self {
+    Self {
+      base_color,
+      metallic: ScalarTexture::constant(0.0),
+      roughness: ScalarTexture::constant(0.5),
+      specular: ScalarTexture::constant(0.5),
+      specular_tint: ScalarTexture::constant(0.0),
+      sheen: ScalarTexture::constant(0.0),
+      clearcoat: ScalarTexture::constant(0.0),
+      clearcoat_gloss: ScalarTexture::constant(1.0),
+      transmission: ScalarTexture::constant(0.0),
+      ior: ScalarTexture::constant(1.5),
+      emission: TextureType::Solid(Color::new(0.0, 0.0, 0.0))
+    }
+  }
+
+  // metallic comes from the blue channel and roughness from the green channel of
+  // the metallic-roughness texture, specular is chosen so the dielectric F0 matches the ior
+  pub fn from_gltf(gltf: &GltfMetallicRoughness) -> Self {
+    let factored = |factor: Color, texture: &Option<Arc<ImageTexture>>| match texture {
+      Some(t) => TextureType::Scaled(Arc::new(TextureType::Image(Arc::clone(t))), factor),
+      None    => TextureType::Solid(factor)
+    };
+    let scalar = |factor: f64, channel: usize| ScalarTexture::channel(
+      factored(Color::new(factor, factor, factor), &gltf.metallic_roughness_texture),
+      channel
+    );
+    let f0 = ((gltf.ior - 1.0) / (gltf.ior + 1.0)).powi(2);
+    Self {
+      metallic: scalar(gltf.metallic_factor, 2),
+      roughness: scalar(gltf.roughness_factor, 1),
+      specular: ScalarTexture::constant(f0 / 0.08),
+      transmission: ScalarTexture::constant(gltf.transmission_factor),
+      ior: ScalarTexture::constant(gltf.ior),
+      emission: factored(gltf.emissive_factor, &gltf.emissive_texture),
+      ..Self::new(factored(gltf.base_color_factor, &gltf.base_color_texture))
+    }
+  }
+
+  // the texture values at a hit, scatter and eval have to agree on them
+  fn lobes(&self, rec: &HitRecord) -> PrincipledLobes {
+    let (u, v, p) = (rec.u, rec.v, rec.p);
+    let white = Color::new(1.0, 1.0, 1.0);
+    let base_color = self.base_color.value(u, v, p);
+    let roughness = self.roughness.value(u, v, p).clamp(0.0, 1.0);
+    // gloss 0 is a satin coat, gloss 1 a polished one
+    let gloss = self.clearcoat_gloss.value(u, v, p).clamp(0.0, 1.0);
+    let tint = if base_color.luminance() > 0.0 {base_color / base_color.luminance()} else {white};
+    let specular_tint = self.specular_tint.value(u, v, p).clamp(0.0, 1.0);
+    PrincipledLobes {
+      base_color,
+      metallic: self.metallic.value(u, v, p).clamp(0.0, 1.0),
+      roughness,
+      transmission: self.transmission.value(u, v, p).clamp(0.0, 1.0),
+      clearcoat: self.clearcoat.value(u, v, p).clamp(0.0, 1.0),
+      distribution: TrowbridgeReitz::from_roughness(roughness),
+      coat: TrowbridgeReitz::from_roughness(0.3 * (1.0 - gloss)),
+      f0: 0.08 * self.specular.value(u, v, p).max(0.0) * Self::mix(white, tint, specular_tint),
+      sheen: self.sheen.value(u, v, p).max(0.0) * Self::mix(white, tint, 0.5)
+    }
+  }
+
+
+  fn schlick_weight(cos: f64) -> f64 {
+    (1.0 - cos.clamp(0.0, 1.0)).powi(5)
+  }
+
+  fn schlick(f0: Color, cos: f64) -> Color {
+    let white = Color::new(1.0, 1.0, 1.0);
+    f0 + Self::schlick_weight(cos) * (white - f0)
+  }
+
+  fn mix(a: Color, b: Color, t: f64) -> Color {
+    (1.0 - t) * a + t * b
+  }
+
+  // reflection off a ggx lobe, the weight is F * G2 / G1 for visible normal sampling
+  fn sample_reflection(distribution: &TrowbridgeReitz, wo: Vector3, f0: Color, sampler: &mut SamplerType) -> Option<(Color, Vector3)> {
+    let wm = distribution.sample_visible(wo, sampler.get_2d());
+    let wi = Vector3::reflect(-wo, wm);
+    if wi.z <= 0.0 {
+      return None;
+    }
+    let weight = Self::schlick(f0, Vector3::dot(wo, wm)) * (distribution.g(wo, wi) / distribution.g1(wo));
+    Some((weight, wi))
+  }
+}
+
+impl Material for Principled {
+  // the lobes are layered and picked stochastically: clearcoat on top, then either
+  // the metal, the transmissive or the opaque dielectric base
+  fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
+    let white = Color::new(1.0, 1.0, 1.0);
+    let lobes = self.lobes(rec);
+    let frame = Onb::new(rec.shading_normal);
+    let wo = frame.to_local(-Vector3::unit_vector(&r_in.direction));
+    if wo.z <= 0.0 {
+      return None;
+    }
+    let scattered = |lobe: Lobe| move |(weight, wi): (Color, Vector3)| Scatter::new(weight, Ray::new(rec.p, frame.to_world(wi)), lobe);
+
+    if sampler.get_1d() < lobes.clearcoat_probability(wo) {
+      // fresnel cancels with the selection probability
+      return Self::sample_reflection(&lobes.coat, wo, white, sampler).map(scattered(Lobe::Specular));
+    }
+
+    if sampler.get_1d() < lobes.metallic {
+      return Self::sample_reflection(&lobes.distribution, wo, lobes.base_color, sampler).map(scattered(Lobe::Specular));
+    }
+
+    if sampler.get_1d() < lobes.transmission {
+      let ior = self.ior.value(rec.u, rec.v, rec.p).max(1.0);
+      let eta = if rec.front_face {ior} else {1.0 / ior};
+      let wm = lobes.distribution.sample_visible(wo, sampler.get_2d());
+      let reflectance = fresnel::dielectric(Vector3::dot(wo, wm), eta);
+      let (tint, wi, lobe) = if sampler.get_1d() < reflectance {
+        (white, Vector3::reflect(-wo, wm), Lobe::Specular)
+      } else {
+        // tint once, on the way into the object
+        (if rec.front_face {lobes.base_color} else {white}, Vector3::refract(-wo, wm, 1.0 / eta), Lobe::Transmission)
+      };
+      if (wi.z > 0.0) != (Vector3::dot(wi, wm) * Vector3::dot(wo, wm) > 0.0) {
+        return None;
+      }
+      let weight = tint * (lobes.distribution.g(wo, wi) / lobes.distribution.g1(wo));
+      return Some(scattered(lobe)((weight, wi)));
+    }
+
+    let specular_probability = lobes.specular_probability(wo);
+    if sampler.get_1d() < specular_probability {
+      return Self::sample_reflection(&lobes.distribution, wo, lobes.f0, sampler)
+        .map(|(weight, wi)| (weight / specular_probability, wi))
+        .map(scattered(Lobe::Specular));
+    }
+
+    let mut wi = Vector3::new(0.0, 0.0, 1.0) + Vector3::sample_unit_vector(sampler.get_2d());
+    if wi.near_zero() {
+      wi = Vector3::new(0.0, 0.0, 1.0);
+    }
+    let wi = Vector3::unit_vector(&wi);
+    let transmitted = (white - Self::schlick(lobes.f0, wo.z)) / (1.0 - specular_probability).max(1e-6);
+    Some(scattered(Lobe::Diffuse)((transmitted * lobes.diffuse(wo, wi), wi)))
+  }
+
+  // scatter picks lobe k with probability p_k and weights it by w_k, so f is the sum
+  // of p_k w_k pdf_k. smooth lobes and transmission can only be sampled
+  fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vector3) -> Option<Evaluation> {
+    let white = Color::new(1.0, 1.0, 1.0);
+    let lobes = self.lobes(rec);
+    if lobes.transmission > 0.0 || lobes.distribution.effectively_smooth() || (lobes.clearcoat > 0.0 && lobes.coat.effectively_smooth()) {
+      return None;
+    }
+    let frame = Onb::new(rec.shading_normal);
+    let wo = frame.to_local(-Vector3::unit_vector(&r_in.direction));
+    let wi = frame.to_local(Vector3::unit_vector(&direction));
+    if wo.z <= 0.0 || wi.z <= 0.0 {
+      return Some(Evaluation::zero());
+    }
+    let wm = Vector3::unit_vector(&(wo + wi));
+    let reflection = |distribution: &TrowbridgeReitz, f0: Color| {
+      let d = distribution.d(wm) / (4.0 * wo.z);
+      (Self::schlick(f0, Vector3::dot(wo, wm)) * (d * distribution.g(wo, wi)), d * distribution.g1(wo))
+    };
+    let clearcoat = lobes.clearcoat_probability(wo);
+    let metallic = (1.0 - clearcoat) * lobes.metallic;
+    let dielectric = (1.0 - clearcoat) * (1.0 - lobes.metallic);
+    let specular_probability = lobes.specular_probability(wo);
+    let (coat_f, coat_pdf) = reflection(&lobes.coat, white);
+    let (metal_f, metal_pdf) = reflection(&lobes.distribution, lobes.base_color);
+    let (specular_f, specular_pdf) = reflection(&lobes.distribution, lobes.f0);
+    let transmitted = (white - Self::schlick(lobes.f0, wo.z)) * ((1.0 - specular_probability) / (1.0 - specular_probability).max(1e-6));
+    let diffuse_pdf = wi.z / std::f64::consts::PI;
+    let f = clearcoat * coat_f + metallic * metal_f + dielectric * (specular_f + diffuse_pdf * transmitted * lobes.diffuse(wo, wi));
+    let pdf = clearcoat * coat_pdf + metallic * metal_pdf
+            + dielectric * (specular_probability * specular_pdf + (1.0 - specular_probability) * diffuse_pdf);
+    Some(Evaluation {f, pdf})
+  }
+
+  fn emitted(&self, rec: &HitRecord) -> Color {
+    self.emission.value(rec.u, rec.v, rec.p)
+  }
+}
+
+
+
+
+
+// blends two materials, weight 0 is all first and 1 is all second
+#[derive(Clone)]
+pub struct Mix {
+  first: Arc<MaterialType>,
+  second: Arc<MaterialType>,
+  weight: ScalarTexture
+}
+
+impl Mix {
+  pub fn new(first: MaterialType, second: MaterialType, weight: ScalarTexture) -> Self {
+    Self {
+      first: Arc::new(first),
+      second: Arc::new(second),
+      weight
+    }
+  }
+
+  fn weight(&self, rec: &HitRecord) -> f64 {
+    self.weight.value(rec.u, rec.v, rec.p).clamp(0.0, 1.0)
+  }
+}
+
+impl Material for Mix {
+  // picking one of the two stochastically with the blend weight as probability
+  // averages to the blended result without any extra weighting
+  fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
+    if sampler.get_1d() < self.weight(rec) {
+      self.second.scatter(r_in, rec, sampler)
+    } else {
+      self.first.scatter(r_in, rec, sampler)
+    }
+  }
+
+  // a direction can come from either, so only when both can be evaluated
+  fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vector3) -> Option<Evaluation> {
+    let (first, second) = (self.first.eval(r_in, rec, direction)?, self.second.eval(r_in, rec, direction)?);
+    let w = self.weight(rec);
+    Some(Evaluation {f: (1.0 - w) * first.f + w * second.f, pdf: (1.0 - w) * first.pdf + w * second.pdf})
+  }
+
+  fn emitted(&self, rec: &HitRecord) -> Color {
+    let w = self.weight(rec);
+    (1.0 - w) * self.first.emitted(rec) + w * self.second.emitted(rec)
+  }
+}
+
+
+
+
+// a dielectric clearcoat over any base material, like car paint or varnish
+#[derive(Clone)]
+pub struct Coated {
+  base: Arc<MaterialType>,
+  refraction_index: f64,
+  distribution: TrowbridgeReitz,
+  tint: Color
+}
+
+impl Coated {
+  pub fn new(base: MaterialType, refraction_index: f64, roughness: f64) -> Self {
+    Self::tinted(base, refraction_index, roughness, Color::new(1.0, 1.0, 1.0))
+  }
+
+  // tint is the color of light after crossing the coat once straight down
+  pub fn tinted(base: MaterialType, refraction_index: f64, roughness: f64, tint: Color) -> Self {
+    Self {
+      base: Arc::new(base),
+      refraction_index,
+      distribution: TrowbridgeReitz::from_roughness(roughness),
+      tint
+    }
+  }
+
+  // the incoming ray bent into the coat, which is what the base gets to see
+  fn refracted(&self, r_in: &Ray, frame: &Onb, wo: Vector3) -> Ray {
+    let direction = frame.to_world(Vector3::refract(-wo, Vector3::new(0.0, 0.0, 1.0), 1.0 / self.refraction_index));
+    Ray {direction, ..*r_in}
+  }
+
+  fn coat_transmittance(&self, cos_o: f64, cos_i: f64) -> Color {
+    let path = 1.0 / cos_o.abs().max(0.05) + 1.0 / cos_i.abs().max(0.05);
+    Color::new(self.tint.x.powf(path), self.tint.y.powf(path), self.tint.z.powf(path))
+  }
+}
+
+impl Material for Coated {
+  fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
+    let frame = Onb::new(rec.shading_normal);
+    let wo = frame.to_local(-Vector3::unit_vector(&r_in.direction));
+    if wo.z <= 0.0 {
+      return None;
+    }
+    // reflect off the coat with probability F, the selection cancels the fresnel term
+    if sampler.get_1d() < fresnel::dielectric(wo.z, self.refraction_index) {
+      let wi = if self.distribution.effectively_smooth() {
+        Vector3::new(-wo.x, -wo.y, wo.z)
+      } else {
+        let wm = self.distribution.sample_visible(wo, sampler.get_2d());
+        Vector3::reflect(-wo, wm)
+      };
+      if wi.z <= 0.0 {
+        return None;
+      }
+      let weight = if self.distribution.effectively_smooth() {
+        1.0
+      } else {
+        self.distribution.g(wo, wi) / self.distribution.g1(wo)
+      };
+      return Some(Scatter::new(Color::new(weight, weight, weight), Ray::new(rec.p, frame.to_world(wi)), Lobe::Specular));
+    }
+    // otherwise the base scatters the light that got through, and the light leaving
+    // it has to get through the coat again
+    let scatter = self.base.scatter(&self.refracted(r_in, &frame, wo), rec, sampler)?;
+    let cos_i = Vector3::dot(Vector3::unit_vector(&scatter.ray.direction), rec.shading_normal);
+    let exit = 1.0 - fresnel::dielectric(cos_i.abs(), self.refraction_index);
+    Some(scatter.weighted(exit * self.coat_transmittance(wo.z, cos_i)))
+  }
+
+  // the rough coat reflection plus the base seen through the coat,
+  // (1 - F_in) (1 - F_out) times the absorption in the coat
+  fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vector3) -> Option<Evaluation> {
+    if self.distribution.effectively_smooth() {
+      return None;
+    }
+    let frame = Onb::new(rec.shading_normal);
+    let wo = frame.to_local(-Vector3::unit_vector(&r_in.direction));
+    let wi = frame.to_local(Vector3::unit_vector(&direction));
+    let base = self.base.eval(&self.refracted(r_in, &frame, wo), rec, direction)?;
+    if wo.z <= 0.0 {
+      return Some(Evaluation::zero());
+    }
+    let enter = fresnel::dielectric(wo.z, self.refraction_index);
+    let exit = 1.0 - fresnel::dielectric(wi.z.abs(), self.refraction_index);
+    let through = (1.0 - enter) * exit * self.coat_transmittance(wo.z, wi.z) * base.f;
+    if wi.z <= 0.0 {
+      return Some(Evaluation {f: through, pdf: (1.0 - enter) * base.pdf});
+    }
+    let wm = Vector3::unit_vector(&(wo + wi));
+    let d = self.distribution.d(wm) / (4.0 * wo.z);
+    // scatter picks the coat with probability F_in, so F_in stands in for the fresnel term
+    let coat = enter * d * self.distribution.g(wo, wi);
+    Some(Evaluation {
+      f: Color::new(coat, coat, coat) + through,
+      pdf: enter * d * self.distribution.g1(wo) + (1.0 - enter) * base.pdf
+    })
+  }
+
+  fn emitted(&self, rec: &HitRecord) -> Color {
+    self.base.emitted(rec)
+  }
+}
+
+
+
+
+
+#[derive(Clone)]
+pub enum Perturbation {
+  // tangent space normals encoded as rgb, green points along +v
+  NormalMap(TextureType),
+  // grayscale height in [0, 1] scaled to world units
+  Bump(ScalarTexture, f64)
+}
+
+// tilts the shading normal of the base material, the geometric normal stays as it is
+#[derive(Clone)]
+pub struct NormalMapped {
+  base: Arc<MaterialType>,
+  perturbation: Perturbation
+}
+
+impl NormalMapped {
+  pub fn normal_map(base: MaterialType, map: TextureType) -> Self {
+    Self {base: Arc::new(base), perturbation: Perturbation::NormalMap(map)}
+  }
+
+  pub fn bump(base: MaterialType, height: ScalarTexture, scale: f64) -> Self {
+    Self {base: Arc::new(base), perturbation: Perturbation::Bump(height, scale)}
+  }
+
+  fn shade<'a>(&self, rec: &HitRecord<'a>) -> HitRecord<'a> {
+    let n = rec.outward_normal();
+    let tangent = Vector3::unit_vector(&rec.dpdu);
+    let bitangent = Vector3::cross(n, tangent);
+    let perturbed = match &self.perturbation {
+      Perturbation::NormalMap(map) => {
+        let c = map.value(rec.u, rec.v, rec.p);
+        let local = 2.0 * c - Color::new(1.0, 1.0, 1.0);
+        local.x * tangent + local.y * bitangent + local.z * n
+      },
+      Perturbation::Bump(height, scale) => {
+        // forward differences of the height field along u and v, the surface is
+        // displaced along the normal and the new normal is the cross of its tangents
+        let du = 1.0 / 2048.0;
+        let dv = 1.0 / 2048.0;
+        let h = height.value(rec.u, rec.v, rec.p);
+        let h_u = height.value(rec.u + du, rec.v, rec.p + du * rec.dpdu);
+        let h_v = height.value(rec.u, rec.v + dv, rec.p + dv * rec.dpdv);
+        let dpdu = rec.dpdu + (scale * (h_u - h) / du) * n;
+        let dpdv = rec.dpdv + (scale * (h_v - h) / dv) * n;
+        let cross = Vector3::cross(dpdu, dpdv);
+        if Vector3::dot(cross, n) < 0.0 {-cross} else {cross}
+      }
+    };
+    let mut shaded = rec.clone();
+    if !perturbed.near_zero() {
+      shaded.set_shading_normal(Vector3::unit_vector(&perturbed));
+    }
+    shaded
+  }
+}
+
+impl Material for NormalMapped {
+  fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
+    self.base.scatter(r_in, &self.shade(rec), sampler)
+  }
+
+  fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vector3) -> Option<Evaluation> {
+    self.base.eval(r_in, &self.shade(rec), direction)
+  }
+
+  fn emitted(&self, rec: &HitRecord) -> Color {
+    self.base.emitted(rec)
+  }
+}
+
+
+
+
+
+// the surface of a random walk subsurface object, see subsurface.rs. the interface is
+// a (rough) dielectric, inside the light scatters isotropically in a chromatic medium.
+// distances are sampled from one randomly picked color channel, the weights below
+// divide by the average pdf over all channels (spectral MIS) so every channel stays unbiased
+#[derive(Clone, Copy)]
+pub struct Subsurface {
+  interface: RoughDielectric,
+  sigma_t: Color,
+  albedo: Color
+}
+
+impl Subsurface {
+  // mean free path is the average distance between scattering events per channel,
+  // albedo the single scattering albedo per channel
+  pub fn new(refraction_index: f64, roughness: f64, albedo: Color, mean_free_path: Color) -> Self {
+    let coefficient = |mfp: f64| 1.0 / mfp.max(1e-6);
+    Self {
+      interface: RoughDielectric::new(refraction_index, roughness),
+      sigma_t: Color::new(coefficient(mean_free_path.x), coefficient(mean_free_path.y), coefficient(mean_free_path.z)),
+      albedo
+    }
+  }
+
+  pub fn sample_distance(&self, u_channel: f64, u: f64) -> f64 {
+    let sigma = match (u_channel * 3.0) as usize {
+      0 => self.sigma_t.x,
+      1 => self.sigma_t.y,
+      _ => self.sigma_t.z
+    };
+    -(1.0 - u).ln() / sigma
+  }
+
+  fn transmittance(&self, distance: f64) -> Color {
+    Color::new(
+      (-self.sigma_t.x * distance).exp(),
+      (-self.sigma_t.y * distance).exp(),
+      (-self.sigma_t.z * distance).exp()
+    )
+  }
+
+  fn average(c: Color) -> f64 {
+    (c.x + c.y + c.z) / 3.0
+  }
+
+  // throughput of a scattering event after travelling distance
+  pub fn collision_weight(&self, distance: f64) -> Color {
+    let tr = self.transmittance(distance);
+    let pdf = Self::average(self.sigma_t * tr);
+    if pdf <= 0.0 {
+      return Color::new(0.0, 0.0, 0.0);
+    }
+    (self.albedo * self.sigma_t * tr) / pdf
+  }
+
+  // throughput of reaching the boundary after travelling distance without scattering
+  pub fn exit_weight(&self, distance: f64) -> Color {
+    let tr = self.transmittance(distance);
+    let pdf = Self::average(tr);
+    if pdf <= 0.0 {
+      return Color::new(0.0, 0.0, 0.0);
+    }
+    tr / pdf
+  }
+}
+
+impl Material for Subsurface {
+  fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
+    let scatter = self.interface.scatter(r_in, rec, sampler)?;
+    if rec.front_face {
+      return Some(scatter);
+    }
+    // a back face hit ends a free flight through the medium
+    Some(scatter.weighted(self.exit_weight(rec.t * r_in.direction.length())))
+  }
+}
+
+
+#[cfg(test)]
+mod material_tests {
+  use super::*;
+  use std::borrow::Cow;
+  use crate::vector3::Point;
+
+  #[test]
+  fn test_tinted_glass_takes_on_the_tint_over_the_distance() {
+    let glass = RoughDielectric::tinted(1.5, 0.0, Color::new(0.2, 0.8, 0.4), 0.5);
+    let t = glass.transmittance(0.5);
+    assert!((t.x - 0.2).abs() < 1e-9 && (t.y - 0.8).abs() < 1e-9 && (t.z - 0.4).abs() < 1e-9);
+  }
+
+  #[test]
+  fn test_tinted_glass_without_a_distance_does_not_absorb() {
+    for tint in [Color::new(0.2, 0.8, 0.4), Color::new(1.0, 1.0, 1.0)] {
+      let glass = RoughDielectric::tinted(1.5, 0.0, tint, 0.0);
+      let t = glass.transmittance(3.0);
+      assert!(t.x == 1.0 && t.y == 1.0 && t.z == 1.0, "{:?}", t);
+    }
+  }
+
+  // a hit on the floor z = 0 by a ray coming in at the given cosine to the normal
+  fn floor_hit(cos: f64, material: MaterialType) -> (Ray, HitRecord<'static>) {
+    let direction = Vector3::new((1.0 - cos * cos).sqrt(), 0.0, -cos);
+    let ray = Ray::new(-direction, direction);
+    let rec = HitRecord::new(&ray, 1.0, Vector3::new(0.0, 0.0, 1.0), Cow::Owned(material));
+    (ray, rec)
+  }
+
+  #[test]
+  fn test_from_gltf_maps_the_channels() {
+    let path = std::env::temp_dir().join(format!("ray_test_gltf_metallic_roughness_{}.png", std::process::id()));
+    image::RgbImage::from_pixel(1, 1, image::Rgb([51, 153, 204])).save(&path).unwrap();
+    let texture = ImageTexture::load(&path, false).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let gltf = GltfMetallicRoughness {
+      base_color_factor: Color::new(1.0, 1.0, 1.0),
+      base_color_texture: None,
+      metallic_factor: 0.5,
+      roughness_factor: 1.0,
+      metallic_roughness_texture: Some(Arc::new(texture)),
+      emissive_factor: Color::new(0.0, 0.0, 0.0),
+      emissive_texture: None,
+      ior: 1.5,
+      transmission_factor: 0.0
+    };
+    let principled = Principled::from_gltf(&gltf);
+    let p = Point::new(0.0, 0.0, 0.0);
+    // metallic is in blue and roughness in green, both times their factors
+    assert!((principled.metallic.value(0.5, 0.5, p) - 0.5 * 0.8).abs() < 1e-6);
+    assert!((principled.roughness.value(0.5, 0.5, p) - 0.6).abs() < 1e-6);
+    // an ior of 1.5 reflects 4% head on, the default specular of 0.5
+    assert!((principled.specular.value(0.5, 0.5, p) - 0.5).abs() < 1e-9);
+  }
+
+  #[test]
+  fn test_white_principled_reflects_no_more_than_it_gets() {
+    let mut sampler = SamplerType::from_name("independent", 1, 7).unwrap();
+    let n = 50_000;
+    for (metallic, roughness, transmission, clearcoat) in [
+      (0.0, 0.05, 0.0, 0.0), (0.0, 0.5, 0.0, 0.0), (0.0, 1.0, 0.0, 0.0), (1.0, 0.5, 0.0, 0.0),
+      (0.0, 0.5, 1.0, 0.0), (0.0, 1.0, 0.0, 1.0)
+    ] {
+      let mut principled = Principled::new(TextureType::Solid(Color::new(1.0, 1.0, 1.0)));
+      principled.metallic = ScalarTexture::constant(metallic);
+      principled.roughness = ScalarTexture::constant(roughness);
+      principled.transmission = ScalarTexture::constant(transmission);
+      principled.clearcoat = ScalarTexture::constant(clearcoat);
+      for cos in [1.0, 0.5, 0.1, 0.02] {
+        let (ray, rec) = floor_hit(cos, MaterialType::Principled(Box::new(principled.clone())));
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+          sampler.start_pixel_sample(i, 0, 0);
+          if let Some(scatter) = rec.material.scatter(&ray, &rec, &mut sampler) {
+            sum += scatter.attenuation;
+          }
+        }
+        let albedo = sum / n as f64;
+        assert!(albedo.x.max(albedo.y).max(albedo.z) < 1.005, "{} {} {} {} at {}: {:?}", metallic, roughness, transmission, clearcoat, cos, albedo);
+      }
+    }
+  }
+
+  // scatter weights against f / pdf of eval for the direction scatter picked, the mean
+  // of both estimates the albedo. one lobe materials have to agree sample by sample
+  fn scatter_against_eval(material: MaterialType, single_lobe: bool) {
+    let mut sampler = SamplerType::from_name("independent", 1, 11).unwrap();
+    let n = 20_000;
+    for cos in [1.0, 0.6, 0.2] {
+      let (ray, rec) = floor_hit(cos, material.clone());
+      let (mut scattered, mut evaluated) = (Color::new(0.0, 0.0, 0.0), Color::new(0.0, 0.0, 0.0));
+      for i in 0..n {
+        sampler.start_pixel_sample(i, 0, 0);
+        let Some(scatter) = rec.material.scatter(&ray, &rec, &mut sampler) else {
+          continue;
+        };
+        let eval = rec.material.eval(&ray, &rec, scatter.ray.direction).unwrap();
+        assert!(eval.pdf > 0.0);
+        let weight = eval.f / eval.pdf;
+        if single_lobe {
+          assert!((weight - scatter.attenuation).length() < 1e-9, "{:?} {:?} at {}", weight, scatter.attenuation, cos);
+        }
+        scattered += scatter.attenuation;
+        evaluated += weight;
+      }
+      let (scattered, evaluated) = (scattered / n as f64, evaluated / n as f64);
+      assert!((scattered - evaluated).length() < 0.01, "{:?} {:?} at {}", scattered, evaluated, cos);
+    }
+  }
+
+  #[test]
+  fn test_scatter_weights_match_eval() {
+    scatter_against_eval(MaterialType::Lambertian(Lambertian::new(Color::new(0.8, 0.4, 0.2))), true);
+    scatter_against_eval(MaterialType::Conductor(Conductor::gold(0.4)), true);
+    let mut metal = Principled::new(TextureType::Solid(Color::new(0.9, 0.6, 0.3)));
+    metal.metallic = ScalarTexture::constant(1.0);
+    metal.roughness = ScalarTexture::constant(0.4);
+    scatter_against_eval(MaterialType::Principled(Box::new(metal)), true);
+    let mut layered = Principled::new(TextureType::Solid(Color::new(0.2, 0.5, 0.8)));
+    layered.metallic = ScalarTexture::constant(0.3);
+    layered.roughness = ScalarTexture::constant(0.6);
+    layered.sheen = ScalarTexture::constant(0.5);
+    layered.clearcoat = ScalarTexture::constant(0.7);
+    layered.clearcoat_gloss = ScalarTexture::constant(0.4);
+    scatter_against_eval(MaterialType::Principled(Box::new(layered)), false);
+    let varnish = |base: MaterialType| MaterialType::Coated(Coated::tinted(base, 1.5, 0.3, Color::new(0.9, 0.8, 0.7)));
+    scatter_against_eval(varnish(MaterialType::Lambertian(Lambertian::new(Color::new(0.8, 0.4, 0.2)))), false);
+    scatter_against_eval(varnish(MaterialType::Conductor(Conductor::copper(0.5))), false);
+  }
+
+  #[test]
+  fn test_coat_refracts_the_view_into_the_base() {
+    // a sharp base under a coat rough enough that its own reflection is spread thin
+    let copper = MaterialType::Conductor(Conductor::copper(0.1));
+    let (ray, rec) = floor_hit(0.5, MaterialType::Coated(Coated::new(copper, 1.5, 1.0)));
+    let at = |direction: Vector3| rec.material.eval(&ray, &rec, direction).unwrap().f.x;
+    // the base reflects most around the mirror direction of the ray bent into the coat
+    let inside = Vector3::refract(ray.direction, Vector3::new(0.0, 0.0, 1.0), 1.0 / 1.5);
+    let bent = Vector3::new(inside.x, inside.y, -inside.z);
+    let mirror = Vector3::new(ray.direction.x, ray.direction.y, -ray.direction.z);
+    assert!(at(bent) > 10.0 * at(mirror), "{} {}", at(bent), at(mirror));
+  }
+
+  #[test]
+  fn test_principled_with_smooth_or_transmissive_lobes_is_not_evaluable() {
+    let mut glass = Principled::new(TextureType::Solid(Color::new(1.0, 1.0, 1.0)));
+    glass.transmission = ScalarTexture::constant(0.5);
+    let mut coated = Principled::new(TextureType::Solid(Color::new(1.0, 1.0, 1.0)));
+    coated.clearcoat = ScalarTexture::constant(1.0);
+    for principled in [glass, coated] {
+      let (ray, rec) = floor_hit(0.5, MaterialType::Principled(Box::new(principled)));
+      assert!(rec.material.eval(&ray, &rec, Vector3::new(0.0, 0.0, 1.0)).is_none());
+    }
+  }
+
+  #[test]
+  fn test_mix_eval_is_the_weighted_sum() {
+    let lambertian = MaterialType::Lambertian(Lambertian::new(Color::new(0.8, 0.4, 0.2)));
+    let gold = MaterialType::Conductor(Conductor::gold(0.3));
+    let mix = MaterialType::Mix(Mix::new(lambertian.clone(), gold.clone(), ScalarTexture::constant(0.3)));
+    let (ray, rec) = floor_hit(0.7, mix);
+    for direction in [Vector3::new(0.7, 0.0, 0.7), Vector3::new(-0.2, 0.5, 0.8), Vector3::new(0.0, 0.0, 1.0)] {
+      let e = rec.material.eval(&ray, &rec, direction).unwrap();
+      let a = lambertian.eval(&ray, &rec, direction).unwrap();
+      let b = gold.eval(&ray, &rec, direction).unwrap();
+      let f = 0.7 * a.f + 0.3 * b.f;
+      assert!((e.f - f).length() < 1e-12, "{:?} {:?}", e.f, f);
+      assert!((e.pdf - (0.7 * a.pdf + 0.3 * b.pdf)).abs() < 1e-12);
+    }
+  }
+
+  #[test]
+  fn test_mix_picks_the_second_by_its_weight() {
+    let red = MaterialType::Lambertian(Lambertian::new(Color::new(1.0, 0.0, 0.0)));
+    let blue = MaterialType::Lambertian(Lambertian::new(Color::new(0.0, 0.0, 1.0)));
+    let (ray, rec) = floor_hit(0.7, MaterialType::Mix(Mix::new(red, blue, ScalarTexture::constant(0.3))));
+    let mut sampler = SamplerType::from_name("independent", 1, 5).unwrap();
+    let n = 100_000;
+    let second = (0..n).filter(|&i| {
+      sampler.start_pixel_sample(i, 0, 0);
+      rec.material.scatter(&ray, &rec, &mut sampler).unwrap().attenuation.z > 0.0
+    }).count();
+    assert!((second as f64 / n as f64 - 0.3).abs() < 0.005, "{}", second);
+  }
+
+  #[test]
+  fn test_clear_coat_over_white_reflects_no_more_than_it_gets() {
+    let mut sampler = SamplerType::from_name("independent", 1, 9).unwrap();
+    let n = 50_000;
+    for roughness in [0.0, 0.2, 0.6] {
+      let white = MaterialType::Lambertian(Lambertian::new(Color::new(1.0, 1.0, 1.0)));
+      let coated = MaterialType::Coated(Coated::tinted(white, 1.5, roughness, Color::new(1.0, 1.0, 1.0)));
+      for cos in [1.0, 0.5, 0.1] {
+        let (ray, rec) = floor_hit(cos, coated.clone());
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+          sampler.start_pixel_sample(i, 0, 0);
+          if let Some(scatter) = rec.material.scatter(&ray, &rec, &mut sampler) {
+            sum += scatter.attenuation;
+          }
+        }
+        let albedo = sum / n as f64;
+        assert!(albedo.x.max(albedo.y).max(albedo.z) < 1.005, "{} at {}: {:?}", roughness, cos, albedo);
+      }
+    }
+  }
+
+  #[test]
+  fn test_flat_normal_map_keeps_the_normal() {
+    let white = MaterialType::Lambertian(Lambertian::new(Color::new(1.0, 1.0, 1.0)));
+    let mapped = NormalMapped::normal_map(white.clone(), TextureType::Solid(Color::new(0.5, 0.5, 1.0)));
+    for cos in [1.0, 0.4] {
+      let (_, rec) = floor_hit(cos, white.clone());
+      let shaded = mapped.shade(&rec);
+      assert!((shaded.shading_normal - rec.normal).length() < 1e-12, "{:?}", shaded.shading_normal);
+    }
+  }
+
+  #[test]
+  fn test_directions_between_the_normals_are_dropped() {
+    let (ray, mut rec) = floor_hit(0.8, MaterialType::Lambertian(Lambertian::new(Color::new(1.0, 1.0, 1.0))));
+    rec.set_shading_normal(Vector3::unit_vector(&Vector3::new(1.0, 0.0, 1.0)));
+    // under the surface but above the tilted shading normal
+    let between = Vector3::new(1.0, 0.0, -0.3);
+    let eval = rec.material.eval(&ray, &rec, between).unwrap();
+    assert!(eval.f.near_zero() && eval.pdf == 0.0);
+    let mut sampler = SamplerType::from_name("independent", 1, 3).unwrap();
+    let mut dropped = 0;
+    for i in 0..10_000 {
+      sampler.start_pixel_sample(i, 0, 0);
+      match rec.material.scatter(&ray, &rec, &mut sampler) {
+        Some(scatter) => assert!(Vector3::dot(scatter.ray.direction, rec.normal) > 0.0),
+        None => dropped += 1
+      }
+    }
+    // the cosine lobe around the tilted normal puts some directions under the surface
+    assert!(dropped > 0);
+  }
+}
@@ -0,0 +1,128 @@
+use std::f64::consts::PI;
+
+use crate::vector3::Vector3;
+
+// Trowbridge-Reitz (GGX) distribution of microfacet normals, all directions are
+// in the local shading frame where the macro surface normal is +z
+#[derive(Clone, Copy)]
+pub struct TrowbridgeReitz {
+  alpha: f64
+}
+
+
+impl TrowbridgeReitz {
+  // perceptual roughness in [0, 1] is squared, the way most authoring tools expose it
+  pub fn from_roughness(roughness: f64) -> Self {
+    let roughness = roughness.clamp(0.0, 1.0);
+    Self {alpha: (roughness * roughness).max(1e-4)}
+  }
+
+  // below this the distribution is treated as a perfect mirror
+  pub fn effectively_smooth(&self) -> bool {
+    self.alpha < 1e-3
+  }
+
+  // density of microfacet normals, projected onto the macro surface it integrates to one
+  pub fn d(&self, wm: Vector3) -> f64 {
+    let cos2 = wm.z * wm.z;
+    if cos2 == 0.0 {
+      return 0.0;
+    }
+    let tan2 = (wm.x * wm.x + wm.y * wm.y) / cos2;
+    let alpha2 = self.alpha * self.alpha;
+    let e = 1.0 + tan2 / alpha2;
+    1.0 / (PI * alpha2 * cos2 * cos2 * e * e)
+  }
+
+  pub fn lambda(&self, w: Vector3) -> f64 {
+    let cos2 = w.z * w.z;
+    if cos2 == 0.0 {
+      return f64::INFINITY;
+    }
+    let tan2 = (w.x * w.x + w.y * w.y) / cos2;
+    ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0) / 2.0
+  }
+
+  // masking of a single direction
+  pub fn g1(&self, w: Vector3) -> f64 {
+    1.0 / (1.0 + self.lambda(w))
+  }
+
+  // height-correlated masking-shadowing of a pair of directions
+  pub fn g(&self, wo: Vector3, wi: Vector3) -> f64 {
+    1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+  }
+
+  // samples a microfacet normal from the distribution of normals visible from w,
+  // Heitz 2018, "Sampling the GGX Distribution of Visible Normals"
+  pub fn sample_visible(&self, w: Vector3, (u1, u2): (f64, f64)) -> Vector3 {
+    let mut wh = Vector3::unit_vector(&Vector3::new(self.alpha * w.x, self.alpha * w.y, w.z));
+    if wh.z < 0.0 {
+      wh = -wh;
+    }
+    let t1 = if wh.z < 0.99999 {
+      Vector3::unit_vector(&Vector3::cross(Vector3::new(0.0, 0.0, 1.0), wh))
+    } else {
+      Vector3::new(1.0, 0.0, 0.0)
+    };
+    let t2 = Vector3::cross(wh, t1);
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let px = r * phi.cos();
+    let mut py = r * phi.sin();
+    let h = (1.0 - px * px).sqrt();
+    let s = (1.0 + wh.z) / 2.0;
+    py = (1.0 - s) * h + s * py;
+    let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+    let nh = px * t1 + py * t2 + pz * wh;
+    Vector3::unit_vector(&Vector3::new(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(1e-6)))
+  }
+}
+
+
+
+#[cfg(test)]
+mod microfacet_tests {
+  use super::*;
+
+  #[test]
+  fn test_visible_normals_face_viewer() {
+    let distrib = TrowbridgeReitz::from_roughness(0.7);
+    let wo = Vector3::unit_vector(&Vector3::new(0.8, 0.1, 0.3));
+    for i in 0..16 {
+      for j in 0..16 {
+        let wm = distrib.sample_visible(wo, ((i as f64 + 0.5) / 16.0, (j as f64 + 0.5) / 16.0));
+        assert!((wm.length() - 1.0).abs() < 0.000001);
+        assert!(wm.z > 0.0);
+        assert!(Vector3::dot(wo, wm) >= -0.000001);
+      }
+    }
+  }
+
+  #[test]
+  fn test_masking_at_normal_incidence() {
+    let distrib = TrowbridgeReitz::from_roughness(0.5);
+    let n = Vector3::new(0.0, 0.0, 1.0);
+    assert!((distrib.g1(n) - 1.0).abs() < 0.000001);
+    assert!((distrib.g(n, n) - 1.0).abs() < 0.000001);
+  }
+
+  // the projected area of the microfacets is the area of the surface
+  #[test]
+  fn test_normals_cover_the_surface() {
+    let distrib = TrowbridgeReitz::from_roughness(0.6);
+    let n = 512;
+    let mut sum = 0.0;
+    for i in 0..n {
+      for j in 0..n {
+        // uniform over the hemisphere, density 1 / 2pi
+        let cos_theta = (i as f64 + 0.5) / n as f64;
+        let phi = 2.0 * PI * (j as f64 + 0.5) / n as f64;
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let wm = Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        sum += distrib.d(wm) * cos_theta * 2.0 * PI;
+      }
+    }
+    assert!((sum / (n * n) as f64 - 1.0).abs() < 0.01);
+  }
+}
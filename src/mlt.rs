@@ -0,0 +1,239 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::aov::{LightPaths, SurfaceInfo};
+use crate::camera::Projection;
+use crate::film::SplatBuffer;
+use crate::hittable::{Hittable, HittableList};
+use crate::integrator::{Integrator, NeePathTracer};
+use crate::interval::Interval;
+use crate::light::Lights;
+use crate::ray::Ray;
+use crate::sampler::{MetropolisSampler, Sampler, SamplerType};
+use crate::vector3::Color;
+
+// primary sample space metropolis (Kelemen et al) over nee paths. the chains run before
+// every pass and splat all the light, camera samples only look up the aov surface
+pub struct Metropolis {
+  path: NeePathTracer,
+  camera: Projection,
+  pub chains: u32,
+  // paths traced to estimate the brightness
+  pub bootstrap: u32,
+  seed: u64,
+  // mean luminance of all paths
+  brightness: f64,
+  states: Vec<Chain>,
+  pub splats: SplatBuffer
+}
+
+pub const DEFAULT_CHAINS: u32 = 1000;
+pub const DEFAULT_BOOTSTRAP: u32 = 100_000;
+
+// of the small steps, in primary sample space
+const SIGMA: f64 = 0.01;
+const LARGE_STEP_PROBABILITY: f64 = 0.3;
+
+// bootstrap paths traced as one unit of work
+const BOOTSTRAP_CHUNK: usize = 1024;
+
+
+// the path a chain is at
+struct Chain {
+  sampler: SamplerType,
+  x: f64,
+  y: f64,
+  light: Color
+}
+
+
+impl Metropolis {
+  pub fn new(max_depth: u32, world: &HittableList, camera: Projection, seed: u64) -> Self {
+    Self {
+      path: NeePathTracer {max_depth, lights: Lights::new(world)},
+      camera,
+      chains: DEFAULT_CHAINS,
+      bootstrap: DEFAULT_BOOTSTRAP,
+      seed,
+      brightness: 0.0,
+      states: Vec::new(),
+      splats: SplatBuffer::new(camera.width, camera.height)
+    }
+  }
+
+  // the pass is in the stream so a resumed render starts its chains on other paths
+  fn path_sampler(&self, pass: u32, path: usize) -> SamplerType {
+    let stream = ((pass as u64) << 32) | path as u64;
+    SamplerType::Metropolis(MetropolisSampler::new(self.seed, stream, SIGMA, LARGE_STEP_PROBABILITY))
+  }
+
+  // drawn in the same order as a camera sample
+  fn evaluate(&self, world: &HittableList, sampler: &mut SamplerType) -> (f64, f64, Color) {
+    sampler.start_pixel_sample(0, 0, 0);
+    let (u1, u2) = sampler.get_2d();
+    let (x, y) = (u1 * self.camera.width as f64, u2 * self.camera.height as f64);
+    let lens = self.camera.sample_lens(sampler.get_2d());
+    let target = self.camera.pixel00_loc + (x - 0.5) * self.camera.pixel_delta_u + (y - 0.5) * self.camera.pixel_delta_v;
+    let light = self.path.li(&Ray::new(lens, target - lens), world, sampler, None).total();
+    (x, y, light)
+  }
+
+  // chains start on bootstrap paths picked in proportion to their luminance
+  fn start_chains(&mut self, world: &HittableList, pass: u32) {
+    let count = self.bootstrap.max(1) as usize;
+    let chunk_count = count.div_ceil(BOOTSTRAP_CHUNK);
+    let thread_count = thread::available_parallelism().map_or(4, |c| c.get());
+    let next_chunk = AtomicUsize::new(0);
+    let this = &*self;
+    let mut chunks = thread::scope(|scope| {
+      let handles = (0..thread_count.min(chunk_count)).map(|_| {
+        let next_chunk = &next_chunk;
+        scope.spawn(move || {
+          let mut chunks = Vec::new();
+          loop {
+            let chunk = next_chunk.fetch_add(1, Ordering::Relaxed);
+            if chunk >= chunk_count {
+              break;
+            }
+            let weights = (chunk * BOOTSTRAP_CHUNK..((chunk + 1) * BOOTSTRAP_CHUNK).min(count))
+              .map(|i| this.evaluate(world, &mut this.path_sampler(pass, i)).2.luminance())
+              .collect::<Vec<f64>>();
+            chunks.push((chunk, weights));
+          }
+          chunks
+        })
+      }).collect::<Vec<_>>();
+      handles.into_iter().flat_map(|h| h.join().unwrap()).collect::<Vec<(usize, Vec<f64>)>>()
+    });
+    chunks.sort_by_key(|&(chunk, _)| chunk);
+    let cdf = chunks.into_iter().flat_map(|(_, weights)| weights)
+      .scan(0.0, |sum, w| {*sum += w; Some(*sum)})
+      .collect::<Vec<f64>>();
+    let total = *cdf.last().expect("at least one path");
+    self.brightness = total / count as f64;
+    if total <= 0.0 {
+      return;
+    }
+    // stratified over the cdf, with the offset from the seed
+    let offset = MetropolisSampler::new(self.seed, u64::MAX, SIGMA, LARGE_STEP_PROBABILITY).uniform();
+    let chains = self.chains.max(1) as usize;
+    self.states = (0..chains).map(|k| {
+      let target = (k as f64 + offset) / chains as f64 * total;
+      let path = cdf.partition_point(|&sum| sum <= target).min(count - 1);
+      // the same sampler makes the same path again
+      let mut sampler = self.path_sampler(pass, path);
+      let (x, y, light) = self.evaluate(world, &mut sampler);
+      Chain {sampler, x, y, light}
+    }).collect();
+  }
+
+  // both the proposal and the current path are splatted by their acceptance chances
+  fn run(&self, world: &HittableList, chain: &mut Chain, mutations: u64) {
+    for _ in 0..mutations {
+      metropolis(&mut chain.sampler).start_iteration();
+      let (x, y, light) = self.evaluate(world, &mut chain.sampler);
+      let (current, proposed) = (chain.light.luminance(), light.luminance());
+      let accept = if current > 0.0 {(proposed / current).min(1.0)} else {1.0};
+      if accept > 0.0 {
+        self.splats.add(x, y, light * (accept * self.brightness / proposed));
+      }
+      if accept < 1.0 {
+        self.splats.add(chain.x, chain.y, chain.light * ((1.0 - accept) * self.brightness / current));
+      }
+      let sampler = metropolis(&mut chain.sampler);
+      if sampler.uniform() < accept {
+        sampler.accept();
+        (chain.x, chain.y, chain.light) = (x, y, light);
+      } else {
+        sampler.reject();
+      }
+    }
+  }
+
+  // fixed-point splats add up the same in any order, so threads just share out the chains
+  // the splats count the mutations, so they stay normalised when a pass is cut short
+  // after them or run again on resume
+  pub fn start_pass(&mut self, world: &HittableList, pass: u32, samples: u32) {
+    if self.states.is_empty() {
+      self.start_chains(world, pass);
+    }
+    if self.states.is_empty() {
+      return;
+    }
+    let total = (self.camera.width * self.camera.height) as u64 * samples as u64;
+    let mut states = std::mem::take(&mut self.states);
+    let chains = states.len() as u64;
+    let thread_count = thread::available_parallelism().map_or(4, |c| c.get());
+    let per_thread = states.len().div_ceil(thread_count);
+    let this = &*self;
+    thread::scope(|scope| {
+      for (t, share) in states.chunks_mut(per_thread).enumerate() {
+        scope.spawn(move || {
+          for (i, chain) in share.iter_mut().enumerate() {
+            let k = (t * per_thread + i) as u64;
+            let mutations = total / chains + u64::from(k < total % chains);
+            this.run(world, chain, mutations);
+            this.splats.add_paths(mutations);
+          }
+        });
+      }
+    });
+    self.states = states;
+  }
+}
+
+
+fn metropolis(sampler: &mut SamplerType) -> &mut MetropolisSampler {
+  match sampler {
+    SamplerType::Metropolis(s) => s,
+    _ => unreachable!("chains are sampled by metropolis samplers")
+  }
+}
+
+
+
+
+impl Integrator for Metropolis {
+  // the light comes from the chains, only the surface is looked up for the aovs
+  fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut SamplerType, surface: Option<&mut Option<SurfaceInfo>>) -> LightPaths<Color> {
+    if let Some(surface) = surface {
+      *surface = world.hit(ray, Interval::new(0.001, f64::INFINITY), sampler.rng()).map(|rec| SurfaceInfo::new(ray, &rec));
+    }
+    LightPaths::new(Color::new(0.0, 0.0, 0.0))
+  }
+}
+
+
+
+#[cfg(test)]
+mod mlt_tests {
+  use super::*;
+  use crate::integrator::IntegratorType;
+  use crate::material::{Dielectric, MaterialType};
+  use crate::sphere::Sphere;
+  use crate::test_scenes::{self, pinhole};
+  use crate::vector3::Point;
+
+  #[test]
+  fn test_agrees_with_next_event_estimation() {
+    // the lamp lights the floor through a glass ball
+    let world = test_scenes::lamp_room(Sphere::new(Point::new(0.2, 0.5, 0.0), 0.3, MaterialType::Dielectric(Dielectric::new(1.5))));
+    let camera = pinhole();
+    let n = 4000;
+    let nee = IntegratorType::Nee(NeePathTracer {max_depth: 5, lights: Lights::new(&world)});
+    let expected = test_scenes::pixel_means(&nee, &world, &camera, n);
+    let mut mlt = Metropolis::new(5, &world, camera, 5);
+    (mlt.chains, mlt.bootstrap) = (64, 20_000);
+    // in passes, the chains carry on from one to the next
+    for pass in 0..4 {
+      mlt.start_pass(&world, pass, n / 4);
+    }
+    let pixels = mlt.splats.resolve(1.0 / n as f64).iter().map(|c| c.y).collect::<Vec<f64>>();
+    let mean = expected.iter().sum::<f64>() / expected.len() as f64;
+    let image_mean = pixels.iter().sum::<f64>() / pixels.len() as f64;
+    assert!((image_mean / mean - 1.0).abs() < 0.02, "{} {}", image_mean, mean);
+    for (a, b) in pixels.iter().zip(expected.iter()) {
+      assert!((a - b).abs() < 0.1 * mean, "{:?} {:?}", pixels, expected);
+    }
+  }
+}
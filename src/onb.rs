@@ -0,0 +1,27 @@
+use crate::vector3::Vector3;
+
+// orthonormal basis around a direction, w is the "up" axis of the local frame
+pub struct Onb {
+  pub u: Vector3,
+  pub v: Vector3,
+  pub w: Vector3
+}
+
+
+impl Onb {
+  pub fn new(n: Vector3) -> Self {
+    let w = Vector3::unit_vector(&n);
+    let a = if w.x.abs() > 0.9 {Vector3::new(0.0, 1.0, 0.0)} else {Vector3::new(1.0, 0.0, 0.0)};
+    let v = Vector3::unit_vector(&Vector3::cross(w, a));
+    let u = Vector3::cross(w, v);
+    Self {u, v, w}
+  }
+
+  pub fn to_local(&self, a: Vector3) -> Vector3 {
+    Vector3::new(Vector3::dot(a, self.u), Vector3::dot(a, self.v), Vector3::dot(a, self.w))
+  }
+
+  pub fn to_world(&self, a: Vector3) -> Vector3 {
+    a.x * self.u + a.y * self.v + a.z * self.w
+  }
+}
@@ -0,0 +1,155 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::aov::Aov;
+
+// command line flags, everything that does not start with -- is passed through
+// as a positional argument (image name, scene name and scene arguments)
+pub struct Options {
+  pub spectral: bool,
+  pub sampler: String,
+  pub filter: String,
+  pub filter_radius: Option<f64>,
+  // adaptive sampling is on when a threshold is given
+  pub adaptive_threshold: Option<f64>,
+  pub min_samples: u32,
+  pub max_samples: Option<u32>,
+  pub heatmap: bool,
+  pub samples_per_pixel: Option<u32>,
+  // progressive rendering is on when any of these is given
+  pub pass_samples: Option<u32>,
+  pub write_interval: Option<Duration>,
+  pub time_budget: Option<Duration>,
+  pub checkpoint_interval: Option<Duration>,
+  pub resume: bool,
+  // everything random in a render follows from it, the same seed gives the same image
+  pub seed: u64,
+  pub aovs: Vec<Aov>,
+  // false colour images of the data aovs besides their float files
+  pub aov_views: bool,
+  pub denoise: Option<String>,
+  pub integrator: String,
+  pub ao_samples: u32,
+  pub ao_distance: Option<f64>,
+  // photons per pass and the starting gather radius of sppm
+  pub photons: Option<u32>,
+  pub photon_radius: Option<f64>,
+  // markov chains and bootstrap paths of mlt
+  pub mlt_chains: Option<u32>,
+  pub mlt_bootstrap: Option<u32>,
+  // passes the guided integrator learns from before it keeps what it learned
+  pub guide_passes: Option<u32>,
+  // a debug view, replaces the integrator
+  pub debug: Option<String>
+}
+
+
+impl Options {
+  pub fn parse(args: &[String]) -> Result<(Self, Vec<String>), String> {
+    let mut options = Self {
+      spectral: false,
+      sampler: String::from("sobol"),
+      filter: String::from("box"),
+      filter_radius: None,
+      adaptive_threshold: None,
+      min_samples: 16,
+      max_samples: None,
+      heatmap: false,
+      samples_per_pixel: None,
+      pass_samples: None,
+      write_interval: None,
+      time_budget: None,
+      checkpoint_interval: None,
+      resume: false,
+      seed: 0,
+      aovs: Vec::new(),
+      aov_views: false,
+      denoise: None,
+      integrator: String::from("path"),
+      ao_samples: 1,
+      ao_distance: None,
+      photons: None,
+      photon_radius: None,
+      mlt_chains: None,
+      mlt_bootstrap: None,
+      guide_passes: None,
+      debug: None
+    };
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+      let mut value = || args.next().cloned().ok_or_else(|| format!("{} needs a value", arg));
+      match arg.as_str() {
+        "--spectral"      => options.spectral = true,
+        "--sampler"       => options.sampler = value()?,
+        "--filter"        => options.filter = value()?,
+        "--filter-radius" => options.filter_radius = Some(Self::number(arg, &value()?)?),
+        "--adaptive"      => options.adaptive_threshold = Some(Self::number(arg, &value()?)?),
+        "--min-spp"       => options.min_samples = Self::number(arg, &value()?)?,
+        "--max-spp"       => options.max_samples = Some(Self::number(arg, &value()?)?),
+        "--heatmap"       => options.heatmap = true,
+        "--spp"           => options.samples_per_pixel = Some(Self::number(arg, &value()?)?),
+        "--progressive"   => options.pass_samples = Some(Self::number(arg, &value()?)?),
+        "--write-every"   => options.write_interval = Some(Self::seconds(arg, &value()?)?),
+        "--time-budget"   => options.time_budget = Some(Self::seconds(arg, &value()?)?),
+        "--checkpoint"    => options.checkpoint_interval = Some(Self::seconds(arg, &value()?)?),
+        "--resume"        => options.resume = true,
+        "--seed"          => options.seed = Self::number(arg, &value()?)?,
+        "--aov"           => options.aovs = Aov::parse_list(&value()?)?,
+        "--aov-view"      => options.aov_views = true,
+        "--denoise"       => options.denoise = Some(value()?),
+        "--integrator"    => options.integrator = value()?,
+        "--ao-samples"    => options.ao_samples = Self::number(arg, &value()?)?,
+        "--ao-distance"   => options.ao_distance = Some(Self::number(arg, &value()?)?),
+        "--photons"       => options.photons = Some(Self::number(arg, &value()?)?),
+        "--photon-radius" => options.photon_radius = Some(Self::number(arg, &value()?)?),
+        "--mlt-chains"    => options.mlt_chains = Some(Self::number(arg, &value()?)?),
+        "--mlt-bootstrap" => options.mlt_bootstrap = Some(Self::number(arg, &value()?)?),
+        "--guide-passes"  => options.guide_passes = Some(Self::number(arg, &value()?)?),
+        "--debug"         => options.debug = Some(value()?),
+        flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+        _ => positional.push(arg.clone())
+      }
+    }
+    Ok((options, positional))
+  }
+
+  pub fn progressive(&self) -> bool {
+    self.pass_samples.is_some() || self.write_interval.is_some() || self.time_budget.is_some()
+  }
+
+  fn number<T: FromStr>(flag: &str, value: &str) -> Result<T, String>
+  where T::Err: std::fmt::Display {
+    value.parse().map_err(|e| format!("invalid value {} for {}: {}", value, flag, e))
+  }
+
+  // negative, infinite and too long times are refused like any other bad value
+  fn seconds(flag: &str, value: &str) -> Result<Duration, String> {
+    Duration::try_from_secs_f64(Self::number(flag, value)?).map_err(|e| format!("invalid value {} for {}: {}", value, flag, e))
+  }
+}
+
+
+#[cfg(test)]
+mod options_tests {
+  use super::*;
+
+  fn parse(args: &[&str]) -> Result<Options, String> {
+    Options::parse(&args.iter().map(|a| a.to_string()).collect::<Vec<String>>()).map(|(options, _)| options)
+  }
+
+  #[test]
+  fn test_times_parse_as_seconds() {
+    let options = parse(&["--time-budget", "1.5", "--write-every", "0", "--checkpoint", "60"]).unwrap();
+    assert_eq!(options.time_budget, Some(Duration::from_millis(1500)));
+    assert_eq!(options.write_interval, Some(Duration::ZERO));
+    assert_eq!(options.checkpoint_interval, Some(Duration::from_secs(60)));
+  }
+
+  #[test]
+  fn test_times_that_are_no_duration_are_refused() {
+    for (flag, value) in [("--time-budget", "inf"), ("--write-every", "1e30"), ("--checkpoint", "inf"), ("--time-budget", "-1"), ("--checkpoint", "nan")] {
+      assert!(parse(&[flag, value]).is_err(), "{} {}", flag, value);
+    }
+  }
+}
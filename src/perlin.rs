@@ -0,0 +1,89 @@
+use crate::rng::Rng;
+use crate::vector3::{Point, Vector3};
+
+const POINT_COUNT: usize = 256;
+
+pub struct Perlin {
+  randvec: Vec<Vector3>,
+  perm_x: Vec<usize>,
+  perm_y: Vec<usize>,
+  perm_z: Vec<usize>
+}
+
+
+impl Perlin {
+  pub fn new(rng: &mut Rng) -> Self {
+    let randvec = (0..POINT_COUNT)
+      .map(|_| Vector3::unit_vector(&Vector3::random_in(-1.0, 1.0, rng)))
+      .collect();
+    Self {
+      randvec,
+      perm_x: Self::generate_perm(rng),
+      perm_y: Self::generate_perm(rng),
+      perm_z: Self::generate_perm(rng)
+    }
+  }
+
+  pub fn noise(&self, p: Point) -> f64 {
+    let u = p.x - p.x.floor();
+    let v = p.y - p.y.floor();
+    let w = p.z - p.z.floor();
+    let i = p.x.floor() as i64;
+    let j = p.y.floor() as i64;
+    let k = p.z.floor() as i64;
+    let mut c = [[[Vector3::new(0.0, 0.0, 0.0); 2]; 2]; 2];
+    for (di, plane) in c.iter_mut().enumerate() {
+      for (dj, row) in plane.iter_mut().enumerate() {
+        for (dk, corner) in row.iter_mut().enumerate() {
+          *corner = self.randvec[
+            self.perm_x[((i + di as i64) & 255) as usize] ^
+            self.perm_y[((j + dj as i64) & 255) as usize] ^
+            self.perm_z[((k + dk as i64) & 255) as usize]
+          ];
+        }
+      }
+    }
+    Self::perlin_interp(&c, u, v, w)
+  }
+
+  pub fn turb(&self, p: Point, depth: i32) -> f64 {
+    let mut accum = 0.0;
+    let mut temp_p = p;
+    let mut weight = 1.0;
+    for _ in 0..depth {
+      accum += weight * self.noise(temp_p);
+      weight *= 0.5;
+      temp_p = 2.0 * temp_p;
+    }
+    accum.abs()
+  }
+
+  fn generate_perm(rng: &mut Rng) -> Vec<usize> {
+    let mut p = (0..POINT_COUNT).collect::<Vec<usize>>();
+    for i in (1..POINT_COUNT).rev() {
+      let target = rng.index(i + 1);
+      p.swap(i, target);
+    }
+    p
+  }
+
+  fn perlin_interp(c: &[[[Vector3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+    let uu = u * u * (3.0 - 2.0 * u);
+    let vv = v * v * (3.0 - 2.0 * v);
+    let ww = w * w * (3.0 - 2.0 * w);
+    let mut accum = 0.0;
+    for (i, plane) in c.iter().enumerate() {
+      for (j, row) in plane.iter().enumerate() {
+        for (k, corner) in row.iter().enumerate() {
+          let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+          let weight_v = Vector3::new(u - fi, v - fj, w - fk);
+          accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                 * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                 * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                 * Vector3::dot(*corner, weight_v);
+        }
+      }
+    }
+    accum
+  }
+}
@@ -0,0 +1,363 @@
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::aov::{LightPaths, SurfaceInfo};
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::integrator::{self, Integrator};
+use crate::interval::Interval;
+use crate::light::{self, Lights};
+use crate::material::{Lobe, Material};
+use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerType};
+use crate::vector3::{Color, Point, Vector3};
+
+// progressive photon mapping (Knaus and Zwicker) for caustics on top of nee, the
+// gather radius shrinks every pass
+pub struct PhotonMapper {
+  pub max_depth: u32,
+  lights: Lights,
+  // the objects photons start from
+  emitters: Vec<usize>,
+  // shot before every pass
+  pub photons: u32,
+  // of the first pass
+  pub radius: f64,
+  map: PhotonMap,
+  pass_radius: f64
+}
+
+pub const DEFAULT_PHOTONS: u32 = 100_000;
+pub const DEFAULT_RADIUS: f64 = 0.05;
+
+// share of the photons so far the next pass keeps
+const ALPHA: f64 = 2.0 / 3.0;
+
+// photons shot as one unit of work
+const PHOTON_CHUNK: usize = 4096;
+
+// photons sample a pixel far off the image so they don't repeat a camera path
+const PHOTON_PIXEL: u32 = u32::MAX;
+
+
+#[derive(Clone, Copy, Debug)]
+struct Photon {
+  p: Point,
+  // unit length, the way it was going when it landed
+  direction: Vector3,
+  // flux, already divided by the number of photons shot
+  power: Color
+}
+
+
+// a kd-tree in the photon list itself, the middle of every range splits it
+struct PhotonMap {
+  photons: Vec<Photon>,
+  // the axis the node at the same index splits along
+  axes: Vec<usize>
+}
+
+
+impl PhotonMap {
+  fn new(mut photons: Vec<Photon>) -> Self {
+    let mut axes = vec![0; photons.len()];
+    Self::build(&mut photons, &mut axes);
+    Self {photons, axes}
+  }
+
+  // splits along the axis the photons are spread out the most along
+  fn build(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.is_empty() {
+      return;
+    }
+    let extent = |axis: usize| {
+      let (min, max) = photons.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), photon| {
+        let c = coordinate(photon.p, axis);
+        (min.min(c), max.max(c))
+      });
+      max - min
+    };
+    let axis = (0..3).max_by(|&a, &b| extent(a).total_cmp(&extent(b))).expect("three axes");
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| coordinate(a.p, axis).total_cmp(&coordinate(b.p, axis)));
+    axes[mid] = axis;
+    let (left, right) = photons.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    Self::build(left, left_axes);
+    Self::build(&mut right[1..], &mut right_axes[1..]);
+  }
+
+  fn for_each_within(&self, p: Point, radius: f64, f: &mut impl FnMut(&Photon)) {
+    self.visit(0, self.photons.len(), p, radius * radius, f);
+  }
+
+  // the side of the split p is on first, the other one only when the sphere reaches over
+  fn visit(&self, start: usize, end: usize, p: Point, radius_squared: f64, f: &mut impl FnMut(&Photon)) {
+    if start >= end {
+      return;
+    }
+    let mid = start + (end - start) / 2;
+    let photon = &self.photons[mid];
+    if (photon.p - p).length_squared() <= radius_squared {
+      f(photon);
+    }
+    let d = coordinate(p, self.axes[mid]) - coordinate(photon.p, self.axes[mid]);
+    let (near, far) = if d < 0.0 {((start, mid), (mid + 1, end))} else {((mid + 1, end), (start, mid))};
+    self.visit(near.0, near.1, p, radius_squared, f);
+    if d * d <= radius_squared {
+      self.visit(far.0, far.1, p, radius_squared, f);
+    }
+  }
+}
+
+
+fn coordinate(p: Point, axis: usize) -> f64 {
+  match axis {
+    0 => p.x,
+    1 => p.y,
+    _ => p.z
+  }
+}
+
+
+impl PhotonMapper {
+  pub fn new(max_depth: u32, world: &HittableList) -> Self {
+    let lights = Lights::new(world);
+    let emitters = lights.objects().collect();
+    Self {
+      max_depth,
+      lights,
+      emitters,
+      photons: DEFAULT_PHOTONS,
+      radius: DEFAULT_RADIUS,
+      map: PhotonMap::new(Vec::new()),
+      pass_radius: DEFAULT_RADIUS
+    }
+  }
+
+  // r² shrinks by (i + alpha) / (i + 1) going into pass i
+  fn pass_radius(&self, pass: u32) -> f64 {
+    let shrink = (1..=pass).map(|i| (i as f64 + ALPHA) / (i as f64 + 1.0)).product::<f64>();
+    self.radius * shrink.sqrt()
+  }
+
+  // chunks are put together in order so the map doesn't depend on the thread count
+  pub fn start_pass(&mut self, world: &HittableList, pass: u32, sampler: &SamplerType) {
+    self.pass_radius = self.pass_radius(pass);
+    let count = self.photons as usize;
+    let chunk_count = count.div_ceil(PHOTON_CHUNK);
+    let thread_count = thread::available_parallelism().map_or(4, |c| c.get());
+    let next_chunk = AtomicUsize::new(0);
+    let this = &*self;
+    let mut chunks = thread::scope(|scope| {
+      let handles = (0..thread_count.min(chunk_count)).map(|_| {
+        let next_chunk = &next_chunk;
+        scope.spawn(move || {
+          let mut sampler = sampler.clone();
+          let mut chunks = Vec::new();
+          loop {
+            let chunk = next_chunk.fetch_add(1, Ordering::Relaxed);
+            if chunk >= chunk_count {
+              break;
+            }
+            let mut photons = Vec::new();
+            for i in chunk * PHOTON_CHUNK..((chunk + 1) * PHOTON_CHUNK).min(count) {
+              let index = pass as u64 * count as u64 + i as u64;
+              sampler.start_pixel_sample(PHOTON_PIXEL, PHOTON_PIXEL, index as u32);
+              this.trace_photon(world, &mut sampler, &mut photons);
+            }
+            chunks.push((chunk, photons));
+          }
+          chunks
+        })
+      }).collect::<Vec<_>>();
+      handles.into_iter().flat_map(|h| h.join().unwrap()).collect::<Vec<(usize, Vec<Photon>)>>()
+    });
+    chunks.sort_by_key(|&(chunk, _)| chunk);
+    self.map = PhotonMap::new(chunks.into_iter().flat_map(|(_, photons)| photons).collect());
+  }
+
+  // kept at the first non-specular hit, if it went through something specular first
+  fn trace_photon(&self, world: &HittableList, sampler: &mut SamplerType, photons: &mut Vec<Photon>) {
+    let (u_light, u_point, u_direction) = (sampler.get_1d(), sampler.get_2d(), sampler.get_2d());
+    let Some(emission) = self.lights.sample_emission(world, u_light, u_point, u_direction, sampler.rng()) else {
+      return;
+    };
+    // the cosine of the emitted light cancels against the density, pi is left
+    let mut power = emission.emitted * (PI / (emission.pdf_point * self.photons as f64));
+    let mut ray = Ray::new(emission.rec.p, emission.direction);
+    for bounces in 0..self.max_depth {
+      let Some(rec) = world.hit(&ray, Interval::new(0.001, f64::INFINITY), sampler.rng()) else {
+        return;
+      };
+      if integrator::evaluable(&ray, &rec) {
+        if bounces > 0 && !rec.material.is_phase_function() {
+          photons.push(Photon {p: rec.p, direction: Vector3::unit_vector(&ray.direction), power});
+        }
+        return;
+      }
+      let Some(scatter) = rec.material.scatter(&ray, &rec, sampler) else {
+        return;
+      };
+      power *= scatter.attenuation;
+      ray = scatter.ray;
+    }
+  }
+
+  // eval includes the cosine the photon density already stands in for, so it's divided out
+  fn gather(&self, ray: &Ray, rec: &HitRecord) -> Color {
+    let mut sum = Color::new(0.0, 0.0, 0.0);
+    self.map.for_each_within(rec.p, self.pass_radius, &mut |photon| {
+      let cos = Vector3::dot(photon.direction, rec.normal).abs();
+      if cos <= 1e-6 {
+        return;
+      }
+      if let Some(eval) = rec.material.eval(ray, rec, -photon.direction) {
+        sum += eval.f * photon.power / cos;
+      }
+    });
+    sum / (PI * self.pass_radius * self.pass_radius)
+  }
+}
+
+
+impl Integrator for PhotonMapper {
+  fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut SamplerType, surface: Option<&mut Option<SurfaceInfo>>) -> LightPaths<Color> {
+    let mut paths = LightPaths::new(Color::new(0.0, 0.0, 0.0));
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = *ray;
+    let mut first_lobe = None;
+    let mut surface = surface;
+    // origin and pdf of the ray, when the lights were sampled there too
+    let mut light_sampled_at: Option<(Point, f64)> = None;
+    // lights reached from a gather point through specular bounces are the photons' share
+    let (mut after_gather, mut through_delta) = (false, false);
+    for bounces in 0..self.max_depth {
+      let Some(rec) = world.hit(&ray, Interval::new(0.001, f64::INFINITY), sampler.rng()) else {
+        let weight = light_sampled_at.map_or(1.0, |(_, pdf)| light::power_heuristic(pdf, self.lights.sky_pdf()));
+        paths.add(throughput * weight * light::sky(&ray), bounces, first_lobe);
+        break;
+      };
+      if let Some(surface) = surface.take() {
+        *surface = Some(SurfaceInfo::new(&ray, &rec));
+      }
+      if !(after_gather && through_delta && self.emitters.contains(&rec.object_id)) {
+        let weight = light_sampled_at.map_or(1.0, |(origin, pdf)| {
+          light::power_heuristic(pdf, self.lights.object_pdf(world, rec.object_id, origin, ray.direction))
+        });
+        paths.add(throughput * weight * rec.material.emitted(&rec), bounces, first_lobe);
+      }
+      // the light sample takes its dimensions first so they stay put whatever scatter draws
+      let (u_light, u) = (sampler.get_1d(), sampler.get_2d());
+      let scatter = rec.material.scatter(&ray, &rec, sampler);
+      let evaluable = integrator::evaluable(&ray, &rec);
+      let lit = bounces + 1 < self.max_depth && evaluable;
+      if lit {
+        let lobe = scatter.as_ref().map_or(Lobe::Diffuse, |s| s.lobe);
+        if let Some(sample) = self.lights.sample(world, rec.p, u_light, u) {
+          let eval = rec.material.eval(&ray, &rec, sample.direction).expect("evaluable");
+          if !eval.f.near_zero() {
+            let incoming = self.lights.incoming(world, rec.p, &sample, sampler.rng());
+            let weight = light::power_heuristic(sample.pdf, eval.pdf) / sample.pdf;
+            paths.add(throughput * eval.f * incoming * weight, bounces + 1, first_lobe.or(Some(lobe)));
+          }
+        }
+      }
+      // a caustic has gone through at least one mirror or glass after leaving the light
+      // photons are only kept and gathered on real surfaces
+      let gathered = lit && !rec.material.is_phase_function();
+      if gathered {
+        let lobe = scatter.as_ref().map_or(Lobe::Diffuse, |s| s.lobe);
+        paths.add(throughput * self.gather(&ray, &rec), bounces + 2, first_lobe.or(Some(lobe)));
+      }
+      (after_gather, through_delta) = match (evaluable, gathered) {
+        (false, _) => (after_gather, after_gather),
+        (true, gathered) => (gathered, false)
+      };
+      let Some(scatter) = scatter else {
+        break;
+      };
+      light_sampled_at = if lit {
+        rec.material.eval(&ray, &rec, scatter.ray.direction).map(|eval| (rec.p, eval.pdf))
+      } else {
+        None
+      };
+      throughput *= scatter.attenuation;
+      first_lobe = first_lobe.or(Some(scatter.lobe));
+      ray = scatter.ray;
+    }
+    paths
+  }
+}
+
+
+
+#[cfg(test)]
+mod photon_tests {
+  use super::*;
+  use crate::hittable::HittableObject;
+  use crate::integrator::{IntegratorType, NeePathTracer};
+  use crate::test_scenes;
+  use crate::material::{Dielectric, DiffuseLight, Lambertian, MaterialType};
+  use crate::quad::Quad;
+  use crate::rng::Rng;
+  use crate::sphere::Sphere;
+
+  #[test]
+  fn test_map_finds_what_a_search_of_every_photon_finds() {
+    let mut rng = Rng::new(11);
+    let photons = (0..2000).map(|i| Photon {
+      p: Point::new(rng.uniform(), 0.2 * rng.uniform(), 4.0 * rng.uniform()),
+      direction: Vector3::new(0.0, -1.0, 0.0),
+      power: Color::new(i as f64, 0.0, 0.0)
+    }).collect::<Vec<Photon>>();
+    let map = PhotonMap::new(photons.clone());
+    for _ in 0..50 {
+      let p = Point::new(rng.uniform(), 0.2 * rng.uniform(), 4.0 * rng.uniform());
+      let radius = 0.2 * rng.uniform();
+      let mut found = Vec::new();
+      map.for_each_within(p, radius, &mut |photon| found.push(photon.power.x as usize));
+      found.sort();
+      let expected = photons.iter()
+        .filter(|photon| (photon.p - p).length() <= radius)
+        .map(|photon| photon.power.x as usize)
+        .collect::<Vec<usize>>();
+      assert_eq!(found, expected);
+    }
+  }
+
+  // a glass ball between a lamp and the floor, focusing the lamp onto the floor under it
+  fn glass_over_floor() -> HittableList {
+    let mut world = HittableList::new();
+    let gray = MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let lamp = MaterialType::DiffuseLight(DiffuseLight::new(Color::new(4.0, 4.0, 4.0)));
+    world.list.push(HittableObject::Quad(Quad::new(Point::new(-50.0, 0.0, 50.0), Vector3::new(100.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -100.0), gray)));
+    world.list.push(HittableObject::Quad(Quad::new(Point::new(-0.5, 2.0, -0.5), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0), lamp)));
+    world.list.push(HittableObject::Sphere(Sphere::new(Point::new(0.0, 0.6, 0.0), 0.3, MaterialType::Dielectric(Dielectric::new(1.5)))));
+    world
+  }
+
+  #[test]
+  fn test_agrees_with_next_event_estimation() {
+    let world = glass_over_floor();
+    let mut nee = IntegratorType::Nee(NeePathTracer {max_depth: 5, lights: Lights::new(&world)});
+    let mut sppm = IntegratorType::PhotonMapping(PhotonMapper::new(5, &world));
+    if let IntegratorType::PhotonMapping(p) = &mut sppm {
+      p.photons = 20_000;
+    }
+    let eye = Point::new(0.0, 1.0, 2.5);
+    let (expected, _) = test_scenes::floor_patch(&mut nee, &world, eye, 1.0, 40, 2000);
+    let (mean, _) = test_scenes::floor_patch(&mut sppm, &world, eye, 1.0, 40, 2000);
+    assert!((mean / expected - 1.0).abs() < 0.02, "{} {}", mean, expected);
+  }
+
+  #[test]
+  fn test_radius_shrinks_every_pass() {
+    let mapper = PhotonMapper::new(5, &glass_over_floor());
+    assert_eq!(mapper.pass_radius(0), mapper.radius);
+    let radii = (0..100).map(|pass| mapper.pass_radius(pass)).collect::<Vec<f64>>();
+    assert!(radii.windows(2).all(|w| w[1] < w[0]));
+    // slow enough that the photons of all passes add up to ever more per area
+    assert!(radii[99] > 0.3 * mapper.radius, "{}", radii[99]);
+  }
+}
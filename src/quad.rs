@@ -0,0 +1,112 @@
+use std::borrow::Cow;
+
+use crate::material::MaterialType;
+use crate::vector3::{Vector3, Point};
+use crate::hittable::{Hittable, HitRecord};
+use crate::ray::Ray;
+use crate::interval::Interval;
+use crate::rng::Rng;
+
+// parallelogram with corner q and edges u and v, or the triangle spanned by them
+pub struct Quad {
+  pub q: Point,
+  pub u: Vector3,
+  pub v: Vector3,
+  pub mat: MaterialType,
+  triangle: bool,
+  normal: Vector3,
+  d: f64,
+  w: Vector3
+}
+
+
+impl Quad {
+  pub fn new(q: Point, u: Vector3, v: Vector3, mat: MaterialType) -> Self {
+    let n = Vector3::cross(u, v);
+    let normal = Vector3::unit_vector(&n);
+    Self {
+      q,
+      u,
+      v,
+      mat,
+      triangle: false,
+      normal,
+      d: Vector3::dot(normal, q),
+      w: n / Vector3::dot(n, n)
+    }
+  }
+
+  pub fn triangle(q: Point, u: Vector3, v: Vector3, mat: MaterialType) -> Self {
+    Self {triangle: true, ..Self::new(q, u, v, mat)}
+  }
+
+  pub fn area(&self) -> f64 {
+    let parallelogram = Vector3::cross(self.u, self.v).length();
+    if self.triangle {parallelogram / 2.0} else {parallelogram}
+  }
+
+  // a point picked uniformly over the area and the normal, along u x v
+  pub fn sample_point(&self, (u1, u2): (f64, f64)) -> (Point, Vector3) {
+    // points past the hypotenuse are folded back into the triangle
+    let (a, b) = if self.triangle && u1 + u2 > 1.0 {(1.0 - u1, 1.0 - u2)} else {(u1, u2)};
+    (self.q + a * self.u + b * self.v, self.normal)
+  }
+
+  // a direction from origin toward a point picked uniformly over the area, with its
+  // density converted to solid angle
+  pub fn sample_direction(&self, origin: Point, u: (f64, f64)) -> Option<(Vector3, f64)> {
+    let direction = self.sample_point(u).0 - origin;
+    let pdf = self.pdf(origin, direction);
+    (pdf > 0.0).then_some((direction, pdf))
+  }
+
+  // density of sample_direction for a direction that hits the quad
+  pub fn pdf(&self, origin: Point, direction: Vector3) -> f64 {
+    let denom = Vector3::dot(self.normal, direction);
+    if denom.abs() < 1e-8 {
+      return 0.0;
+    }
+    let t = (self.d - Vector3::dot(self.normal, origin)) / denom;
+    let distance_squared = t * t * direction.length_squared();
+    let cos = denom.abs() / direction.length();
+    distance_squared / (cos * self.area())
+  }
+
+  // distance of the point with edge coordinates (alpha, beta) to the closest edge,
+  // the hypotenuse of a triangle is where alpha + beta is one
+  pub fn edge_distance(&self, alpha: f64, beta: f64) -> f64 {
+    if self.triangle {
+      alpha.min(beta).min(1.0 - alpha - beta)
+    } else {
+      alpha.min(beta).min(1.0 - alpha).min(1.0 - beta)
+    }
+  }
+}
+
+
+impl Hittable for Quad {
+  fn hit(&self, ray: &Ray, ray_t: Interval, _rng: &mut Rng) -> Option<HitRecord<'_>> {
+    let denom = Vector3::dot(self.normal, ray.direction);
+    if denom.abs() < 1e-8 {
+      return None;
+    }
+    let t = (self.d - Vector3::dot(self.normal, ray.origin)) / denom;
+    if !ray_t.surrounds(t) {
+      return None;
+    }
+    // planar coordinates of the hit point in the basis of the edges
+    let planar_hitpt_vector = ray.at(t) - self.q;
+    let alpha = Vector3::dot(self.w, Vector3::cross(planar_hitpt_vector, self.v));
+    let beta = Vector3::dot(self.w, Vector3::cross(self.u, planar_hitpt_vector));
+    if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+      return None;
+    }
+    if self.triangle && alpha + beta > 1.0 {
+      return None;
+    }
+    let mut hr = HitRecord::new(ray, t, self.normal, Cow::Borrowed(&self.mat));
+    (hr.u, hr.v) = (alpha, beta);
+    (hr.dpdu, hr.dpdv) = (self.u, self.v);
+    Some(hr)
+  }
+}
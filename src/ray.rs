@@ -1,15 +1,18 @@
 use crate::vector3::{Point, Vector3};
 
 
+#[derive(Clone, Copy)]
 pub struct Ray {
   pub origin: Point,
-  pub direction: Vector3
+  pub direction: Vector3,
+  // hero wavelength in nanometers when rendering spectrally
+  pub wavelength: Option<f64>
 }
 
 
 impl Ray {
   pub fn new(origin: Point, direction: Vector3) -> Self {
-    Self {origin, direction}
+    Self {origin, direction, wavelength: None}
   }
 
   pub fn at(&self, t: f64) -> Point {
@@ -0,0 +1,799 @@
+use std::sync::OnceLock;
+
+use crate::rng::{Rng, ONE_MINUS_EPSILON};
+
+// a source of sample values in [0, 1). every camera sample starts a new pixel sample,
+// after that each get call consumes the next dimension: the pixel offset first, then
+// the lens, then whatever the materials ask for along the path
+pub trait Sampler {
+  fn start_pixel_sample(&mut self, col: u32, row: u32, index: u32);
+  fn get_1d(&mut self) -> f64;
+  fn get_2d(&mut self) -> (f64, f64);
+  // random numbers for the decisions that don't get their own sample dimension, like
+  // collisions in volumes, seeded per pixel sample just like the dimensions are
+  fn rng(&mut self) -> &mut Rng;
+}
+
+
+#[derive(Clone)]
+pub enum SamplerType {
+  Independent(IndependentSampler),
+  Stratified(StratifiedSampler),
+  Halton(HaltonSampler),
+  Sobol(SobolSampler),
+  BlueNoise(BlueNoiseSampler),
+  // only made by the metropolis integrator, for its chains
+  Metropolis(MetropolisSampler)
+}
+
+impl SamplerType {
+  // the seed picks the scrambling of the sequences, the same seed gives the same samples
+  pub fn from_name(name: &str, samples_per_pixel: u32, seed: u64) -> Result<Self, String> {
+    match name {
+      "independent" => Ok(SamplerType::Independent(IndependentSampler::new(seed))),
+      "stratified"  => Ok(SamplerType::Stratified(StratifiedSampler::new(samples_per_pixel, seed))),
+      "halton"      => Ok(SamplerType::Halton(HaltonSampler::new(seed))),
+      "sobol"       => Ok(SamplerType::Sobol(SobolSampler::new(seed))),
+      "bluenoise"   => Ok(SamplerType::BlueNoise(BlueNoiseSampler::new(seed))),
+      _             => Err(format!("unknown sampler {}", name))
+    }
+  }
+
+  // the stratified sampler lays its strata out for one sample count, samples taken
+  // with another count don't belong to the same pattern
+  pub fn name(&self) -> String {
+    match self {
+      SamplerType::Independent(_) => String::from("independent"),
+      SamplerType::Stratified(s)  => format!("stratified {}", s.samples_per_pixel),
+      SamplerType::Halton(_)      => String::from("halton"),
+      SamplerType::Sobol(_)       => String::from("sobol"),
+      SamplerType::BlueNoise(_)   => String::from("bluenoise"),
+      SamplerType::Metropolis(_)  => String::from("metropolis")
+    }
+  }
+}
+
+impl Sampler for SamplerType {
+  fn start_pixel_sample(&mut self, col: u32, row: u32, index: u32) {
+    match self {
+      SamplerType::Independent(s) => s.start_pixel_sample(col, row, index),
+      SamplerType::Stratified(s)  => s.start_pixel_sample(col, row, index),
+      SamplerType::Halton(s)      => s.start_pixel_sample(col, row, index),
+      SamplerType::Sobol(s)       => s.start_pixel_sample(col, row, index),
+      SamplerType::BlueNoise(s)   => s.start_pixel_sample(col, row, index),
+      SamplerType::Metropolis(s)  => s.start_pixel_sample(col, row, index)
+    }
+  }
+
+  fn get_1d(&mut self) -> f64 {
+    match self {
+      SamplerType::Independent(s) => s.get_1d(),
+      SamplerType::Stratified(s)  => s.get_1d(),
+      SamplerType::Halton(s)      => s.get_1d(),
+      SamplerType::Sobol(s)       => s.get_1d(),
+      SamplerType::BlueNoise(s)   => s.get_1d(),
+      SamplerType::Metropolis(s)  => s.get_1d()
+    }
+  }
+
+  fn get_2d(&mut self) -> (f64, f64) {
+    match self {
+      SamplerType::Independent(s) => s.get_2d(),
+      SamplerType::Stratified(s)  => s.get_2d(),
+      SamplerType::Halton(s)      => s.get_2d(),
+      SamplerType::Sobol(s)       => s.get_2d(),
+      SamplerType::BlueNoise(s)   => s.get_2d(),
+      SamplerType::Metropolis(s)  => s.get_2d()
+    }
+  }
+
+  fn rng(&mut self) -> &mut Rng {
+    match self {
+      SamplerType::Independent(s) => s.rng(),
+      SamplerType::Stratified(s)  => s.rng(),
+      SamplerType::Halton(s)      => s.rng(),
+      SamplerType::Sobol(s)       => s.rng(),
+      SamplerType::BlueNoise(s)   => s.rng(),
+      SamplerType::Metropolis(s)  => s.rng()
+    }
+  }
+}
+
+
+
+
+fn to_unit(bits: u32) -> f64 {
+  (bits as f64 / 4294967296.0).min(ONE_MINUS_EPSILON)
+}
+
+// 64 bit finalizer from murmurhash3, good enough to decorrelate pixels and dimensions
+fn mix_bits(mut v: u64) -> u64 {
+  v ^= v >> 31;
+  v = v.wrapping_mul(0x7fb5d329728ea185);
+  v ^= v >> 27;
+  v = v.wrapping_mul(0x81dadef4bc2dd44d);
+  v ^= v >> 33;
+  v
+}
+
+fn hash(a: u64, b: u64) -> u64 {
+  mix_bits(a ^ mix_bits(b.wrapping_add(0x9e3779b97f4a7c15)))
+}
+
+fn pixel_seed(seed: u64, col: u32, row: u32) -> u64 {
+  hash(seed, ((col as u64) << 32) | row as u64)
+}
+
+fn sample_rng(pixel_seed: u64, index: u32) -> Rng {
+  Rng::new(hash(pixel_seed, index as u64))
+}
+
+
+
+
+// plain uniform random numbers, every dimension independent of the others
+#[derive(Clone)]
+pub struct IndependentSampler {
+  seed: u64,
+  rng: Rng
+}
+
+impl IndependentSampler {
+  pub fn new(seed: u64) -> Self {
+    Self {seed, rng: Rng::new(seed)}
+  }
+}
+
+impl Sampler for IndependentSampler {
+  fn start_pixel_sample(&mut self, col: u32, row: u32, index: u32) {
+    self.rng = sample_rng(pixel_seed(self.seed, col, row), index);
+  }
+
+  fn get_1d(&mut self) -> f64 {
+    self.rng.uniform()
+  }
+
+  fn get_2d(&mut self) -> (f64, f64) {
+    (self.rng.uniform(), self.rng.uniform())
+  }
+
+  fn rng(&mut self) -> &mut Rng {
+    &mut self.rng
+  }
+}
+
+
+
+
+// jittered strata, each sample of a pixel lands in its own stratum. the strata are
+// visited in a different random order per pixel and dimension (Kensler's permutation)
+// so dimensions don't correlate with each other
+#[derive(Clone)]
+pub struct StratifiedSampler {
+  samples_per_pixel: u32,
+  strata_per_axis: u32,
+  global_seed: u64,
+  seed: u64,
+  index: u32,
+  dimension: u64,
+  // jitter inside the strata
+  rng: Rng
+}
+
+impl StratifiedSampler {
+  pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+    Self {
+      samples_per_pixel: samples_per_pixel.max(1),
+      strata_per_axis: (samples_per_pixel.max(1) as f64).sqrt().ceil() as u32,
+      global_seed: seed,
+      seed: 0,
+      index: 0,
+      dimension: 0,
+      rng: Rng::new(seed)
+    }
+  }
+
+  fn stratum(&mut self, count: u32) -> u32 {
+    let p = hash(self.seed, self.dimension) as u32;
+    self.dimension += 1;
+    permute(self.index % count, count, p)
+  }
+}
+
+impl Sampler for StratifiedSampler {
+  fn start_pixel_sample(&mut self, col: u32, row: u32, index: u32) {
+    self.seed = pixel_seed(self.global_seed, col, row);
+    self.index = index;
+    self.dimension = 0;
+    self.rng = sample_rng(self.seed, index);
+  }
+
+  fn get_1d(&mut self) -> f64 {
+    let n = self.samples_per_pixel;
+    let stratum = self.stratum(n);
+    ((stratum as f64 + self.rng.uniform()) / n as f64).min(ONE_MINUS_EPSILON)
+  }
+
+  fn get_2d(&mut self) -> (f64, f64) {
+    let n = self.strata_per_axis;
+    let stratum = self.stratum(n * n);
+    (
+      (((stratum % n) as f64 + self.rng.uniform()) / n as f64).min(ONE_MINUS_EPSILON),
+      (((stratum / n) as f64 + self.rng.uniform()) / n as f64).min(ONE_MINUS_EPSILON)
+    )
+  }
+
+  fn rng(&mut self) -> &mut Rng {
+    &mut self.rng
+  }
+}
+
+
+// a pseudo random permutation of [0, l) picked by p, from Kensler's
+// "Correlated Multi-Jittered Sampling"
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+  let mut w = l - 1;
+  w |= w >> 1;
+  w |= w >> 2;
+  w |= w >> 4;
+  w |= w >> 8;
+  w |= w >> 16;
+  loop {
+    i ^= p;
+    i = i.wrapping_mul(0xe170893d);
+    i ^= p >> 16;
+    i ^= (i & w) >> 4;
+    i ^= p >> 8;
+    i = i.wrapping_mul(0x0929eb3f);
+    i ^= p >> 23;
+    i ^= (i & w) >> 1;
+    i = i.wrapping_mul(1 | p >> 27);
+    i = i.wrapping_mul(0x6935fa69);
+    i ^= (i & w) >> 11;
+    i = i.wrapping_mul(0x74dcb303);
+    i ^= (i & w) >> 2;
+    i = i.wrapping_mul(0x9e501cc3);
+    i ^= (i & w) >> 2;
+    i = i.wrapping_mul(0xc860a3df);
+    i &= w;
+    i ^= i >> 5;
+    if i < l {
+      break;
+    }
+  }
+  (i.wrapping_add(p)) % l
+}
+
+
+
+
+const PRIMES: [u64; 64] = [
+  2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+  59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+  137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+  227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311
+];
+
+// the radical inverse of the sample index in the n-th prime base for the n-th dimension,
+// shifted by a per pixel random offset (Cranley-Patterson rotation). dimensions past
+// the table fall back to random numbers, by then the path has lost most of its weight
+#[derive(Clone)]
+pub struct HaltonSampler {
+  global_seed: u64,
+  seed: u64,
+  index: u64,
+  dimension: usize,
+  rng: Rng
+}
+
+impl HaltonSampler {
+  pub fn new(seed: u64) -> Self {
+    Self {global_seed: seed, seed: 0, index: 0, dimension: 0, rng: Rng::new(seed)}
+  }
+}
+
+impl Sampler for HaltonSampler {
+  fn start_pixel_sample(&mut self, col: u32, row: u32, index: u32) {
+    self.seed = pixel_seed(self.global_seed, col, row);
+    self.index = index as u64;
+    self.dimension = 0;
+    self.rng = sample_rng(self.seed, index);
+  }
+
+  fn get_1d(&mut self) -> f64 {
+    let dimension = self.dimension;
+    self.dimension += 1;
+    if dimension >= PRIMES.len() {
+      return self.rng.uniform();
+    }
+    let offset = to_unit(hash(self.seed, dimension as u64) as u32);
+    let value = radical_inverse(PRIMES[dimension], self.index) + offset;
+    (value - value.floor()).min(ONE_MINUS_EPSILON)
+  }
+
+  fn get_2d(&mut self) -> (f64, f64) {
+    (self.get_1d(), self.get_1d())
+  }
+
+  fn rng(&mut self) -> &mut Rng {
+    &mut self.rng
+  }
+}
+
+
+pub fn radical_inverse(base: u64, mut index: u64) -> f64 {
+  let inv_base = 1.0 / base as f64;
+  let mut inv_base_n = 1.0;
+  let mut reversed = 0u64;
+  while index > 0 {
+    let next = index / base;
+    reversed = reversed * base + (index - next * base);
+    inv_base_n *= inv_base;
+    index = next;
+  }
+  (reversed as f64 * inv_base_n).min(ONE_MINUS_EPSILON)
+}
+
+
+
+
+// the first two dimensions of the sobol sequence with hash based owen scrambling,
+// padded to any number of dimensions by shuffling the sample order independently
+// for every dimension pair (Burley, "Practical Hash-based Owen Scrambling")
+#[derive(Clone)]
+pub struct SobolSampler {
+  global_seed: u64,
+  seed: u64,
+  index: u32,
+  dimension: u64,
+  rng: Rng
+}
+
+impl SobolSampler {
+  pub fn new(seed: u64) -> Self {
+    Self {global_seed: seed, seed: 0, index: 0, dimension: 0, rng: Rng::new(seed)}
+  }
+
+  fn next_seed(&mut self) -> u32 {
+    let seed = hash(self.seed, self.dimension) as u32;
+    self.dimension += 1;
+    seed
+  }
+}
+
+impl Sampler for SobolSampler {
+  fn start_pixel_sample(&mut self, col: u32, row: u32, index: u32) {
+    self.seed = pixel_seed(self.global_seed, col, row);
+    self.index = index;
+    self.dimension = 0;
+    self.rng = sample_rng(self.seed, index);
+  }
+
+  fn get_1d(&mut self) -> f64 {
+    let seed = self.next_seed();
+    let index = nested_uniform_scramble(self.index, seed);
+    to_unit(nested_uniform_scramble(index.reverse_bits(), seed ^ 0xa511e9b3))
+  }
+
+  fn get_2d(&mut self) -> (f64, f64) {
+    let seed = self.next_seed();
+    let index = nested_uniform_scramble(self.index, seed);
+    (
+      to_unit(nested_uniform_scramble(index.reverse_bits(), seed ^ 0xa511e9b3)),
+      to_unit(nested_uniform_scramble(sobol_second_dimension(index), seed ^ 0x63d83595))
+    )
+  }
+
+  fn rng(&mut self) -> &mut Rng {
+    &mut self.rng
+  }
+}
+
+
+fn sobol_second_dimension(mut index: u32) -> u32 {
+  let mut v = 1u32 << 31;
+  let mut x = 0;
+  while index != 0 {
+    if index & 1 != 0 {
+      x ^= v;
+    }
+    index >>= 1;
+    v ^= v >> 1;
+  }
+  x
+}
+
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+  x = x.wrapping_add(seed);
+  x ^= x.wrapping_mul(0x6c50b47c);
+  x ^= x.wrapping_mul(0xb82f1e52);
+  x ^= x.wrapping_mul(0xc7afe638);
+  x ^= x.wrapping_mul(0x8d22f6e6);
+  x
+}
+
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+  laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+
+
+
+// a blue noise mask tiled over the image and shifted per dimension, advanced through
+// the samples of a pixel by the golden ratio (1d) and R2 (2d) sequences. neighbouring
+// pixels get very different values, so the error looks like fine grain instead of blotches
+#[derive(Clone)]
+pub struct BlueNoiseSampler {
+  seed: u64,
+  col: u32,
+  row: u32,
+  index: u32,
+  dimension: u64,
+  rng: Rng
+}
+
+const MASK_SIZE: usize = 64;
+
+impl BlueNoiseSampler {
+  pub fn new(seed: u64) -> Self {
+    Self {seed, col: 0, row: 0, index: 0, dimension: 0, rng: Rng::new(seed)}
+  }
+
+  fn mask_value(&mut self) -> f64 {
+    let offset = hash(self.seed ^ 0x5bd1e995, self.dimension);
+    self.dimension += 1;
+    let x = (self.col as usize + (offset as usize % MASK_SIZE)) % MASK_SIZE;
+    let y = (self.row as usize + ((offset >> 32) as usize % MASK_SIZE)) % MASK_SIZE;
+    blue_noise_mask()[y * MASK_SIZE + x]
+  }
+}
+
+impl Sampler for BlueNoiseSampler {
+  fn start_pixel_sample(&mut self, col: u32, row: u32, index: u32) {
+    (self.col, self.row, self.index) = (col, row, index);
+    self.dimension = 0;
+    self.rng = sample_rng(pixel_seed(self.seed, col, row), index);
+  }
+
+  fn get_1d(&mut self) -> f64 {
+    let golden = 0.618033988749895;
+    let value = self.mask_value() + self.index as f64 * golden;
+    (value - value.floor()).min(ONE_MINUS_EPSILON)
+  }
+
+  fn get_2d(&mut self) -> (f64, f64) {
+    // R2 sequence, the 2d generalization of the golden ratio
+    let plastic = 1.324717957244746;
+    let x = self.mask_value() + self.index as f64 / plastic;
+    let y = self.mask_value() + self.index as f64 / (plastic * plastic);
+    ((x - x.floor()).min(ONE_MINUS_EPSILON), (y - y.floor()).min(ONE_MINUS_EPSILON))
+  }
+
+  fn rng(&mut self) -> &mut Rng {
+    &mut self.rng
+  }
+}
+
+
+fn blue_noise_mask() -> &'static [f64] {
+  static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+  MASK.get_or_init(|| void_and_cluster(MASK_SIZE, 1.5))
+}
+
+
+// Ulichney's void and cluster method: start from a relaxed sparse pattern, then keep
+// filling the largest void. the order in which pixels are filled is the threshold
+fn void_and_cluster(size: usize, sigma: f64) -> Vec<f64> {
+  let n = size * size;
+  // energy a point adds at each toroidal offset
+  let kernel: Vec<f64> = (0..n).map(|i| {
+    let wrap = |d: usize| {let d = d as f64; d.min(size as f64 - d)};
+    let (dx, dy) = (wrap(i % size), wrap(i / size));
+    (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+  }).collect();
+  let mut energy = vec![0.0; n];
+  let mut on = vec![false; n];
+  let update = |energy: &mut Vec<f64>, p: usize, sign: f64| {
+    let (px, py) = (p % size, p / size);
+    for (i, e) in energy.iter_mut().enumerate() {
+      let dx = (i % size + size - px) % size;
+      let dy = (i / size + size - py) % size;
+      *e += sign * kernel[dy * size + dx];
+    }
+  };
+  let tightest_cluster = |energy: &Vec<f64>, on: &Vec<bool>| {
+    (0..n).filter(|&i| on[i]).max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+  };
+  let largest_void = |energy: &Vec<f64>, on: &Vec<bool>| {
+    (0..n).filter(|&i| !on[i]).min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+  };
+
+  // a random initial pattern with a tenth of the pixels on, relaxed by moving
+  // the point of the tightest cluster into the largest void until it is stable
+  let initial = n / 10;
+  let mut state = 0x2545f4914f6cdd1du64;
+  let mut placed = 0;
+  while placed < initial {
+    state = mix_bits(state);
+    let p = (state % n as u64) as usize;
+    if !on[p] {
+      on[p] = true;
+      update(&mut energy, p, 1.0);
+      placed += 1;
+    }
+  }
+  for _ in 0..n {
+    let cluster = tightest_cluster(&energy, &on);
+    on[cluster] = false;
+    update(&mut energy, cluster, -1.0);
+    let void = largest_void(&energy, &on);
+    on[void] = true;
+    update(&mut energy, void, 1.0);
+    if void == cluster {
+      break;
+    }
+  }
+
+  let mut rank = vec![0usize; n];
+  // ranks of the initial points, removing the tightest clusters first
+  let (mut pattern, mut pattern_energy) = (on.clone(), energy.clone());
+  for r in (0..initial).rev() {
+    let cluster = tightest_cluster(&pattern_energy, &pattern);
+    pattern[cluster] = false;
+    update(&mut pattern_energy, cluster, -1.0);
+    rank[cluster] = r;
+  }
+  // and the rest by filling voids
+  for r in initial..n {
+    let void = largest_void(&energy, &on);
+    on[void] = true;
+    update(&mut energy, void, 1.0);
+    rank[void] = r;
+  }
+  rank.iter().map(|&r| (r as f64 + 0.5) / n as f64).collect()
+}
+
+
+
+
+// the sampler of metropolis light transport in primary sample space (Kelemen et al).
+// the sample values of a path are kept from one iteration to the next and mutated
+// instead of drawn anew: a large step draws all of them again, a small step moves each
+// one a little with wraparound. values are only made and mutated when a path asks for
+// them, one that sat unused for a while catches up on the small steps it missed, and
+// rejecting a proposal puts back what it changed. what rng decides is drawn anew for
+// every evaluation, the chains treat it like noise on the light of a path
+#[derive(Clone)]
+pub struct MetropolisSampler {
+  seed: u64,
+  stream: u64,
+  sigma: f64,
+  large_step_probability: f64,
+  // for the mutations and accepting them, apart from what the path sees
+  mutation_rng: Rng,
+  rng: Rng,
+  values: Vec<PrimarySample>,
+  iteration: u64,
+  large_step: bool,
+  last_large_step: u64,
+  dimension: usize,
+  evaluations: u64
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct PrimarySample {
+  value: f64,
+  // the iteration that last changed it
+  modified: u64,
+  // both as they were before the current iteration
+  backup: f64,
+  backup_modified: u64
+}
+
+impl MetropolisSampler {
+  // samplers with the same seed and stream make the same values, the first
+  // evaluation draws all of them uniformly
+  pub fn new(seed: u64, stream: u64, sigma: f64, large_step_probability: f64) -> Self {
+    Self {
+      seed,
+      stream,
+      sigma,
+      large_step_probability,
+      mutation_rng: Rng::with_stream(seed, stream),
+      rng: Rng::new(seed),
+      values: Vec::new(),
+      iteration: 0,
+      large_step: true,
+      last_large_step: 0,
+      dimension: 0,
+      evaluations: 0
+    }
+  }
+
+  // the values of the next evaluation are a mutation of the current ones
+  pub fn start_iteration(&mut self) {
+    self.iteration += 1;
+    self.large_step = self.mutation_rng.uniform() < self.large_step_probability;
+  }
+
+  pub fn accept(&mut self) {
+    if self.large_step {
+      self.last_large_step = self.iteration;
+    }
+  }
+
+  pub fn reject(&mut self) {
+    for sample in &mut self.values {
+      if sample.modified == self.iteration {
+        sample.value = sample.backup;
+        sample.modified = sample.backup_modified;
+      }
+    }
+    self.iteration -= 1;
+  }
+
+  // for the acceptance test
+  pub fn uniform(&mut self) -> f64 {
+    self.mutation_rng.uniform()
+  }
+
+  fn next(&mut self) -> f64 {
+    if self.dimension >= self.values.len() {
+      self.values.resize(self.dimension + 1, PrimarySample::default());
+    }
+    let sample = &mut self.values[self.dimension];
+    self.dimension += 1;
+    // the last large step drew it anew, even though the path didn't ask for it then
+    if sample.modified < self.last_large_step {
+      sample.value = self.mutation_rng.uniform();
+      sample.modified = self.last_large_step;
+    }
+    (sample.backup, sample.backup_modified) = (sample.value, sample.modified);
+    if self.large_step {
+      sample.value = self.mutation_rng.uniform();
+    } else {
+      // box muller, steps add up like a random walk
+      let (u1, u2) = (self.mutation_rng.uniform(), self.mutation_rng.uniform());
+      let normal = (-2.0 * (1.0 - u1).ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+      let steps = (self.iteration - sample.modified) as f64;
+      sample.value += normal * self.sigma * steps.sqrt();
+      sample.value -= sample.value.floor();
+    }
+    sample.modified = self.iteration;
+    sample.value.min(ONE_MINUS_EPSILON)
+  }
+}
+
+impl Sampler for MetropolisSampler {
+  // starts an evaluation of the current values, the pixel comes from the values
+  fn start_pixel_sample(&mut self, _col: u32, _row: u32, _index: u32) {
+    self.dimension = 0;
+    self.rng = Rng::with_stream(hash(self.seed, self.evaluations), self.stream);
+    self.evaluations += 1;
+  }
+
+  fn get_1d(&mut self) -> f64 {
+    self.next()
+  }
+
+  fn get_2d(&mut self) -> (f64, f64) {
+    (self.next(), self.next())
+  }
+
+  fn rng(&mut self) -> &mut Rng {
+    &mut self.rng
+  }
+}
+
+
+
+#[cfg(test)]
+mod sampler_tests {
+  use super::*;
+
+  fn all_samplers(samples_per_pixel: u32) -> Vec<SamplerType> {
+    ["independent", "stratified", "halton", "sobol", "bluenoise"].iter()
+      .map(|name| SamplerType::from_name(name, samples_per_pixel, 0).unwrap())
+      .collect()
+  }
+
+  #[test]
+  fn test_samples_in_unit_interval() {
+    for mut sampler in all_samplers(16) {
+      for index in 0..16 {
+        sampler.start_pixel_sample(3, 7, index);
+        for _ in 0..20 {
+          let u = sampler.get_1d();
+          let (x, y) = sampler.get_2d();
+          assert!((0.0..1.0).contains(&u));
+          assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_permute_is_a_permutation() {
+    let mut seen: Vec<u32> = (0..10).map(|i| permute(i, 10, 0xdeadbeef)).collect();
+    seen.sort();
+    assert_eq!(seen, (0..10).collect::<Vec<u32>>());
+  }
+
+  #[test]
+  fn test_stratified_1d_covers_every_stratum() {
+    let mut sampler = StratifiedSampler::new(8, 0);
+    let mut strata: Vec<usize> = (0..8).map(|i| {
+      sampler.start_pixel_sample(1, 2, i);
+      (sampler.get_1d() * 8.0) as usize
+    }).collect();
+    strata.sort();
+    assert_eq!(strata, (0..8).collect::<Vec<usize>>());
+  }
+
+  #[test]
+  fn test_radical_inverse() {
+    assert_eq!(radical_inverse(2, 1), 0.5);
+    assert_eq!(radical_inverse(2, 3), 0.75);
+    assert!((radical_inverse(3, 5) - 7.0 / 9.0).abs() < 0.000001);
+  }
+
+  #[test]
+  fn test_sobol_2d_first_four_in_distinct_quadrants() {
+    let mut sampler = SobolSampler::new(0);
+    let mut quadrants: Vec<usize> = (0..4).map(|i| {
+      sampler.start_pixel_sample(5, 9, i);
+      let (x, y) = sampler.get_2d();
+      (x * 2.0) as usize + 2 * (y * 2.0) as usize
+    }).collect();
+    quadrants.sort();
+    assert_eq!(quadrants, vec![0, 1, 2, 3]);
+  }
+
+  #[test]
+  fn test_same_seed_same_samples() {
+    let draw = |seed: u64| {
+      ["independent", "stratified", "halton", "sobol", "bluenoise"].iter().map(|name| {
+        let mut sampler = SamplerType::from_name(name, 16, seed).unwrap();
+        sampler.start_pixel_sample(5, 9, 3);
+        let (u1, u2) = sampler.get_2d();
+        (u1, u2, sampler.get_1d(), sampler.rng().uniform())
+      }).collect::<Vec<_>>()
+    };
+    assert_eq!(draw(1), draw(1));
+    for (a, b) in draw(1).iter().zip(draw(2).iter()) {
+      assert_ne!(a, b);
+    }
+  }
+
+  #[test]
+  fn test_metropolis_reject_puts_the_values_back() {
+    let mut sampler = MetropolisSampler::new(4, 9, 0.01, 0.0);
+    let draw = |sampler: &mut MetropolisSampler| {
+      sampler.start_pixel_sample(0, 0, 0);
+      (0..5).map(|_| sampler.get_1d()).collect::<Vec<f64>>()
+    };
+    let first = draw(&mut sampler);
+    sampler.start_iteration();
+    let mutated = draw(&mut sampler);
+    // small steps only
+    assert!(first.iter().zip(mutated.iter()).all(|(a, b)| a != b && ((a - b).abs() < 0.1 || (a - b).abs() > 0.9)));
+    sampler.reject();
+    sampler.start_iteration();
+    sampler.start_pixel_sample(0, 0, 0);
+    // mutated again from the first values, not from the rejected ones
+    let backup = sampler.values.iter().map(|s| s.value).collect::<Vec<f64>>();
+    assert_eq!(backup, first);
+    assert!((sampler.get_1d() - first[0]).abs() < 0.1);
+  }
+
+  #[test]
+  fn test_blue_noise_mask_is_a_permutation_of_thresholds() {
+    let mut ranks: Vec<usize> = blue_noise_mask().iter()
+      .map(|v| (v * (MASK_SIZE * MASK_SIZE) as f64) as usize)
+      .collect();
+    ranks.sort();
+    assert_eq!(ranks, (0..MASK_SIZE * MASK_SIZE).collect::<Vec<usize>>());
+  }
+}
@@ -0,0 +1,697 @@
+use crate::alpha_mask::{AlphaMasked, AlphaMode};
+use crate::constant_medium::ConstantMedium;
+use crate::heterogeneous_medium::{Density, DensityGrid, HeterogeneousMedium, NoiseDensity};
+use crate::hittable::{HittableList, HittableObject};
+use std::io;
+use std::sync::Arc;
+
+use crate::material::{Coated, Conductor, Dielectric, DiffuseLight, GltfMetallicRoughness, HenyeyGreenstein, Lambertian, MaterialType, Metal, Mix, NormalMapped, Principled, RoughDielectric, Subsurface};
+use crate::perlin::Perlin;
+use crate::quad::Quad;
+use crate::rng::Rng;
+use crate::spectrum::Dispersion;
+use crate::sphere::Sphere;
+use crate::subsurface::SubsurfaceObject;
+use crate::texture::{Checker, ImageTexture, NoiseTexture, ScalarTexture, TextureType};
+use crate::vector3::{Color, Point, Vector3};
+
+
+// extra command line arguments are passed through for scenes that load data,
+// the rng generates the noise of procedural textures and volumes
+pub fn build(name: &str, args: &[String], rng: &mut Rng) -> Result<HittableList, String> {
+  match name {
+    "spheres" => Ok(spheres()),
+    "smoke"   => Ok(smoke()),
+    "fire"    => Ok(fire(rng)),
+    "cloud"   => cloud(args, rng),
+    "metals"  => Ok(metals()),
+    "glass"   => Ok(glass()),
+    "principled" => principled(args, rng),
+    "layered" => Ok(layered(rng)),
+    "bumps"   => bumps(args, rng),
+    "foliage" => foliage(args, rng),
+    "subsurface" => Ok(subsurface()),
+    "prism"   => prism(args),
+    "room"    => Ok(room()),
+    _         => Err(format!("unknown scene {}", name))
+  }
+}
+
+
+pub fn spheres() -> HittableList {
+  let material_ground = MaterialType::Lambertian(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
+  let material_center = MaterialType::Lambertian(Lambertian::new(Color::new(0.1, 0.2, 0.5)));
+  let material_left = MaterialType::Dielectric(Dielectric::new(1.5));
+  let material_bubble = MaterialType::Dielectric(Dielectric::new(1.0 / 1.5));
+  let material_right = MaterialType::Metal(Metal::new(Color::new(0.8, 0.6, 0.2), 0.1));
+  let mut world = HittableList::new();
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(
+        Point::new(0.0,  0.0, -1.5),
+        0.5,
+        material_center
+      )
+    )
+  );
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(
+        Point::new(0.0, -100.5, -1.0),
+        100.0,
+        material_ground
+      )
+    )
+  );
+  world.list.push(
+  HittableObject::Sphere(
+    Sphere::new(
+      // Point::new(-1.0, 0.0, -1.0),
+      Point::new(-0.8, 0.0, -1.0),
+      0.5,
+      material_left
+    )
+  )
+  );
+  world.list.push(
+  HittableObject::Sphere(
+    Sphere::new(
+      // Point::new(-1.0, 0.0, -1.0),
+      Point::new(-0.8, 0.0, -1.0),
+      0.4,
+      material_bubble
+    )
+  )
+  );
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(
+        Point::new(1.0, 0.0, -1.0),
+        0.5,
+        material_right
+      )
+    )
+  );
+  world
+}
+
+
+pub fn smoke() -> HittableList {
+  let material_ground = MaterialType::Lambertian(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
+  let material_glass = MaterialType::Dielectric(Dielectric::new(1.5));
+  let mut world = HittableList::new();
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(Point::new(0.0, -100.5, -1.0), 100.0, material_ground.clone())
+    )
+  );
+  // white smoke in the middle
+  world.list.push(
+    HittableObject::ConstantMedium(
+      ConstantMedium::new(
+        HittableObject::Sphere(Sphere::new(Point::new(0.0, 0.0, -1.5), 0.5, material_ground.clone())),
+        2.0,
+        Color::new(0.9, 0.9, 0.9)
+      )
+    )
+  );
+  // dark smoke on the right
+  world.list.push(
+    HittableObject::ConstantMedium(
+      ConstantMedium::new(
+        HittableObject::Sphere(Sphere::new(Point::new(1.0, 0.0, -1.0), 0.5, material_ground.clone())),
+        6.0,
+        Color::new(0.1, 0.1, 0.1)
+      )
+    )
+  );
+  // glass shell filled with a dense blue medium, a cheap subsurface look
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(Point::new(-0.8, 0.0, -1.0), 0.5, material_glass.clone())
+    )
+  );
+  world.list.push(
+    HittableObject::ConstantMedium(
+      ConstantMedium::new(
+        HittableObject::Sphere(Sphere::new(Point::new(-0.8, 0.0, -1.0), 0.5, material_glass.clone())),
+        20.0,
+        Color::new(0.2, 0.4, 0.9)
+      )
+    )
+  );
+  // thin fog over the whole scene, the camera sits inside it
+  world.list.push(
+    HittableObject::ConstantMedium(
+      ConstantMedium::new(
+        HittableObject::Sphere(Sphere::new(Point::new(0.0, 0.0, 0.0), 5.0, material_ground.clone())),
+        0.05,
+        Color::new(1.0, 1.0, 1.0)
+      )
+    )
+  );
+  world
+}
+
+
+pub fn fire(rng: &mut Rng) -> HittableList {
+  let material_ground = MaterialType::Lambertian(Lambertian::new(Color::new(0.3, 0.3, 0.3)));
+  let mut world = HittableList::new();
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(Point::new(0.0, -100.5, -1.0), 100.0, material_ground.clone())
+    )
+  );
+  // forward scattering, mostly absorbing medium that glows where it is dense
+  world.list.push(
+    HittableObject::HeterogeneousMedium(
+      HeterogeneousMedium::new(
+        HittableObject::Sphere(Sphere::new(Point::new(0.0, 0.0, -1.2), 0.5, material_ground.clone())),
+        Density::Noise(NoiseDensity::new(6.0, 12.0, rng)),
+        HenyeyGreenstein::emissive(Color::new(0.3, 0.2, 0.1), 0.6, Color::new(1.2, 0.45, 0.09))
+      )
+    )
+  );
+  world
+}
+
+
+// cloud <file.raw> <nx> <ny> <nz> loads a grid, without arguments a noisy ball is generated
+pub fn cloud(args: &[String], rng: &mut Rng) -> Result<HittableList, String> {
+  let min = Point::new(-0.5, -0.5, -1.5);
+  let max = Point::new(0.5, 0.5, -0.5);
+  let grid = if let Some(path) = args.first() {
+    let dims = args[1..].iter()
+                        .map(|a| a.parse::<usize>().map_err(|e| format!("bad grid dimension {}: {}", a, e)))
+                        .collect::<Result<Vec<usize>, String>>()?;
+    if dims.len() != 3 {
+      return Err("cloud scene expects <file.raw> <nx> <ny> <nz>".to_string());
+    }
+    DensityGrid::load_raw(path, dims[0], dims[1], dims[2], min, max)
+      .map_err(|e| format!("could not load {}: {}", path, e))?
+  } else {
+    procedural_cloud(64, min, max, rng).map_err(|e| format!("could not make the cloud: {}", e))?
+  };
+  let material_ground = MaterialType::Lambertian(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
+  let mut world = HittableList::new();
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(Point::new(0.0, -100.5, -1.0), 100.0, material_ground.clone())
+    )
+  );
+  world.list.push(
+    HittableObject::HeterogeneousMedium(
+      HeterogeneousMedium::new(
+        HittableObject::Sphere(Sphere::new(Point::new(0.0, 0.0, -1.0), 0.87, material_ground.clone())),
+        Density::Grid(grid, 30.0),
+        HenyeyGreenstein::new(Color::new(0.95, 0.95, 0.95), 0.3)
+      )
+    )
+  );
+  Ok(world)
+}
+
+
+fn procedural_cloud(n: usize, min: Point, max: Point, rng: &mut Rng) -> io::Result<DensityGrid> {
+  let noise = Perlin::new(rng);
+  let mut data = Vec::with_capacity(n * n * n);
+  for z in 0..n {
+    for y in 0..n {
+      for x in 0..n {
+        let p = Point::new(x as f64, y as f64, z as f64) / n as f64 - Point::new(0.5, 0.5, 0.5);
+        let falloff = (1.0 - 2.0 * p.length()).max(0.0);
+        data.push((falloff * (0.5 + noise.turb(4.0 * p, 5))) as f32);
+      }
+    }
+  }
+  DensityGrid::new(n, n, n, data, min, max)
+}
+
+
+// the four conductor presets, smooth in the front row and rough in the back
+pub fn metals() -> HittableList {
+  let material_ground = MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+  let mut world = HittableList::new();
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(Point::new(0.0, -100.5, -1.0), 100.0, material_ground.clone())
+    )
+  );
+  let presets: [fn(f64) -> Conductor; 4] = [
+    Conductor::gold,
+    Conductor::copper,
+    Conductor::aluminium,
+    Conductor::silver
+  ];
+  for (i, preset) in presets.iter().enumerate() {
+    let x = -1.2 + 0.8 * i as f64;
+    world.list.push(
+      HittableObject::Sphere(
+        Sphere::new(Point::new(x, -0.2, -1.2), 0.3, MaterialType::Conductor(preset(0.0)))
+      )
+    );
+    world.list.push(
+      HittableObject::Sphere(
+        Sphere::new(Point::new(x, 0.15, -2.4), 0.3, MaterialType::Conductor(preset(0.4)))
+      )
+    );
+  }
+  world
+}
+
+
+// clear, frosted, tinted and tinted frosted glass in front of colored spheres
+pub fn glass() -> HittableList {
+  let material_ground = MaterialType::Lambertian(Lambertian::new(Color::new(0.8, 0.8, 0.8)));
+  let mut world = HittableList::new();
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(Point::new(0.0, -100.5, -1.0), 100.0, material_ground.clone())
+    )
+  );
+  let panes = [
+    RoughDielectric::new(1.5, 0.0),
+    RoughDielectric::new(1.5, 0.3),
+    RoughDielectric::tinted(1.5, 0.0, Color::new(0.2, 0.8, 0.4), 0.5),
+    RoughDielectric::tinted(1.5, 0.3, Color::new(0.2, 0.8, 0.4), 0.5)
+  ];
+  let backdrop = [
+    Color::new(0.8, 0.1, 0.1),
+    Color::new(0.1, 0.1, 0.8),
+    Color::new(0.8, 0.8, 0.1),
+    Color::new(0.8, 0.1, 0.8)
+  ];
+  for (i, (pane, color)) in panes.iter().zip(backdrop).enumerate() {
+    let x = -1.2 + 0.8 * i as f64;
+    world.list.push(
+      HittableObject::Sphere(
+        Sphere::new(Point::new(x, -0.15, -1.2), 0.35, MaterialType::RoughDielectric(*pane))
+      )
+    );
+    world.list.push(
+      HittableObject::Sphere(
+        Sphere::new(Point::new(x, -0.3, -2.2), 0.2, MaterialType::Lambertian(Lambertian::new(color)))
+      )
+    );
+  }
+  world
+}
+
+
+// principled <base_color.png> [metallic_roughness.png] puts a glTF-style textured
+// sphere in the middle, the rows around it sweep the sliders
+pub fn principled(args: &[String], rng: &mut Rng) -> Result<HittableList, String> {
+  let load = |path: &String, srgb: bool| ImageTexture::load(path, srgb)
+    .map(Arc::new)
+    .map_err(|e| format!("could not load {}: {}", path, e));
+  let checker = TextureType::Checker(Checker::new(0.1, Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+  let material_ground = MaterialType::Principled(Box::new(Principled {
+    roughness: ScalarTexture::constant(0.8),
+    ..Principled::new(checker)
+  }));
+  let mut world = HittableList::new();
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(Point::new(0.0, -100.5, -1.0), 100.0, material_ground)
+    )
+  );
+
+  let gold = Color::new(1.0, 0.78, 0.34);
+  let red = TextureType::Solid(Color::new(0.7, 0.1, 0.1));
+  let sweep = |i: usize| i as f64 / 4.0;
+  for i in 0..5 {
+    let x = -1.6 + 0.8 * i as f64;
+    // front row: metallic from 0 to 1 at medium roughness
+    let front = Principled {
+      metallic: ScalarTexture::constant(sweep(i)),
+      roughness: ScalarTexture::constant(0.3),
+      ..Principled::new(TextureType::Solid(gold))
+    };
+    // back row: roughness from 0 to 1 under a polished clearcoat
+    let back = Principled {
+      roughness: ScalarTexture::constant(sweep(i)),
+      clearcoat: ScalarTexture::constant(1.0),
+      ..Principled::new(red.clone())
+    };
+    world.list.push(
+      HittableObject::Sphere(
+        Sphere::new(Point::new(x, -0.25, -1.2), 0.25, MaterialType::Principled(Box::new(front)))
+      )
+    );
+    world.list.push(
+      HittableObject::Sphere(
+        Sphere::new(Point::new(x, -0.2, -2.4), 0.3, MaterialType::Principled(Box::new(back)))
+      )
+    );
+  }
+
+  // velvet, glass, marble and a glowing sphere on top
+  let velvet = Principled {
+    roughness: ScalarTexture::constant(1.0),
+    specular: ScalarTexture::constant(0.0),
+    sheen: ScalarTexture::constant(1.0),
+    ..Principled::new(TextureType::Solid(Color::new(0.3, 0.05, 0.4)))
+  };
+  let glass = Principled {
+    roughness: ScalarTexture::constant(0.1),
+    transmission: ScalarTexture::constant(1.0),
+    ..Principled::new(TextureType::Solid(Color::new(0.8, 0.95, 0.9)))
+  };
+  let marble_veins = TextureType::Noise(Arc::new(NoiseTexture::new(4.0, Color::new(1.0, 1.0, 1.0), rng)));
+  let marble = Principled {
+    roughness: ScalarTexture::channel(marble_veins.clone(), 0),
+    ..Principled::new(marble_veins)
+  };
+  let lamp = Principled {
+    emission: TextureType::Solid(Color::new(4.0, 3.0, 2.0)),
+    ..Principled::new(TextureType::Solid(Color::new(0.0, 0.0, 0.0)))
+  };
+  for (i, material) in [velvet, glass, marble, lamp].into_iter().enumerate() {
+    world.list.push(
+      HittableObject::Sphere(
+        Sphere::new(Point::new(-1.2 + 0.8 * i as f64, 0.45, -1.8), 0.2, MaterialType::Principled(Box::new(material)))
+      )
+    );
+  }
+
+  // without a metallic-roughness map the factors describe a matte dielectric
+  let metallic_roughness_texture = args.get(1).map(|p| load(p, false)).transpose()?;
+  let textured = metallic_roughness_texture.is_some();
+  let imported = GltfMetallicRoughness {
+    base_color_factor: Color::new(1.0, 1.0, 1.0),
+    base_color_texture: args.first().map(|p| load(p, true)).transpose()?,
+    metallic_factor: if textured {1.0} else {0.0},
+    roughness_factor: if textured {1.0} else {0.6},
+    metallic_roughness_texture,
+    emissive_factor: Color::new(0.0, 0.0, 0.0),
+    emissive_texture: None,
+    ior: 1.5,
+    transmission_factor: 0.0
+  };
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(Point::new(0.0, 0.15, -1.2), 0.2, MaterialType::Principled(Box::new(Principled::from_gltf(&imported))))
+    )
+  );
+  Ok(world)
+}
+
+
+// car paint, varnished wood, rusty copper and a checkered mix of gold and plastic
+pub fn layered(rng: &mut Rng) -> HittableList {
+  let material_ground = MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+  let mut world = HittableList::new();
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(Point::new(0.0, -100.5, -1.0), 100.0, material_ground)
+    )
+  );
+  let flakes = MaterialType::Principled(Box::new(Principled {
+    metallic: ScalarTexture::constant(0.6),
+    roughness: ScalarTexture::constant(0.4),
+    ..Principled::new(TextureType::Solid(Color::new(0.6, 0.02, 0.02)))
+  }));
+  let car_paint = MaterialType::Coated(Coated::new(flakes, 1.5, 0.0));
+  let grain = TextureType::Noise(Arc::new(NoiseTexture::new(12.0, Color::new(0.45, 0.25, 0.1), rng)));
+  let wood = MaterialType::Principled(Box::new(Principled {
+    roughness: ScalarTexture::constant(0.9),
+    ..Principled::new(grain)
+  }));
+  let varnished_wood = MaterialType::Coated(Coated::tinted(wood, 1.5, 0.1, Color::new(0.95, 0.8, 0.5)));
+  let rust = MaterialType::Lambertian(Lambertian::new(Color::new(0.35, 0.12, 0.05)));
+  let rust_pattern = TextureType::Noise(Arc::new(NoiseTexture::new(3.0, Color::new(1.0, 1.0, 1.0), rng)));
+  let rusty_copper = MaterialType::Mix(Mix::new(
+    MaterialType::Conductor(Conductor::copper(0.2)),
+    rust,
+    ScalarTexture::channel(rust_pattern, 0)
+  ));
+  let checker = TextureType::Checker(Checker::new(0.08, Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0)));
+  let checkered = MaterialType::Mix(Mix::new(
+    MaterialType::Conductor(Conductor::gold(0.1)),
+    MaterialType::Lambertian(Lambertian::new(Color::new(0.1, 0.3, 0.7))),
+    ScalarTexture::channel(checker, 0)
+  ));
+  for (i, material) in [car_paint, varnished_wood, rusty_copper, checkered].into_iter().enumerate() {
+    world.list.push(
+      HittableObject::Sphere(
+        Sphere::new(Point::new(-1.2 + 0.8 * i as f64, -0.15, -1.3), 0.35, material)
+      )
+    );
+  }
+  world
+}
+
+
+// bumps [normal_map.png] shows bump mapped spheres, with a normal map on the
+// rightmost one when a file is given
+pub fn bumps(args: &[String], rng: &mut Rng) -> Result<HittableList, String> {
+  let material_ground = MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+  let mut world = HittableList::new();
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(Point::new(0.0, -100.5, -1.0), 100.0, material_ground)
+    )
+  );
+  let noise = TextureType::Noise(Arc::new(NoiseTexture::new(8.0, Color::new(1.0, 1.0, 1.0), rng)));
+  let stone = MaterialType::NormalMapped(NormalMapped::bump(
+    MaterialType::Lambertian(Lambertian::new(Color::new(0.6, 0.55, 0.5))),
+    ScalarTexture::channel(noise, 0),
+    0.01
+  ));
+  let tiles = TextureType::Checker(Checker::new(0.1, Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0)));
+  let hammered = MaterialType::NormalMapped(NormalMapped::bump(
+    MaterialType::Conductor(Conductor::copper(0.15)),
+    ScalarTexture::channel(tiles, 0),
+    0.002
+  ));
+  let plastic = MaterialType::Principled(Box::new(Principled {
+    roughness: ScalarTexture::constant(0.3),
+    ..Principled::new(TextureType::Solid(Color::new(0.1, 0.3, 0.7)))
+  }));
+  let mapped = match args.first() {
+    Some(path) => {
+      let map = ImageTexture::load(path, false).map_err(|e| format!("could not load {}: {}", path, e))?;
+      NormalMapped::normal_map(plastic, TextureType::Image(Arc::new(map)))
+    },
+    None => {
+      let ripples = TextureType::Noise(Arc::new(NoiseTexture::new(30.0, Color::new(1.0, 1.0, 1.0), rng)));
+      NormalMapped::bump(plastic, ScalarTexture::channel(ripples, 0), 0.003)
+    }
+  };
+  for (i, material) in [stone, hammered, MaterialType::NormalMapped(mapped)].into_iter().enumerate() {
+    world.list.push(
+      HittableObject::Sphere(
+        Sphere::new(Point::new(-1.0 + 1.0 * i as f64, -0.1, -1.3), 0.4, material)
+      )
+    );
+  }
+  Ok(world)
+}
+
+
+// foliage [leaf.png] puts a cutout fence, a sphere with holes and soft edged leaves
+// in front of the camera, a png with an alpha channel replaces the procedural leaves
+pub fn foliage(args: &[String], rng: &mut Rng) -> Result<HittableList, String> {
+  let material_ground = MaterialType::Lambertian(Lambertian::new(Color::new(0.3, 0.5, 0.2)));
+  let mut world = HittableList::new();
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(Point::new(0.0, -100.5, -1.0), 100.0, material_ground)
+    )
+  );
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(Point::new(0.6, 0.0, -2.2), 0.5, MaterialType::Lambertian(Lambertian::new(Color::new(0.7, 0.2, 0.1))))
+    )
+  );
+  // wooden fence with square holes
+  let holes = TextureType::Checker(Checker::new(0.1, Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0)));
+  world.list.push(
+    HittableObject::AlphaMasked(
+      AlphaMasked::new(
+        HittableObject::Quad(Quad::new(
+          Point::new(-2.0, -0.5, -1.6),
+          Vector3::new(4.0, 0.0, 0.0),
+          Vector3::new(0.0, 0.6, 0.0),
+          MaterialType::Lambertian(Lambertian::new(Color::new(0.45, 0.3, 0.15)))
+        )),
+        ScalarTexture::channel(holes.clone(), 0),
+        AlphaMode::Cutout(0.5)
+      )
+    )
+  );
+  // a sphere with holes shows its inside through them
+  world.list.push(
+    HittableObject::AlphaMasked(
+      AlphaMasked::new(
+        HittableObject::Sphere(Sphere::new(
+          Point::new(-0.7, 0.0, -1.2),
+          0.35,
+          MaterialType::Conductor(Conductor::gold(0.2))
+        )),
+        ScalarTexture::channel(holes, 0),
+        AlphaMode::Cutout(0.5)
+      )
+    )
+  );
+  // leaves with fractional alpha are hit stochastically
+  let (leaf_material, leaf_alpha) = match args.first() {
+    Some(path) => {
+      let error = |e: image::ImageError| format!("could not load {}: {}", path, e);
+      let color = ImageTexture::load(path, true).map_err(error)?;
+      let alpha = ImageTexture::load_alpha(path).map_err(error)?;
+      (
+        MaterialType::Principled(Box::new(Principled::new(TextureType::Image(Arc::new(color))))),
+        ScalarTexture::channel(TextureType::Image(Arc::new(alpha)), 0)
+      )
+    },
+    None => {
+      let veins = TextureType::Noise(Arc::new(NoiseTexture::new(20.0, Color::new(1.0, 1.0, 1.0), rng)));
+      (
+        MaterialType::Lambertian(Lambertian::new(Color::new(0.2, 0.5, 0.1))),
+        ScalarTexture::channel(veins, 0)
+      )
+    }
+  };
+  for i in 0..3 {
+    let x = 0.1 + 0.3 * i as f64;
+    world.list.push(
+      HittableObject::AlphaMasked(
+        AlphaMasked::new(
+          HittableObject::Quad(Quad::new(
+            Point::new(x, -0.1 + 0.15 * i as f64, -0.9),
+            Vector3::new(0.25, 0.05, -0.1),
+            Vector3::new(-0.05, 0.25, 0.05),
+            leaf_material.clone()
+          )),
+          leaf_alpha.clone(),
+          AlphaMode::Stochastic
+        )
+      )
+    );
+  }
+  Ok(world)
+}
+
+
+// skin, wax and marble, red light travels furthest in skin
+pub fn subsurface() -> HittableList {
+  let material_ground = MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+  let mut world = HittableList::new();
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(Point::new(0.0, -100.5, -1.0), 100.0, material_ground.clone())
+    )
+  );
+  let materials = [
+    Subsurface::new(1.4, 0.3, Color::new(0.95, 0.8, 0.7), Color::new(0.2, 0.08, 0.05)),
+    Subsurface::new(1.45, 0.1, Color::new(0.97, 0.95, 0.8), Color::new(0.15, 0.12, 0.08)),
+    Subsurface::new(1.5, 0.0, Color::new(0.98, 0.98, 0.98), Color::new(0.1, 0.1, 0.1))
+  ];
+  for (i, material) in materials.into_iter().enumerate() {
+    world.list.push(
+      HittableObject::Subsurface(
+        SubsurfaceObject::new(
+          HittableObject::Sphere(Sphere::new(Point::new(-1.0 + 1.0 * i as f64, -0.1, -1.3), 0.4, material_ground.clone())),
+          material
+        )
+      )
+    );
+  }
+  world
+}
+
+
+// a glass prism in front of a wall of glowing squares, rendered with --spectral
+// every edge seen through the glass splits into a rainbow.
+// args: [flint | crown | cauchy] [roughness]
+pub fn prism(args: &[String]) -> Result<HittableList, String> {
+  let dispersion = match args.first().map_or("flint", String::as_str) {
+    "flint"  => Dispersion::sf11(),
+    "crown"  => Dispersion::bk7(),
+    "cauchy" => Dispersion::Cauchy(1.5046, 0.0042),
+    other    => return Err(format!("unknown glass {}", other))
+  };
+  let roughness = match args.get(1) {
+    Some(r) => r.parse::<f64>().map_err(|e| format!("invalid roughness {}: {}", r, e))?,
+    None => 0.0
+  };
+  let mut world = HittableList::new();
+  world.list.push(
+    HittableObject::Sphere(
+      Sphere::new(Point::new(0.0, -100.5, -1.0), 100.0, MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))))
+    )
+  );
+  let squares = TextureType::Checker(Checker::new(0.25, Color::new(4.0, 4.0, 4.0), Color::new(0.0, 0.0, 0.0)));
+  let wall = MaterialType::Principled(Box::new(Principled {
+    emission: squares,
+    ..Principled::new(TextureType::Solid(Color::new(0.0, 0.0, 0.0)))
+  }));
+  world.list.push(
+    HittableObject::Quad(
+      Quad::new(Point::new(-6.0, -0.5, -2.5), Vector3::new(12.0, 0.0, 0.0), Vector3::new(0.0, 8.0, 0.0), wall)
+    )
+  );
+  let glass = if roughness > 0.0 {
+    MaterialType::RoughDielectric(RoughDielectric::dispersive(dispersion, roughness))
+  } else {
+    MaterialType::Dielectric(Dielectric::dispersive(dispersion))
+  };
+  // equilateral cross section standing on the ground, the apex points to the right
+  // so looking through it bends the view to the left onto the wall
+  let (y0, height) = (-0.5, 0.8);
+  let a = Point::new(-0.25, y0, -0.8);
+  let b = Point::new(-0.25, y0, -1.3);
+  let c = Point::new(-0.25 + 0.25 * 3.0f64.sqrt(), y0, -1.05);
+  let axis = Vector3::new(0.0, height, 0.0);
+  let centroid = (a + b + c) / 3.0 + 0.5 * axis;
+  let faces = [
+    (a, b - a, axis, false),
+    (b, c - b, axis, false),
+    (c, a - c, axis, false),
+    (a, b - a, c - a, true),
+    (a + axis, b - a, c - a, true)
+  ];
+  for (q, u, v, triangle) in faces {
+    // the normal has to point out of the prism for the glass to know inside from outside
+    let center = q + (u + v) / 3.0;
+    let (u, v) = if Vector3::dot(Vector3::cross(u, v), center - centroid) < 0.0 {(v, u)} else {(u, v)};
+    let face = if triangle {
+      Quad::triangle(q, u, v, glass.clone())
+    } else {
+      Quad::new(q, u, v, glass.clone())
+    };
+    world.list.push(HittableObject::Quad(face));
+  }
+  Ok(world)
+}
+
+
+// a closed room lit only by a small lamp under the ceiling, with a glass ball that
+// focuses the lamp into a caustic on the floor. the camera stands inside by the back
+// wall, so no sky gets in. made for --integrator bdpt
+pub fn room() -> HittableList {
+  let white = MaterialType::Lambertian(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+  let red = MaterialType::Lambertian(Lambertian::new(Color::new(0.65, 0.05, 0.05)));
+  let green = MaterialType::Lambertian(Lambertian::new(Color::new(0.12, 0.45, 0.15)));
+  let lamp = MaterialType::DiffuseLight(DiffuseLight::new(Color::new(40.0, 40.0, 40.0)));
+  let (x0, x1, y0, y1, z0, z1) = (-2.0, 2.0, -1.0, 1.5, -3.5, 1.0);
+  let (width, height, depth) = (x1 - x0, y1 - y0, z1 - z0);
+  let mut world = HittableList::new();
+  let walls = [
+    (Point::new(x0, y0, z1), Vector3::new(width, 0.0, 0.0), Vector3::new(0.0, 0.0, -depth), white.clone()),
+    (Point::new(x0, y1, z1), Vector3::new(width, 0.0, 0.0), Vector3::new(0.0, 0.0, -depth), white.clone()),
+    (Point::new(x0, y0, z0), Vector3::new(width, 0.0, 0.0), Vector3::new(0.0, height, 0.0), white.clone()),
+    (Point::new(x0, y0, z1), Vector3::new(width, 0.0, 0.0), Vector3::new(0.0, height, 0.0), white.clone()),
+    (Point::new(x0, y0, z1), Vector3::new(0.0, 0.0, -depth), Vector3::new(0.0, height, 0.0), red),
+    (Point::new(x1, y0, z1), Vector3::new(0.0, 0.0, -depth), Vector3::new(0.0, height, 0.0), green)
+  ];
+  for (q, u, v, material) in walls {
+    world.list.push(HittableObject::Quad(Quad::new(q, u, v, material)));
+  }
+  // u x v points down, the side the lamp shines from
+  world.list.push(HittableObject::Quad(Quad::new(Point::new(-0.25, y1 - 0.01, -2.25), Vector3::new(0.5, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.5), lamp)));
+  world.list.push(HittableObject::Sphere(Sphere::new(Point::new(0.6, y0 + 0.45, -2.0), 0.45, MaterialType::Dielectric(Dielectric::new(1.5)))));
+  world.list.push(HittableObject::Sphere(Sphere::new(Point::new(-0.9, y0 + 0.4, -2.6), 0.4, white)));
+  world
+}
@@ -0,0 +1,330 @@
+use std::ops::{Add, Mul};
+use std::sync::OnceLock;
+
+use crate::vector3::Color;
+
+// visible range the wavelengths are sampled from, in nanometers
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+pub const N_SAMPLES: usize = 4;
+
+
+// hero wavelength sampling: one uniformly sampled wavelength plus the others evenly
+// rotated through the range, so a single path carries a few spectral samples at once
+#[derive(Clone, Copy)]
+pub struct SampledWavelengths {
+  lambda: [f64; N_SAMPLES],
+  pdf: [f64; N_SAMPLES]
+}
+
+impl SampledWavelengths {
+  pub fn sample_uniform(u: f64) -> Self {
+    let range = LAMBDA_MAX - LAMBDA_MIN;
+    let mut lambda = [0.0; N_SAMPLES];
+    lambda[0] = LAMBDA_MIN + u * range;
+    let step = range / N_SAMPLES as f64;
+    for i in 1..N_SAMPLES {
+      lambda[i] = lambda[i - 1] + step;
+      if lambda[i] > LAMBDA_MAX {
+        lambda[i] -= range;
+      }
+    }
+    Self {lambda, pdf: [1.0 / range; N_SAMPLES]}
+  }
+
+  pub fn hero(&self) -> f64 {
+    self.lambda[0]
+  }
+
+  // after a wavelength dependent event such as dispersion only the hero wavelength
+  // can follow the path, it takes over the weight of the dropped ones
+  pub fn terminate_secondary(&mut self) {
+    if self.secondary_terminated() {
+      return;
+    }
+    for pdf in self.pdf.iter_mut().skip(1) {
+      *pdf = 0.0;
+    }
+    self.pdf[0] /= N_SAMPLES as f64;
+  }
+
+  pub fn secondary_terminated(&self) -> bool {
+    self.pdf[1..].iter().all(|&p| p == 0.0)
+  }
+
+  // monte carlo estimate of the XYZ tristimulus values of the sampled spectrum
+  pub fn to_xyz(self, s: SampledSpectrum) -> Color {
+    let mut xyz = Color::new(0.0, 0.0, 0.0);
+    for i in 0..N_SAMPLES {
+      if self.pdf[i] == 0.0 {
+        continue;
+      }
+      xyz += (s.0[i] / self.pdf[i]) * cie_xyz(self.lambda[i]);
+    }
+    xyz / N_SAMPLES as f64
+  }
+}
+
+
+
+
+// spectral values at the wavelengths of a SampledWavelengths
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampledSpectrum(pub [f64; N_SAMPLES]);
+
+impl SampledSpectrum {
+  pub fn constant(value: f64) -> Self {
+    Self([value; N_SAMPLES])
+  }
+
+  // upsamples an rgb triple with Smits' method, a sum of smooth white, primary and
+  // secondary basis spectra. it is linear, so it works for emission above 1 too
+  pub fn from_rgb(rgb: Color, wavelengths: &SampledWavelengths) -> Self {
+    let mut values = [0.0; N_SAMPLES];
+    for (value, &lambda) in values.iter_mut().zip(wavelengths.lambda.iter()) {
+      *value = rgb_to_spectrum(rgb, lambda);
+    }
+    Self(values)
+  }
+}
+
+impl Add for SampledSpectrum {
+  type Output = Self;
+  fn add(self, rhs: Self) -> Self::Output {
+    let mut values = self.0;
+    for (v, r) in values.iter_mut().zip(rhs.0) {
+      *v += r;
+    }
+    Self(values)
+  }
+}
+
+impl Mul for SampledSpectrum {
+  type Output = Self;
+  fn mul(self, rhs: Self) -> Self::Output {
+    let mut values = self.0;
+    for (v, r) in values.iter_mut().zip(rhs.0) {
+      *v *= r;
+    }
+    Self(values)
+  }
+}
+
+
+
+
+// index of refraction as a function of wavelength
+#[derive(Clone, Copy)]
+pub enum Dispersion {
+  // n = a + b / lambda^2, b in square micrometers
+  Cauchy(f64, f64),
+  // n^2 = 1 + sum b_i lambda^2 / (lambda^2 - c_i), c in square micrometers
+  Sellmeier([f64; 3], [f64; 3])
+}
+
+impl Dispersion {
+  pub fn bk7() -> Self {
+    Dispersion::Sellmeier(
+      [1.03961212, 0.231792344, 1.01046945],
+      [0.00600069867, 0.0200179144, 103.560653]
+    )
+  }
+
+  // dense flint glass, disperses about twice as much as bk7
+  pub fn sf11() -> Self {
+    Dispersion::Sellmeier(
+      [1.73759695, 0.313747346, 1.89878101],
+      [0.013188707, 0.0623068142, 155.23629]
+    )
+  }
+
+  pub fn ior(&self, wavelength: f64) -> f64 {
+    let l2 = (wavelength / 1000.0).powi(2);
+    match self {
+      Dispersion::Cauchy(a, b) => a + b / l2,
+      Dispersion::Sellmeier(b, c) => {
+        let sum: f64 = b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum();
+        (1.0 + sum).sqrt()
+      }
+    }
+  }
+
+  // the index at the sodium d line, used when rendering in rgb
+  pub fn ior_d(&self) -> f64 {
+    self.ior(589.3)
+  }
+}
+
+
+
+
+// analytic multi-lobe fit of the CIE 1931 color matching functions
+// (Wyman, Sloan and Shirley 2013)
+fn cie_xyz(lambda: f64) -> Color {
+  let g = |mu: f64, sigma1: f64, sigma2: f64| {
+    let t = (lambda - mu) / if lambda < mu {sigma1} else {sigma2};
+    (-0.5 * t * t).exp()
+  };
+  Color::new(
+    1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+    0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+    1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8)
+  )
+}
+
+
+fn xyz_to_linear_srgb(xyz: Color) -> Color {
+  Color::new(
+     3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z,
+    -0.9692660 * xyz.x + 1.8760108 * xyz.y + 0.0415560 * xyz.z,
+     0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z
+  )
+}
+
+
+// linear srgb of a constant unit spectrum, dividing by it keeps white surfaces white
+fn white_point() -> Color {
+  static WHITE: OnceLock<Color> = OnceLock::new();
+  *WHITE.get_or_init(|| {
+    let mut xyz = Color::new(0.0, 0.0, 0.0);
+    let mut lambda = LAMBDA_MIN;
+    while lambda < LAMBDA_MAX {
+      xyz += cie_xyz(lambda + 0.5);
+      lambda += 1.0;
+    }
+    xyz_to_linear_srgb(xyz)
+  })
+}
+
+
+pub fn xyz_to_rgb(xyz: Color) -> Color {
+  let rgb = xyz_to_linear_srgb(xyz);
+  let white = white_point();
+  Color::new(rgb.x / white.x, rgb.y / white.y, rgb.z / white.z)
+}
+
+
+
+
+// Smits' basis spectra, sampled evenly from 380 to 720 nanometers
+const SMITS_WHITE: [f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+
+fn basis(table: &[f64; 10], lambda: f64) -> f64 {
+  let x = ((lambda - 380.0) / (720.0 - 380.0) * 9.0).clamp(0.0, 9.0);
+  let i = (x as usize).min(8);
+  let f = x - i as f64;
+  table[i] * (1.0 - f) + table[i + 1] * f
+}
+
+
+fn rgb_to_spectrum(rgb: Color, lambda: f64) -> f64 {
+  let (r, g, b) = (rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0));
+  let value = if r <= g && r <= b {
+    r * basis(&SMITS_WHITE, lambda) + if g <= b {
+      (g - r) * basis(&SMITS_CYAN, lambda) + (b - g) * basis(&SMITS_BLUE, lambda)
+    } else {
+      (b - r) * basis(&SMITS_CYAN, lambda) + (g - b) * basis(&SMITS_GREEN, lambda)
+    }
+  } else if g <= r && g <= b {
+    g * basis(&SMITS_WHITE, lambda) + if r <= b {
+      (r - g) * basis(&SMITS_MAGENTA, lambda) + (b - r) * basis(&SMITS_BLUE, lambda)
+    } else {
+      (b - g) * basis(&SMITS_MAGENTA, lambda) + (r - b) * basis(&SMITS_RED, lambda)
+    }
+  } else {
+    b * basis(&SMITS_WHITE, lambda) + if r <= g {
+      (r - b) * basis(&SMITS_YELLOW, lambda) + (g - r) * basis(&SMITS_GREEN, lambda)
+    } else {
+      (g - b) * basis(&SMITS_YELLOW, lambda) + (r - g) * basis(&SMITS_RED, lambda)
+    }
+  };
+  value.max(0.0)
+}
+
+
+
+#[cfg(test)]
+mod spectrum_tests {
+  use super::*;
+  use crate::hittable::{HittableList, HittableObject};
+  use crate::integrator::PathTracer;
+  use crate::material::{Coated, Dielectric, Lambertian, MaterialType};
+  use crate::ray::Ray;
+  use crate::sampler::{Sampler, SamplerType};
+  use crate::sphere::Sphere;
+  use crate::vector3::{Point, Vector3};
+
+  #[test]
+  fn test_hero_wavelengths_in_range() {
+    for u in [0.0, 0.3, 0.999] {
+      let w = SampledWavelengths::sample_uniform(u);
+      assert!(w.lambda.iter().all(|l| (LAMBDA_MIN..=LAMBDA_MAX).contains(l)));
+    }
+  }
+
+  #[test]
+  fn test_white_stays_white() {
+    // averaging many wavelength samples of a white reflectance gives rgb white
+    let mut xyz = Color::new(0.0, 0.0, 0.0);
+    let n = 1000;
+    for i in 0..n {
+      let w = SampledWavelengths::sample_uniform((i as f64 + 0.5) / n as f64);
+      xyz += w.to_xyz(SampledSpectrum::from_rgb(Color::new(1.0, 1.0, 1.0), &w));
+    }
+    let rgb = xyz_to_rgb(xyz / n as f64);
+    assert!((rgb.x - 1.0).abs() < 0.01);
+    assert!((rgb.y - 1.0).abs() < 0.01);
+    assert!((rgb.z - 1.0).abs() < 0.01);
+  }
+
+  #[test]
+  fn test_terminate_secondary_keeps_hero_weight() {
+    let mut w = SampledWavelengths::sample_uniform(0.5);
+    let s = SampledSpectrum::constant(1.0);
+    let before = cie_xyz(w.hero()) / w.pdf[0];
+    w.terminate_secondary();
+    let after = w.to_xyz(s);
+    assert!((after.y - before.y).abs() < 0.000001);
+  }
+
+  #[test]
+  fn test_bk7_dispersion() {
+    let bk7 = Dispersion::bk7();
+    assert!((bk7.ior_d() - 1.5168).abs() < 0.0005);
+    assert!(bk7.ior(450.0) > bk7.ior(650.0));
+  }
+
+  fn coated(base: MaterialType) -> MaterialType {
+    MaterialType::Coated(Coated::new(base, 1.5, 0.0))
+  }
+
+  #[test]
+  fn test_coat_over_dispersive_glass_is_dispersive() {
+    let glass = MaterialType::Dielectric(Dielectric::dispersive(Dispersion::bk7()));
+    assert!(coated(glass).dispersive());
+    assert!(!coated(MaterialType::Dielectric(Dielectric::new(1.5))).dispersive());
+    assert!(!coated(MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)))).dispersive());
+  }
+
+  #[test]
+  fn test_path_through_coated_dispersive_glass_keeps_only_the_hero() {
+    let mut world = HittableList::new();
+    let glass = MaterialType::Dielectric(Dielectric::dispersive(Dispersion::bk7()));
+    world.list.push(HittableObject::Sphere(Sphere::new(Point::new(0.0, 0.0, -2.0), 0.5, coated(glass))));
+    let tracer = PathTracer {max_depth: 4, spectral: false};
+    let mut sampler = SamplerType::from_name("independent", 1, 1).unwrap();
+    sampler.start_pixel_sample(0, 0, 0);
+    let mut wavelengths = SampledWavelengths::sample_uniform(0.5);
+    let mut ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0));
+    ray.wavelength = Some(wavelengths.hero());
+    tracer.li_spectral(&ray, &world, &mut wavelengths, &mut sampler, None);
+    assert!(wavelengths.secondary_terminated());
+  }
+}
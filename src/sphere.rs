@@ -1,8 +1,13 @@
+use std::borrow::Cow;
+use std::f64::consts::PI;
+
 use crate::material::{MaterialType};
+use crate::onb::Onb;
 use crate::vector3::{Vector3, Point};
 use crate::hittable::{Hittable, HitRecord};
 use crate::ray::Ray;
 use crate::interval::Interval;
+use crate::rng::Rng;
 
 pub struct Sphere {
   pub center: Point,
@@ -19,11 +24,64 @@ impl Sphere {
       mat
     }
   }
+
+  // p is a point on the unit sphere, u runs around the y axis starting at -x,
+  // v runs from the bottom pole to the top one
+  pub fn get_sphere_uv(p: Point) -> (f64, f64) {
+    let theta = (-p.y).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z).atan2(p.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
+  }
+
+  // derivatives of the point along u and v, at the poles where they degenerate
+  // any frame around the normal is used
+  pub fn get_sphere_tangents(&self, q: Point) -> (Vector3, Vector3) {
+    let sin_theta = (q.x * q.x + q.z * q.z).sqrt();
+    if sin_theta < 1e-8 {
+      let frame = Onb::new(q);
+      return (frame.u, frame.v);
+    }
+    let dpdu = 2.0 * PI * self.radius * Vector3::new(q.z, 0.0, -q.x);
+    let dpdv = PI * self.radius * Vector3::new(-q.y * q.x / sin_theta, sin_theta, -q.y * q.z / sin_theta);
+    (dpdu, dpdv)
+  }
+
+  // a direction from origin inside the cone the sphere covers, uniform over the cone.
+  // None from inside, where the sphere is all around
+  pub fn sample_direction(&self, origin: Point, (u1, u2): (f64, f64)) -> Option<(Vector3, f64)> {
+    let to_center = self.center - origin;
+    let cos_max = self.cone_cos(to_center)?;
+    let cos_theta = 1.0 - u1 * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    let direction = Onb::new(to_center).to_world(Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+    Some((direction, 1.0 / (2.0 * PI * (1.0 - cos_max))))
+  }
+
+  // density of sample_direction for a direction that hits the sphere
+  pub fn pdf(&self, origin: Point) -> f64 {
+    self.cone_cos(self.center - origin).map_or(0.0, |cos_max| 1.0 / (2.0 * PI * (1.0 - cos_max)))
+  }
+
+  pub fn area(&self) -> f64 {
+    4.0 * PI * self.radius * self.radius
+  }
+
+  // a point picked uniformly over the surface and the normal pointing out there
+  pub fn sample_point(&self, u: (f64, f64)) -> (Point, Vector3) {
+    let n = Vector3::sample_unit_vector(u);
+    (self.center + self.radius * n, n)
+  }
+
+  fn cone_cos(&self, to_center: Vector3) -> Option<f64> {
+    let sin2_max = self.radius * self.radius / to_center.length_squared();
+    (sin2_max < 1.0).then(|| (1.0 - sin2_max).sqrt())
+  }
 }
 
 
 impl Hittable for Sphere {
-  fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+  fn hit(&self, ray: &Ray, ray_t: Interval, _rng: &mut Rng) -> Option<HitRecord<'_>> {
     let oc = self.center - ray.origin;
     let a = ray.direction.length_squared();
     let h = Vector3::dot(ray.direction, oc);
@@ -40,19 +98,40 @@ impl Hittable for Sphere {
         return None;
       }
     }
-    // TODO: rewrite whatever this is
     let t = root;
-    let p = ray.at(t);
-    let outward_normal = (p - self.center) / self.radius;
-    let material = self.mat;
-    let mut hr = HitRecord {
-      t,
-      p,
-      normal: Vector3 { x: 0.0, y: 0.0, z: 0.0 },
-      front_face: false,
-      material
-    };
-    hr.set_face_normal(ray, outward_normal);
+    let outward_normal = (ray.at(t) - self.center) / self.radius;
+    let mut hr = HitRecord::new(ray, t, outward_normal, Cow::Borrowed(&self.mat));
+    (hr.u, hr.v) = Self::get_sphere_uv(outward_normal);
+    (hr.dpdu, hr.dpdv) = self.get_sphere_tangents(outward_normal);
     Some(hr) 
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod sphere_tests {
+  use super::*;
+  use crate::material::Lambertian;
+  use crate::vector3::Color;
+
+  #[test]
+  fn test_tangents_match_finite_differences_of_the_hit_point() {
+    let sphere = Sphere::new(Point::new(0.3, -0.2, 1.0), 1.7, MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+    let mut rng = Rng::new(1);
+    // the point at (u, v), found by shooting a ray at it from outside
+    let hit_at = |u: f64, v: f64, rng: &mut Rng| {
+      let (theta, phi) = (PI * v, 2.0 * PI * u);
+      let direction = Vector3::new(-theta.sin() * phi.cos(), -theta.cos(), theta.sin() * phi.sin());
+      let origin = sphere.center + 3.0 * sphere.radius * direction;
+      sphere.hit(&Ray::new(origin, -direction), Interval::new(0.001, f64::INFINITY), rng).unwrap()
+    };
+    let h = 1e-5;
+    for (u, v) in [(0.1, 0.3), (0.45, 0.5), (0.8, 0.9), (0.6, 0.15)] {
+      let rec = hit_at(u, v, &mut rng);
+      assert!((rec.u - u).abs() < 1e-9 && (rec.v - v).abs() < 1e-9, "{} {}", rec.u, rec.v);
+      let dpdu = (hit_at(u + h, v, &mut rng).p - hit_at(u - h, v, &mut rng).p) / (2.0 * h);
+      let dpdv = (hit_at(u, v + h, &mut rng).p - hit_at(u, v - h, &mut rng).p) / (2.0 * h);
+      assert!((rec.dpdu - dpdu).length() < 1e-4 * dpdu.length(), "{:?} {:?}", rec.dpdu, dpdu);
+      assert!((rec.dpdv - dpdv).length() < 1e-4 * dpdv.length(), "{:?} {:?}", rec.dpdv, dpdv);
+    }
+  }
+}
@@ -0,0 +1,92 @@
+use std::borrow::Cow;
+
+use crate::constant_medium::boundary_crossings;
+use crate::material::{Isotropic, MaterialType, Subsurface};
+use crate::vector3::Vector3;
+use crate::hittable::{Hittable, HitRecord, HittableObject};
+use crate::ray::Ray;
+use crate::interval::Interval;
+use crate::rng::Rng;
+
+// a closed object filled with a dense scattering medium, rendered as a random walk:
+// rays refract in through the surface, scatter isotropically until they reach the
+// boundary again and then refract out (or back in)
+pub struct SubsurfaceObject {
+  pub boundary: Box<HittableObject>,
+  pub subsurface: Subsurface,
+  surface: MaterialType
+}
+
+
+impl SubsurfaceObject {
+  pub fn new(boundary: HittableObject, subsurface: Subsurface) -> Self {
+    Self {
+      boundary: Box::new(boundary),
+      subsurface,
+      surface: MaterialType::Subsurface(subsurface)
+    }
+  }
+}
+
+
+impl Hittable for SubsurfaceObject {
+  fn hit(&self, ray: &Ray, ray_t: Interval, rng: &mut Rng) -> Option<HitRecord<'_>> {
+    let (entry, exit) = boundary_crossings(&self.boundary, ray, rng)?;
+    let inside = entry.t <= ray_t.min && exit.t > ray_t.min;
+    if !inside {
+      let mut rec = self.boundary.hit(ray, ray_t, rng)?;
+      rec.material = Cow::Borrowed(&self.surface);
+      return Some(rec);
+    }
+    // free flight from the ray origin, the same exponential sampling as the other media
+    let ray_length = ray.direction.length();
+    let distance = self.subsurface.sample_distance(rng.uniform(), rng.uniform());
+    let t = (distance / ray_length).max(ray_t.min);
+    if t < exit.t {
+      if t >= ray_t.max {
+        return None;
+      }
+      let phase_function = MaterialType::Isotropic(Isotropic::new(self.subsurface.collision_weight(t * ray_length)));
+      return Some(HitRecord::new(ray, t, Vector3::new(1.0, 0.0, 0.0), Cow::Owned(phase_function)));
+    }
+    if exit.t >= ray_t.max {
+      return None;
+    }
+    let mut rec = exit;
+    rec.material = Cow::Borrowed(&self.surface);
+    Some(rec)
+  }
+}
+
+
+
+#[cfg(test)]
+mod subsurface_tests {
+  use crate::vector3::Color;
+  use super::*;
+
+  #[test]
+  fn test_gray_medium_weights() {
+    // with the same mean free path in every channel the spectral MIS weights
+    // reduce to the albedo for collisions and to one for exits
+    let albedo = Color::new(0.9, 0.5, 0.1);
+    let s = Subsurface::new(1.4, 0.0, albedo, Color::new(0.2, 0.2, 0.2));
+    let c = s.collision_weight(0.3);
+    assert!((c.x - 0.9).abs() < 0.000001);
+    assert!((c.z - 0.1).abs() < 0.000001);
+    let e = s.exit_weight(0.3);
+    assert!((e.y - 1.0).abs() < 0.000001);
+  }
+
+  #[test]
+  fn test_chromatic_exit_weight_averages_to_one() {
+    // averaged over the three channel choices the weights are unbiased, which for
+    // a single fixed distance means the mean of the per channel weights is 1 / pdf * pdf
+    let s = Subsurface::new(1.4, 0.0, Color::new(1.0, 1.0, 1.0), Color::new(1.0, 0.3, 0.1));
+    let e = s.exit_weight(0.2);
+    let tr = Color::new((-0.2f64).exp(), (-0.2f64 / 0.3).exp(), (-2.0f64).exp());
+    let avg = (tr.x + tr.y + tr.z) / 3.0;
+    assert!((e.x - tr.x / avg).abs() < 0.000001);
+    assert!((e.z - tr.z / avg).abs() < 0.000001);
+  }
+}
@@ -0,0 +1,85 @@
+use crate::camera::Projection;
+use crate::hittable::{HittableList, HittableObject};
+use crate::integrator::{Integrator, IntegratorType};
+use crate::material::{DiffuseLight, Lambertian, MaterialType};
+use crate::quad::Quad;
+use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerType};
+use crate::sphere::Sphere;
+use crate::vector3::{Color, Point, Vector3};
+
+// scenes and estimates the integrator tests compare each other on
+
+// a gray floor and wall under the sky, a lamp above the floor facing down and a ball
+pub fn lamp_room(ball: Sphere) -> HittableList {
+  let mut world = HittableList::new();
+  let gray = MaterialType::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+  let lamp = MaterialType::DiffuseLight(DiffuseLight::new(Color::new(10.0, 10.0, 10.0)));
+  world.list.push(HittableObject::Quad(Quad::new(Point::new(-50.0, 0.0, 50.0), Vector3::new(100.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -100.0), gray.clone())));
+  world.list.push(HittableObject::Quad(Quad::new(Point::new(-3.0, 0.0, -0.8), Vector3::new(6.0, 0.0, 0.0), Vector3::new(0.0, 3.0, 0.0), gray)));
+  world.list.push(HittableObject::Quad(Quad::new(Point::new(-0.3, 1.2, -0.3), Vector3::new(0.6, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.6), lamp)));
+  world.list.push(HittableObject::Sphere(ball));
+  world
+}
+
+// a pinhole 4x4 pixels wide looking down at the floor under the lamp
+pub fn pinhole() -> Projection {
+  let (center, lookat, vup) = (Point::new(0.0, 1.0, 2.0), Point::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+  let w = Vector3::unit_vector(&(center - lookat));
+  let u = Vector3::unit_vector(&Vector3::cross(vup, w));
+  let v = Vector3::cross(w, u);
+  let (pixel_delta_u, pixel_delta_v) = (u * 0.25, -v * 0.25);
+  let zero = Vector3::new(0.0, 0.0, 0.0);
+  Projection {
+    center,
+    forward: -w,
+    focus_dist: 1.0,
+    defocus_disk_u: zero,
+    defocus_disk_v: zero,
+    pixel00_loc: center - w - 1.5 * (pixel_delta_u + pixel_delta_v),
+    pixel_delta_u,
+    pixel_delta_v,
+    width: 4,
+    height: 4
+  }
+}
+
+// the mean light through every pixel, row by row, splats left out
+pub fn pixel_means(integrator: &IntegratorType, world: &HittableList, camera: &Projection, n: u32) -> Vec<f64> {
+  let mut sampler = SamplerType::from_name("independent", n, 5).unwrap();
+  let mut means = Vec::new();
+  for row in 0..camera.height {
+    for col in 0..camera.width {
+      let mut sum = 0.0;
+      for i in 0..n {
+        sampler.start_pixel_sample(col as u32, row as u32, i);
+        let (dx, dy) = sampler.get_2d();
+        let target = camera.pixel00_loc + (col as f64 + dx - 0.5) * camera.pixel_delta_u + (row as f64 + dy - 0.5) * camera.pixel_delta_v;
+        sum += integrator.li(&Ray::new(camera.center, target - camera.center), world, &mut sampler, None).total().y;
+      }
+      means.push(sum / n as f64);
+    }
+  }
+  means
+}
+
+// the mean and variance of the light from a square of the floor around the origin seen
+// from eye, over passes that each take one sample of every ray
+pub fn floor_patch(integrator: &mut IntegratorType, world: &HittableList, eye: Point, size: f64, passes: u32, rays: u32) -> (f64, f64) {
+  let sampler = SamplerType::from_name("independent", passes, 3).unwrap();
+  let (mut sum, mut squares) = (0.0, 0.0);
+  for pass in 0..passes {
+    integrator.start_pass(world, pass, 1, &sampler);
+    let mut sampler = sampler.clone();
+    for i in 0..rays {
+      sampler.start_pixel_sample(i, 0, pass);
+      let (x, z) = sampler.get_2d();
+      let target = Point::new(size * (x - 0.5), 0.0, size * (z - 0.5));
+      let y = integrator.li(&Ray::new(eye, target - eye), world, &mut sampler, None).total().y;
+      sum += y;
+      squares += y * y;
+    }
+  }
+  let n = (passes * rays) as f64;
+  (sum / n, squares / n - (sum / n).powi(2))
+}
@@ -0,0 +1,200 @@
+use std::{path::Path, sync::Arc};
+
+use crate::perlin::Perlin;
+use crate::rng::Rng;
+use crate::vector3::{Color, Point};
+
+pub trait Texture {
+  fn value(&self, u: f64, v: f64, p: Point) -> Color;
+}
+
+
+#[derive(Clone)]
+pub enum TextureType {
+  Solid(Color),
+  Checker(Checker),
+  Image(Arc<ImageTexture>),
+  Noise(Arc<NoiseTexture>),
+  Scaled(Arc<TextureType>, Color)
+}
+
+impl Texture for TextureType {
+  fn value(&self, u: f64, v: f64, p: Point) -> Color {
+    match self {
+      TextureType::Solid(c)          => *c,
+      TextureType::Checker(c)        => c.value(u, v, p),
+      TextureType::Image(i)          => i.value(u, v, p),
+      TextureType::Noise(n)          => n.value(u, v, p),
+      TextureType::Scaled(t, factor) => *factor * t.value(u, v, p)
+    }
+  }
+}
+
+
+
+
+// one channel of a texture, used for scalar material parameters
+#[derive(Clone)]
+pub struct ScalarTexture {
+  texture: TextureType,
+  channel: usize
+}
+
+impl ScalarTexture {
+  pub fn constant(value: f64) -> Self {
+    Self::channel(TextureType::Solid(Color::new(value, value, value)), 0)
+  }
+
+  // 0 is red, 1 is green, 2 is blue
+  pub fn channel(texture: TextureType, channel: usize) -> Self {
+    Self {texture, channel: channel.min(2)}
+  }
+
+  pub fn value(&self, u: f64, v: f64, p: Point) -> f64 {
+    let c = self.texture.value(u, v, p);
+    match self.channel {
+      0 => c.x,
+      1 => c.y,
+      _ => c.z
+    }
+  }
+}
+
+
+
+
+// 3d checker pattern, independent of the surface parametrization
+#[derive(Clone, Copy)]
+pub struct Checker {
+  inv_scale: f64,
+  even: Color,
+  odd: Color
+}
+
+impl Checker {
+  pub fn new(scale: f64, even: Color, odd: Color) -> Self {
+    Self {inv_scale: 1.0 / scale, even, odd}
+  }
+}
+
+impl Texture for Checker {
+  fn value(&self, _u: f64, _v: f64, p: Point) -> Color {
+    let x = (self.inv_scale * p.x).floor() as i64;
+    let y = (self.inv_scale * p.y).floor() as i64;
+    let z = (self.inv_scale * p.z).floor() as i64;
+    if (x + y + z) % 2 == 0 {self.even} else {self.odd}
+  }
+}
+
+
+
+
+pub struct ImageTexture {
+  width: usize,
+  height: usize,
+  data: Vec<Color>
+}
+
+impl ImageTexture {
+  // color textures are stored in srgb and get linearized, data textures
+  // such as roughness or normal maps should be loaded with srgb = false
+  pub fn load<P: AsRef<Path>>(path: P, srgb: bool) -> Result<Self, image::ImageError> {
+    let img = image::open(path)?.to_rgb32f();
+    let (width, height) = img.dimensions();
+    let decode = |c: f32| -> f64 {
+      let c = c as f64;
+      if !srgb {
+        c
+      } else if c <= 0.04045 {
+        c / 12.92
+      } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+      }
+    };
+    let data = img.pixels()
+                  .map(|px| Color::new(decode(px[0]), decode(px[1]), decode(px[2])))
+                  .collect();
+    Ok(Self {
+      width: width as usize,
+      height: height as usize,
+      data
+    })
+  }
+
+  // the alpha channel of an image as a grayscale texture, for cutout masks
+  pub fn load_alpha<P: AsRef<Path>>(path: P) -> Result<Self, image::ImageError> {
+    let img = image::open(path)?.to_rgba32f();
+    let (width, height) = img.dimensions();
+    let data = img.pixels()
+                  .map(|px| Color::new(px[3] as f64, px[3] as f64, px[3] as f64))
+                  .collect();
+    Ok(Self {
+      width: width as usize,
+      height: height as usize,
+      data
+    })
+  }
+}
+
+impl Texture for ImageTexture {
+  fn value(&self, u: f64, v: f64, _p: Point) -> Color {
+    if self.width == 0 || self.height == 0 {
+      return Color::new(0.0, 1.0, 1.0);
+    }
+    // wrap around so tiled uvs work, v = 0 is the bottom row of the image
+    let u = u - u.floor();
+    let v = 1.0 - (v - v.floor());
+    let i = ((u * self.width as f64) as usize).min(self.width - 1);
+    let j = ((v * self.height as f64) as usize).min(self.height - 1);
+    self.data[j * self.width + i]
+  }
+}
+
+
+
+
+// marble-like veins from perlin turbulence
+pub struct NoiseTexture {
+  noise: Perlin,
+  scale: f64,
+  color: Color
+}
+
+impl NoiseTexture {
+  pub fn new(scale: f64, color: Color, rng: &mut Rng) -> Self {
+    Self {noise: Perlin::new(rng), scale, color}
+  }
+}
+
+impl Texture for NoiseTexture {
+  fn value(&self, _u: f64, _v: f64, p: Point) -> Color {
+    self.color * 0.5 * (1.0 + (self.scale * p.z + 10.0 * self.noise.turb(p, 7)).sin())
+  }
+}
+
+
+#[cfg(test)]
+mod texture_tests {
+  use super::*;
+
+  #[test]
+  fn test_checker_alternates_across_cells() {
+    let (even, odd) = (Color::new(1.0, 0.0, 0.0), Color::new(0.0, 0.0, 1.0));
+    let checker = TextureType::Checker(Checker::new(0.5, even, odd));
+    let at = |x: f64, y: f64, z: f64| checker.value(0.0, 0.0, Point::new(x, y, z));
+    assert_eq!(at(0.25, 0.25, 0.25), even);
+    assert_eq!(at(0.75, 0.25, 0.25), odd);
+    assert_eq!(at(0.75, 0.75, 0.25), even);
+    // cells below zero keep alternating
+    assert_eq!(at(-0.25, 0.25, 0.25), odd);
+    assert_eq!(at(-0.75, 0.25, 0.25), even);
+  }
+
+  #[test]
+  fn test_scaled_multiplies_each_channel() {
+    let checker = TextureType::Checker(Checker::new(1.0, Color::new(0.5, 1.0, 0.25), Color::new(0.0, 0.0, 0.0)));
+    let scaled = TextureType::Scaled(Arc::new(checker), Color::new(2.0, 0.5, 4.0));
+    assert_eq!(scaled.value(0.3, 0.7, Point::new(0.5, 0.5, 0.5)), Color::new(1.0, 0.5, 1.0));
+    assert_eq!(scaled.value(0.3, 0.7, Point::new(1.5, 0.5, 0.5)), Color::new(0.0, 0.0, 0.0));
+  }
+}
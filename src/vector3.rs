@@ -3,6 +3,8 @@ use std::{
   ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub}
 };
 
+use crate::rng::Rng;
+
 
 pub type Color = Vector3;
 pub type Point = Vector3;
@@ -36,6 +38,11 @@ impl Vector3 {
     self.z * self.z
   }
 
+  // of a linear rgb color, with the rec. 709 weights
+  pub fn luminance(&self) -> f64 {
+    0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+  }
+
   pub fn dot(left: Self, right: Self) -> f64 {
     left.x * right.x +
     left.y * right.y +
@@ -54,21 +61,17 @@ impl Vector3 {
     *v / v.length()
   }
 
-  pub fn random() -> Self {
-    Self::new(rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>())
+  pub fn random(rng: &mut Rng) -> Self {
+    Self::new(rng.uniform(), rng.uniform(), rng.uniform())
   }
 
-  pub fn random_in(min: f64, max: f64) -> Self {
-    Self::new(
-      rand::random_range(min..max),
-      rand::random_range(min..max),
-      rand::random_range(min..max)
-    )
+  pub fn random_in(min: f64, max: f64, rng: &mut Rng) -> Self {
+    Self::new(rng.range(min, max), rng.range(min, max), rng.range(min, max))
   }
   
-  pub fn random_unit_vector() -> Self {
+  pub fn random_unit_vector(rng: &mut Rng) -> Self {
     loop {
-      let p = Vector3::random_in(-1.0, 1.0);
+      let p = Vector3::random_in(-1.0, 1.0, rng);
       let lensq = p.length_squared();
       if lensq <= 1.0 && lensq > 1e-160 {
         return p / lensq.sqrt();
@@ -76,8 +79,31 @@ impl Vector3 {
     }
   }
 
-  pub fn random_on_hemishpere(normal: Vector3) -> Self {
-    let on_unit_sphere = Vector3::random_unit_vector();
+  // maps a point of the unit square uniformly onto the unit sphere
+  pub fn sample_unit_vector((u1, u2): (f64, f64)) -> Self {
+    let z = 1.0 - 2.0 * u1;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * u2;
+    Self::new(r * phi.cos(), r * phi.sin(), z)
+  }
+
+  // concentric mapping of the unit square onto the unit disk in the xy plane
+  pub fn sample_in_unit_disk((u1, u2): (f64, f64)) -> Self {
+    let (a, b) = (2.0 * u1 - 1.0, 2.0 * u2 - 1.0);
+    if a == 0.0 && b == 0.0 {
+      return Self::new(0.0, 0.0, 0.0);
+    }
+    let quarter = std::f64::consts::FRAC_PI_4;
+    let (r, theta) = if a.abs() > b.abs() {
+      (a, quarter * (b / a))
+    } else {
+      (b, 2.0 * quarter - quarter * (a / b))
+    };
+    Self::new(r * theta.cos(), r * theta.sin(), 0.0)
+  }
+
+  pub fn random_on_hemishpere(normal: Vector3, rng: &mut Rng) -> Self {
+    let on_unit_sphere = Vector3::random_unit_vector(rng);
     if Vector3::dot(on_unit_sphere, normal) > 0.0 {
       on_unit_sphere
     } else {
@@ -85,6 +111,14 @@ impl Vector3 {
     }
   }
 
+  pub fn min(a: Self, b: Self) -> Self {
+    Self::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+  }
+
+  pub fn max(a: Self, b: Self) -> Self {
+    Self::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+  }
+
   pub fn near_zero(&self) -> bool {
     let s = 1e-8;
     self.x.abs() < s && self.y.abs() < s && self.z.abs() < s 
@@ -107,9 +141,9 @@ impl Vector3 {
     r_out_perp + r_out_parallel
   }
 
-  pub fn random_in_unit_disk() -> Self {
+  pub fn random_in_unit_disk(rng: &mut Rng) -> Self {
     loop {
-      let p = Vector3::new(rand::random_range(-1..1), rand::random_range(-1..1), 0);
+      let p = Vector3::new(rng.range(-1.0, 1.0), rng.range(-1.0, 1.0), 0.0);
       if p.length_squared() < 1.0 {
         return p;
       }